@@ -0,0 +1,210 @@
+use anyhow::{Context, Result};
+use semver::{Version, VersionReq};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{debug, info, warn};
+use crate::config::Config;
+use crate::models::{Advisory, AdvisoryCache, AdvisoryFeed, AdvisoryMatch, AdvisoryPolicy, AppConfig, InstalledPackage};
+use crate::registry::RegistryClient;
+
+// Feeds are downloaded again after this long, installs in between are checked against the cached copy
+const FEED_CACHE_TTL_SECS: u64 = 60 * 60;
+
+/// Raised when an install is stopped because the version has an advisory the policy blocks.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AdvisoryBlockedError {
+    pub kind: String, // "advisory_blocked"
+    pub message: String,
+    pub package_name: String,
+    pub version: String,
+    pub advisories: Vec<Advisory>,
+}
+
+impl fmt::Display for AdvisoryBlockedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for AdvisoryBlockedError {}
+
+/// Known-bad package versions, published as feeds next to registries and cached locally
+/// so installs can be checked without going online.
+pub struct AdvisoryStore;
+
+impl AdvisoryStore {
+    pub fn get_cache_path() -> Result<PathBuf> {
+        let config_path = Config::get_config_path()?;
+        Ok(config_path.with_file_name("advisories.json"))
+    }
+
+    /// Loads the cached advisories, keyed by the registry whose feed they came from.
+    pub fn load() -> Result<AdvisoryCache> {
+        let cache_path = Self::get_cache_path()?;
+
+        if !cache_path.exists() {
+            return Ok(AdvisoryCache::default());
+        }
+
+        let content = fs::read_to_string(&cache_path)
+            .context("Failed to read advisory cache")?;
+
+        serde_json::from_str(&content)
+            .context("Failed to parse advisory cache")
+    }
+
+    pub fn save(cache: &AdvisoryCache) -> Result<()> {
+        let cache_path = Self::get_cache_path()?;
+
+        let content = serde_json::to_string_pretty(cache)
+            .context("Failed to serialize advisory cache")?;
+
+        fs::write(&cache_path, content)
+            .context("Failed to write advisory cache")
+    }
+
+    /// Downloads the advisory feeds of all enabled registries into the cache, skipping feeds fetched recently.
+    /// Registries that can't be reached keep their previously cached advisories.
+    pub async fn refresh(config: &AppConfig) -> Result<()> {
+        let mut cache = Self::load().unwrap_or_default();
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+
+        for registry in config.registries.iter().filter(|r| r.enabled) {
+            let fetched_at = cache.fetched_at.get(&registry.id).copied().unwrap_or(0);
+            if now.saturating_sub(fetched_at) < FEED_CACHE_TTL_SECS {
+                debug!("Advisories for registry {} are still fresh", registry.name);
+                continue;
+            }
+
+            match Self::fetch_registry_advisories(&registry.url, &registry.id).await {
+                Ok(Some(advisories)) => {
+                    info!("Loaded {} advisories for registry {}", advisories.len(), registry.name);
+                    cache.advisories.insert(registry.id.clone(), advisories);
+                    cache.fetched_at.insert(registry.id.clone(), now);
+                }
+                Ok(None) => {
+                    cache.advisories.remove(&registry.id);
+                    cache.fetched_at.insert(registry.id.clone(), now);
+                }
+                Err(e) => warn!("Could not refresh advisories for registry {}: {}", registry.name, e),
+            }
+        }
+
+        // Registries that were removed no longer contribute advisories
+        let is_configured = |registry_id: &String| config.registries.iter().any(|r| &r.id == registry_id);
+        cache.advisories.retain(|registry_id, _| is_configured(registry_id));
+        cache.fetched_at.retain(|registry_id, _| is_configured(registry_id));
+
+        Self::save(&cache)
+    }
+
+    async fn fetch_registry_advisories(registry_url: &str, registry_id: &str) -> Result<Option<Vec<Advisory>>> {
+        let registry = RegistryClient::fetch_registry(registry_url, Some(registry_id)).await?;
+
+        let advisories_url = match registry.advisories_url {
+            Some(url) => url,
+            None => return Ok(None),
+        };
+
+        // Feeds may be given relative to the registry, so they can be hosted alongside it
+        let feed_url = reqwest::Url::parse(registry_url)
+            .and_then(|base| base.join(&advisories_url))
+            .map(|url| url.to_string())
+            .unwrap_or(advisories_url);
+
+        let feed = Self::fetch_feed(&feed_url).await?;
+
+        Ok(Some(
+            feed.advisories.into_iter()
+                .map(|advisory| Advisory { registry_id: Some(registry_id.to_string()), ..advisory })
+                .collect(),
+        ))
+    }
+
+    pub async fn fetch_feed(feed_url: &str) -> Result<AdvisoryFeed> {
+        let response = reqwest::get(feed_url)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to fetch advisory feed: {}", e))?;
+
+        response
+            .json()
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to parse advisory feed JSON: {}", e))
+    }
+
+    /// Returns the cached advisories that affect a specific version of a package.
+    /// Only the feed of the registry the package is installed from applies, another registry's package
+    /// of the same name is a different package. Packages from no registry have no feed to match against.
+    pub fn matching(package_name: &str, version: &str, registry_id: Option<&str>) -> Vec<Advisory> {
+        let (version, registry_id) = match (Self::parse_version(version), registry_id) {
+            (Some(version), Some(registry_id)) => (version, registry_id),
+            _ => return Vec::new(),
+        };
+
+        Self::load()
+            .unwrap_or_default()
+            .advisories
+            .remove(registry_id)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|advisory| advisory.package == package_name)
+            .filter(|advisory| match VersionReq::parse(&advisory.affected) {
+                Ok(requirement) => requirement.matches(&version),
+                Err(e) => {
+                    warn!("Advisory {} has an invalid version range '{}': {}", advisory.id, advisory.affected, e);
+                    false
+                }
+            })
+            .collect()
+    }
+
+    /// Stops installs of versions with advisories of a blocked severity.
+    /// Returns the remaining advisories, which the install should warn about.
+    pub fn check(package_name: &str, version: &str, registry_id: Option<&str>, policy: &AdvisoryPolicy) -> Result<Vec<Advisory>> {
+        let advisories = Self::matching(package_name, version, registry_id);
+
+        let blocked: Vec<Advisory> = advisories.iter()
+            .filter(|advisory| policy.blocked_severities.iter().any(|s| s.eq_ignore_ascii_case(&advisory.severity)))
+            .cloned()
+            .collect();
+
+        if !blocked.is_empty() {
+            let summary = blocked.iter()
+                .map(|advisory| format!("{} ({}): {}", advisory.id, advisory.severity, advisory.message))
+                .collect::<Vec<_>>()
+                .join("; ");
+
+            return Err(AdvisoryBlockedError {
+                kind: "advisory_blocked".to_string(),
+                message: format!("{} {} has security advisories: {}", package_name, version, summary),
+                package_name: package_name.to_string(),
+                version: version.to_string(),
+                advisories: blocked,
+            }.into());
+        }
+
+        Ok(advisories)
+    }
+
+    /// Matches installed packages against the cached advisories.
+    pub fn audit(packages: &[InstalledPackage]) -> Vec<AdvisoryMatch> {
+        packages.iter()
+            .flat_map(|package| {
+                Self::matching(&package.name, &package.version, package.installed_from_registry.as_deref())
+                    .into_iter()
+                    .map(|advisory| AdvisoryMatch {
+                        package_name: package.name.clone(),
+                        installed_version: package.version.clone(),
+                        advisory,
+                    })
+            })
+            .collect()
+    }
+
+    fn parse_version(version: &str) -> Option<Version> {
+        Version::parse(version.trim().trim_start_matches('v')).ok()
+    }
+}
@@ -1,12 +1,94 @@
 use tauri::command;
 use uuid::Uuid;
 use anyhow::Result;
+use tracing::{instrument, warn};
+use crate::advisories::AdvisoryStore;
 use crate::models::*;
 use crate::config::Config;
+use crate::errors::{CommandError, CupmError};
+use crate::git_operations::GitOperations;
+use crate::history::HistoryStore;
+use crate::logging::Logging;
 use crate::package_manager::PackageManager;
+use crate::patches::PatchManager;
+use crate::provenance::ProvenanceStore;
+use crate::registry::RegistryClient;
+use crate::registry_trust::RegistryTrust;
+use crate::safety_scan::SafetyScanner;
+use crate::unity_package::UnityPackageArchive;
+
+/// Updates the advisory cache before an install so known-bad versions are caught.
+/// Being offline isn't fatal, installs are then checked against the advisories cached earlier.
+async fn refresh_advisories() {
+    match Config::load() {
+        Ok(config) => {
+            if let Err(e) = AdvisoryStore::refresh(&config).await {
+                warn!("Could not refresh advisories: {}", e);
+            }
+        }
+        Err(e) => warn!("Could not load config to refresh advisories: {}", e),
+    }
+}
+
+/// Adds the advisories the policy only warns about to a successful install,
+/// from the feed of the registry the package was installed from.
+fn with_advisory_warnings(project_path: &str, mut result: InstallResult) -> InstallResult {
+    let registry_id = ProvenanceStore::get(project_path, &result.package_name)
+        .ok()
+        .flatten()
+        .and_then(|provenance| provenance.registry_id);
+
+    result.advisories = AdvisoryStore::matching(&result.package_name, &result.version, registry_id.as_deref());
+    result
+}
+
+/// Starts a history entry for an install from a folder or tarball, snapshotting the package it will replace.
+fn begin_source_install(project_path: &str, operation: &str, source_path: &str) -> HistoryEntry {
+    match PackageManager::peek_package_name(std::path::Path::new(source_path)) {
+        Some(package_name) => HistoryStore::begin_with_snapshot(project_path, operation, &package_name),
+        // An unreadable source fails the install, there is nothing to undo
+        None => HistoryStore::begin(project_path, operation, None),
+    }
+}
+
+/// Records an operation that installs a package in the project history, whatever its outcome.
+fn record_install(entry: HistoryEntry, result: &Result<InstallResult, CommandError>) {
+    match result {
+        Ok(result) => HistoryStore::finish(entry, Some(&result.package_name), Some(&result.version), None),
+        Err(e) => HistoryStore::finish(entry, None, None, Some(e)),
+    }
+}
+
+/// Records an operation that doesn't install anything, such as a removal, in the project history.
+fn record_outcome<T>(entry: HistoryEntry, result: &Result<T, CommandError>) {
+    HistoryStore::finish(entry, None, None, result.as_ref().err());
+}
+
+/// Reverts operations newest first, each recorded as an "undo" entry, and stops at the first that fails.
+fn revert_operations(project_path: &str, operations: Vec<HistoryEntry>) -> Result<Vec<HistoryEntry>, CommandError> {
+    let mut reverted = Vec::new();
+
+    for operation in operations {
+        let mut entry = HistoryStore::begin(project_path, "undo", operation.package_name.as_deref());
+        entry.reverts = Some(operation.id.clone());
+
+        let result = PackageManager::revert_operation(project_path, &operation)
+            .map_err(CommandError::from);
+
+        match &result {
+            Ok(version) => HistoryStore::finish(entry, None, version.as_deref(), None),
+            Err(e) => HistoryStore::finish(entry, None, None, Some(e)),
+        }
+
+        result?;
+        reverted.push(operation);
+    }
+
+    Ok(reverted)
+}
 
 #[command]
-pub async fn get_system_theme() -> Result<String, String> {
+pub async fn get_system_theme() -> Result<String, CommandError> {
     // Try to detect system theme preference
     // This is a simplified version - in a full implementation,
     // you'd check Windows registry, macOS defaults, or Linux desktop environment
@@ -37,17 +119,19 @@ pub async fn get_system_theme() -> Result<String, String> {
 }
 
 #[command]
-pub async fn get_config() -> Result<AppConfig, String> {
-    Config::load().map_err(|e| e.to_string())
+pub async fn get_config() -> Result<AppConfig, CommandError> {
+    Config::load().map_err(CommandError::from)
 }
 
 #[command]
-pub async fn save_config(config: AppConfig) -> Result<(), String> {
-    Config::save(&config).map_err(|e| e.to_string())
+#[instrument(skip_all)]
+pub async fn save_config(config: AppConfig) -> Result<(), CommandError> {
+    Config::save(&config).map_err(CommandError::from)
 }
 
 #[command]
-pub async fn add_registry(name: String, url: String) -> Result<Registry, String> {
+#[instrument(skip_all)]
+pub async fn add_registry(name: String, url: String) -> Result<Registry, CommandError> {
     let registry = Registry {
         id: Uuid::new_v4().to_string(),
         name,
@@ -55,36 +139,51 @@ pub async fn add_registry(name: String, url: String) -> Result<Registry, String>
         enabled: true,
     };
 
-    let mut config = Config::load().map_err(|e| e.to_string())?;
+    let mut config = Config::load().map_err(CommandError::from)?;
     config.registries.push(registry.clone());
-    Config::save(&config).map_err(|e| e.to_string())?;
+    Config::save(&config).map_err(CommandError::from)?;
+
+    // Fetching with the new id pins what the registry serves right now
+    if let Err(e) = RegistryClient::fetch_registry(&registry.url, Some(&registry.id)).await {
+        warn!("Could not pin registry {}, it will be pinned on the next fetch: {}", registry.url, e);
+    }
 
     Ok(registry)
 }
 
 #[command]
-pub async fn remove_registry(registry_id: String) -> Result<(), String> {
-    let mut config = Config::load().map_err(|e| e.to_string())?;
+#[instrument(skip_all, fields(registry_id = %registry_id))]
+pub async fn remove_registry(registry_id: String) -> Result<(), CommandError> {
+    let mut config = Config::load().map_err(CommandError::from)?;
     config.registries.retain(|r| r.id != registry_id);
-    Config::save(&config).map_err(|e| e.to_string())?;
+    Config::save(&config).map_err(CommandError::from)?;
+    RegistryTrust::remove(&registry_id).map_err(CommandError::from)?;
     Ok(())
 }
 
 #[command]
-pub async fn add_project_path(path: String) -> Result<(), String> {
-    let mut config = Config::load().map_err(|e| e.to_string())?;
+#[instrument(skip_all, fields(registry_id = %registry_id))]
+pub async fn confirm_registry_changes(registry_id: String) -> Result<(), CommandError> {
+    RegistryTrust::confirm(&registry_id).map_err(CommandError::from)
+}
+
+#[command]
+#[instrument(skip_all, fields(project = %path))]
+pub async fn add_project_path(path: String) -> Result<(), CommandError> {
+    let mut config = Config::load().map_err(CommandError::from)?;
 
     if !config.project_paths.contains(&path) {
         config.project_paths.push(path);
-        Config::save(&config).map_err(|e| e.to_string())?;
+        Config::save(&config).map_err(CommandError::from)?;
     }
 
     Ok(())
 }
 
 #[command]
-pub async fn remove_project_path(path: String) -> Result<(), String> {
-    let mut config = Config::load().map_err(|e| e.to_string())?;
+#[instrument(skip_all, fields(project = %path))]
+pub async fn remove_project_path(path: String) -> Result<(), CommandError> {
+    let mut config = Config::load().map_err(CommandError::from)?;
     config.project_paths.retain(|p| p != &path);
 
     // Clear selected project if it was removed
@@ -92,116 +191,599 @@ pub async fn remove_project_path(path: String) -> Result<(), String> {
         config.selected_project_path = None;
     }
 
-    Config::save(&config).map_err(|e| e.to_string())?;
+    Config::save(&config).map_err(CommandError::from)?;
     Ok(())
 }
 
 #[command]
-pub async fn get_packages_from_registry(registry_url: String) -> Result<PackageRegistry, String> {
-    let client = reqwest::Client::new();
-    let response = client
-        .get(&registry_url)
-        .send()
+#[instrument(skip_all)]
+pub async fn get_packages_from_registry(registry_url: String) -> Result<PackageRegistry, CommandError> {
+    // Tag packages with the id of the configured registry they were fetched from
+    let config = Config::load().map_err(CommandError::from)?;
+    let registry_id = config.registries.iter()
+        .find(|r| r.url == registry_url)
+        .map(|r| r.id.clone());
+
+    RegistryClient::fetch_registry(&registry_url, registry_id.as_deref())
         .await
-        .map_err(|e| format!("Failed to fetch registry: {}", e))?;
+        .map_err(CommandError::from)
+}
 
-    let mut registry: PackageRegistry = response
-        .json()
-        .await
-        .map_err(|e| format!("Failed to parse registry JSON: {}", e))?;
-
-    // Expand packages that have multiple versions into separate package entries
-    let mut expanded_packages = Vec::new();
-
-    for package in registry.packages {
-        if let Some(versions) = &package.versions {
-            // New format: package has multiple versions
-            for version_info in versions {
-                let expanded_package = Package {
-                    name: package.name.clone(),
-                    display_name: package.display_name.clone(),
-                    description: package.description.clone(),
-                    git_url: package.git_url.clone(),
-                    author: package.author.clone(),
-                    keywords: package.keywords.clone(),
-                    category: package.category.clone(),
-                    license: package.license.clone(),
-                    versions: None, // Don't include versions in expanded format
-                    // Use version-specific data
-                    version: Some(version_info.version.clone()),
-                    git_tag: version_info.git_tag.clone(),
-                    git_branch: version_info.git_branch.clone(),
-                    dependencies: version_info.dependencies.clone(),
-                    unity_version: version_info.unity_version.clone(),
-                    is_prerelease: version_info.is_prerelease,
-                };
-                expanded_packages.push(expanded_package);
+#[command]
+#[instrument(skip_all)]
+pub async fn get_remote_versions(
+    git_url: String,
+    registry_tags: Option<Vec<String>>,
+    registry_branches: Option<Vec<String>>,
+) -> Result<RemoteVersionReport, CommandError> {
+    let refs = GitOperations::list_remote_refs(&git_url)
+        .map_err(CommandError::from)?;
+
+    let (tags, branches): (Vec<RemoteRef>, Vec<RemoteRef>) = refs.into_iter()
+        .partition(|r| r.kind == "tag");
+
+    let registry_tags = registry_tags.unwrap_or_default();
+    let registry_branches = registry_branches.unwrap_or_default();
+
+    let unlisted_tags = tags.iter()
+        .filter(|t| !registry_tags.contains(&t.name))
+        .map(|t| t.name.clone())
+        .collect();
+
+    let missing_tags = registry_tags.iter()
+        .filter(|tag| !tags.iter().any(|t| &t.name == *tag))
+        .cloned()
+        .collect();
+
+    let missing_branches = registry_branches.iter()
+        .filter(|branch| !branches.iter().any(|b| &b.name == *branch))
+        .cloned()
+        .collect();
+
+    Ok(RemoteVersionReport {
+        git_url,
+        tags,
+        branches,
+        unlisted_tags,
+        missing_tags,
+        missing_branches,
+    })
+}
+
+#[command]
+#[instrument(skip_all, fields(project = %project_path))]
+pub async fn get_installed_packages(project_path: String) -> Result<Vec<InstalledPackage>, CommandError> {
+    let project_info = PackageManager::get_project_info(&project_path)
+        .map_err(CommandError::from)?;
+
+    Ok(project_info.packages)
+}
+
+#[command]
+#[instrument(skip_all, fields(project = %project_path))]
+pub async fn install_package(project_path: String, package: Package) -> Result<InstallResult, CommandError> {
+    refresh_advisories().await;
+
+    let mut entry = HistoryStore::begin_with_snapshot(&project_path, "install", &package.name);
+    entry.to_version = package.version.clone();
+
+    let result = PackageManager::install_package(&project_path, &package)
+        .map(|result| with_advisory_warnings(&project_path, result))
+        .map_err(CommandError::from);
+
+    record_install(entry, &result);
+    result
+}
+
+#[command]
+#[instrument(skip_all, fields(project = %project_path))]
+pub async fn install_from_git_url(
+    project_path: String,
+    git_url: String,
+    git_tag: Option<String>,
+    git_branch: Option<String>,
+    git_commit: Option<String>,
+) -> Result<InstallResult, CommandError> {
+    refresh_advisories().await;
+
+    // The package name is only known once the repository is cloned, so its previous state can't be kept.
+    // A package that is new to the project can still be undone by removing it.
+    let installed_before: Vec<String> = PackageManager::get_project_info(&project_path)
+        .map(|info| info.packages.into_iter().map(|p| p.name).collect())
+        .unwrap_or_default();
+    let mut entry = HistoryStore::begin(&project_path, "install", None);
+
+    let result = PackageManager::install_from_git_url(
+        &project_path,
+        &git_url,
+        git_tag.as_deref(),
+        git_branch.as_deref(),
+        git_commit.as_deref(),
+    )
+    .map(|result| with_advisory_warnings(&project_path, result))
+    .map_err(CommandError::from);
+
+    if let Ok(installed) = &result {
+        if !installed_before.contains(&installed.package_name) {
+            HistoryStore::snapshot_new_package(&mut entry, &installed.package_name);
+        }
+    }
+
+    record_install(entry, &result);
+    result
+}
+
+#[command]
+#[instrument(skip_all, fields(project = %project_path))]
+pub async fn install_from_local_folder(
+    project_path: String,
+    source_path: String,
+    copy: bool,
+    relative_reference: Option<bool>,
+) -> Result<InstallResult, CommandError> {
+    refresh_advisories().await;
+
+    let entry = begin_source_install(&project_path, "install", &source_path);
+
+    let result = PackageManager::install_from_local_folder(&project_path, &source_path, copy, relative_reference.unwrap_or(true))
+        .map(|result| with_advisory_warnings(&project_path, result))
+        .map_err(CommandError::from);
+
+    record_install(entry, &result);
+    result
+}
+
+#[command]
+#[instrument(skip_all, fields(project = %project_path))]
+pub async fn install_from_tarball(project_path: String, tarball_path: String) -> Result<InstallResult, CommandError> {
+    refresh_advisories().await;
+
+    let entry = begin_source_install(&project_path, "install", &tarball_path);
+
+    let result = PackageManager::install_from_tarball(&project_path, &tarball_path)
+        .map(|result| with_advisory_warnings(&project_path, result))
+        .map_err(CommandError::from);
+
+    record_install(entry, &result);
+    result
+}
+
+#[command]
+#[instrument(skip_all, fields(project = %project_path))]
+pub async fn find_adoptable_git_dependencies(project_path: String) -> Result<Vec<AdoptionCandidate>, CommandError> {
+    let git_dependencies = PackageManager::get_git_dependencies(&project_path)
+        .map_err(CommandError::from)?;
+
+    if git_dependencies.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let config = Config::load().map_err(CommandError::from)?;
+    let mut candidates: Vec<AdoptionCandidate> = Vec::new();
+
+    for registry in config.registries.iter().filter(|r| r.enabled) {
+        let registry_data = match RegistryClient::fetch_registry(&registry.url, Some(&registry.id)).await {
+            Ok(registry_data) => registry_data,
+            Err(e) => {
+                warn!("Skipping registry {} while looking for adoptable packages: {}", registry.name, e);
+                continue;
+            }
+        };
+
+        for (package_name, dependency, locked_commit) in &git_dependencies {
+            if candidates.iter().any(|c| &c.package_name == package_name) {
+                continue;
+            }
+
+            let matches = registry_data.packages.iter().any(|p| {
+                &p.name == package_name && GitOperations::urls_match(&p.git_url, &dependency.url)
+            });
+
+            if matches {
+                candidates.push(AdoptionCandidate {
+                    package_name: package_name.clone(),
+                    manifest_entry: PackageManager::check_package_conflicts(&project_path, package_name)
+                        .map_err(CommandError::from)?
+                        .unwrap_or_default(),
+                    git_url: dependency.url.clone(),
+                    git_ref: dependency.reference.clone(),
+                    subfolder: dependency.path.clone(),
+                    locked_commit: locked_commit.clone(),
+                    registry_id: registry.id.clone(),
+                    registry_name: registry.name.clone(),
+                });
             }
-        } else {
-            // Legacy format: single version package
-            expanded_packages.push(package);
         }
     }
 
-    registry.packages = expanded_packages;
-    Ok(registry)
+    Ok(candidates)
+}
+
+#[command]
+#[instrument(skip_all, fields(project = %project_path))]
+pub async fn adopt_git_dependency(
+    project_path: String,
+    package_name: String,
+    registry_id: String,
+) -> Result<InstallResult, CommandError> {
+    refresh_advisories().await;
+
+    let mut entry = HistoryStore::begin_with_snapshot(&project_path, "adopt", &package_name);
+    entry.registry_id = Some(registry_id.clone());
+
+    let result = match find_registry_package(&registry_id, &package_name).await {
+        Ok(package) => PackageManager::adopt_git_dependency(&project_path, &package)
+            .map(|result| with_advisory_warnings(&project_path, result))
+            .map_err(CommandError::from),
+        Err(e) => Err(e),
+    };
+
+    record_install(entry, &result);
+    result
+}
+
+async fn find_registry_package(registry_id: &str, package_name: &str) -> Result<Package, CommandError> {
+    let config = Config::load().map_err(CommandError::from)?;
+    let registry = config.registries.iter()
+        .find(|r| r.id == registry_id)
+        .ok_or_else(|| CupmError::RegistryNotFound { registry_id: registry_id.to_string() })?;
+
+    let registry_data = RegistryClient::fetch_registry(&registry.url, Some(&registry.id))
+        .await
+        .map_err(CommandError::from)?;
+
+    let package = registry_data.packages.into_iter()
+        .find(|p| p.name == package_name)
+        .ok_or_else(|| CupmError::PackageNotInRegistry {
+            package_name: package_name.to_string(),
+            registry: registry.name.clone(),
+        })?;
+
+    Ok(package)
 }
 
 #[command]
-pub async fn get_installed_packages(project_path: String) -> Result<Vec<InstalledPackage>, String> {
+#[instrument(skip_all, fields(project = %project_path))]
+pub async fn convert_to_git_dependency(project_path: String, package_name: String) -> Result<String, CommandError> {
+    let entry = HistoryStore::begin_with_snapshot(&project_path, "convert", &package_name);
+
+    let result = PackageManager::convert_to_git_dependency(&project_path, &package_name)
+        .map_err(CommandError::from);
+
+    record_outcome(entry, &result);
+    result
+}
+
+#[command]
+#[instrument(skip_all, fields(project = %project_path))]
+pub async fn acknowledge_safety_report(project_path: String, report: SafetyReport) -> Result<(), CommandError> {
+    SafetyScanner::acknowledge(&project_path, &report)
+        .map_err(CommandError::from)
+}
+
+#[command]
+#[instrument(skip_all, fields(project = %project_path))]
+pub async fn audit_project(project_path: String) -> Result<Vec<AdvisoryMatch>, CommandError> {
+    refresh_advisories().await;
+
     let project_info = PackageManager::get_project_info(&project_path)
-        .map_err(|e| e.to_string())?;
+        .map_err(CommandError::from)?;
 
-    Ok(project_info.packages)
+    Ok(AdvisoryStore::audit(&project_info.packages))
+}
+
+#[command]
+#[instrument(skip_all, fields(project = %project_path))]
+pub async fn verify_package(project_path: String, package_name: String) -> Result<PackageVerification, CommandError> {
+    PackageManager::verify_package(&project_path, &package_name)
+        .map_err(CommandError::from)
+}
+
+#[command]
+#[instrument(skip_all, fields(project = %project_path))]
+pub async fn diagnose_project(project_path: String) -> Result<Vec<ProjectIssue>, CommandError> {
+    PackageManager::diagnose_project(&project_path)
+        .map_err(CommandError::from)
+}
+
+#[command]
+#[instrument(skip_all, fields(project = %project_path))]
+pub async fn repair_project(project_path: String) -> Result<RepairReport, CommandError> {
+    let entry = HistoryStore::begin(&project_path, "repair", None);
+
+    let result = PackageManager::repair_project(&project_path)
+        .map_err(CommandError::from);
+
+    record_outcome(entry, &result);
+    result
+}
+
+#[command]
+#[instrument(skip_all, fields(project = %project_path))]
+pub async fn migrate_package_folders(project_path: String) -> Result<FolderMigrationReport, CommandError> {
+    let entry = HistoryStore::begin(&project_path, "migrate_folders", None);
+
+    let result = PackageManager::migrate_package_folders(&project_path)
+        .map_err(CommandError::from);
+
+    record_outcome(entry, &result);
+    result
+}
+
+#[command]
+#[instrument(skip_all, fields(project = %project_path))]
+pub async fn find_orphaned_packages(project_path: String) -> Result<Vec<OrphanedPackage>, CommandError> {
+    PackageManager::find_orphaned_packages(&project_path)
+        .map_err(CommandError::from)
+}
+
+#[command]
+#[instrument(skip_all, fields(project = %project_path))]
+pub async fn delete_orphaned_package(project_path: String, folder_name: String) -> Result<(), CommandError> {
+    let entry = HistoryStore::begin(&project_path, "delete_orphan", None);
+
+    let result = PackageManager::delete_orphaned_package(&project_path, &folder_name)
+        .map_err(CommandError::from);
+
+    record_outcome(entry, &result);
+    result
+}
+
+#[command]
+#[instrument(skip_all, fields(project = %project_path))]
+pub async fn register_orphaned_package(project_path: String, folder_name: String) -> Result<String, CommandError> {
+    let entry = HistoryStore::begin(&project_path, "register_orphan", None);
+
+    let result = PackageManager::register_orphaned_package(&project_path, &folder_name)
+        .map_err(CommandError::from);
+
+    match &result {
+        Ok(package_name) => HistoryStore::finish(entry, Some(package_name), None, None),
+        Err(e) => HistoryStore::finish(entry, None, None, Some(e)),
+    }
+    result
+}
+
+#[command]
+#[instrument(skip_all, fields(project = %project_path))]
+pub async fn get_branch_status(project_path: String, package_name: String) -> Result<BranchStatus, CommandError> {
+    PackageManager::get_branch_status(&project_path, &package_name)
+        .map_err(CommandError::from)
+}
+
+#[command]
+#[instrument(skip_all, fields(project = %project_path))]
+pub async fn fast_forward_package(project_path: String, package_name: String) -> Result<InstallResult, CommandError> {
+    refresh_advisories().await;
+
+    let entry = HistoryStore::begin_with_snapshot(&project_path, "fast_forward", &package_name);
+
+    let result = PackageManager::fast_forward_package(&project_path, &package_name)
+        .map(|result| with_advisory_warnings(&project_path, result))
+        .map_err(CommandError::from);
+
+    record_install(entry, &result);
+    result
+}
+
+#[command]
+#[instrument(skip_all, fields(project = %project_path))]
+pub async fn link_package(project_path: String, source_path: String, use_symlink: bool) -> Result<InstallResult, CommandError> {
+    let entry = begin_source_install(&project_path, "link", &source_path);
+
+    let result = PackageManager::link_package(&project_path, &source_path, use_symlink)
+        .map_err(CommandError::from);
+
+    record_install(entry, &result);
+    result
+}
+
+#[command]
+#[instrument(skip_all, fields(project = %project_path))]
+pub async fn unlink_package(project_path: String, package_name: String) -> Result<Option<InstallResult>, CommandError> {
+    let entry = HistoryStore::begin_with_snapshot(&project_path, "unlink", &package_name);
+
+    let result = PackageManager::unlink_package(&project_path, &package_name)
+        .map_err(CommandError::from);
+
+    // Unlinking reinstalls the package it replaced, if there was one
+    match &result {
+        Ok(Some(restored)) => HistoryStore::finish(entry, None, Some(&restored.version), None),
+        _ => record_outcome(entry, &result),
+    }
+    result
+}
+
+#[command]
+#[instrument(skip_all, fields(project = %project_path))]
+pub async fn update_package(project_path: String, package: Package) -> Result<InstallResult, CommandError> {
+    refresh_advisories().await;
+
+    let mut entry = HistoryStore::begin_with_snapshot(&project_path, "update", &package.name);
+    entry.to_version = package.version.clone();
+
+    let result = PackageManager::update_package(&project_path, &package)
+        .map(|result| with_advisory_warnings(&project_path, result))
+        .map_err(CommandError::from);
+
+    record_install(entry, &result);
+    result
+}
+
+#[command]
+#[instrument(skip_all, fields(project = %project_path))]
+pub async fn remove_package(project_path: String, package_name: String) -> Result<(), CommandError> {
+    let entry = HistoryStore::begin_with_snapshot(&project_path, "remove", &package_name);
+
+    let result = PackageManager::remove_package(&project_path, &package_name)
+        .map_err(CommandError::from);
+
+    record_outcome(entry, &result);
+    result
+}
+
+#[command]
+#[instrument(skip_all, fields(project = %project_path))]
+pub async fn create_package_patch(project_path: String, package_name: String) -> Result<Option<String>, CommandError> {
+    let package_path = PackageManager::get_package_repository_path(&project_path, &package_name)
+        .map_err(CommandError::from)?;
+
+    let patch_path = PatchManager::create_patch(&project_path, &package_name, &package_path)
+        .map_err(CommandError::from)?;
+
+    Ok(patch_path.map(|p| p.to_string_lossy().to_string()))
+}
+
+#[command]
+#[instrument(skip_all, fields(project = %project_path))]
+pub async fn apply_package_patch(project_path: String, package_name: String) -> Result<Option<PatchResult>, CommandError> {
+    let package_path = PackageManager::get_package_repository_path(&project_path, &package_name)
+        .map_err(CommandError::from)?;
+
+    PatchManager::apply_patch(&project_path, &package_name, &package_path)
+        .map_err(CommandError::from)
+}
+
+#[command]
+#[instrument(skip_all, fields(project = %project_path))]
+pub async fn list_package_patches(project_path: String) -> Result<Vec<PackagePatch>, CommandError> {
+    PatchManager::list_patches(&project_path)
+        .map_err(CommandError::from)
+}
+
+#[command]
+#[instrument(skip_all, fields(project = %project_path))]
+pub async fn remove_package_patch(project_path: String, package_name: String) -> Result<(), CommandError> {
+    PatchManager::remove_patch(&project_path, &package_name)
+        .map_err(CommandError::from)
+}
+
+#[command]
+#[instrument(skip_all, fields(project = %project_path))]
+pub async fn import_unity_package(
+    project_path: String,
+    archive_path: String,
+    overwrite: Option<bool>,
+) -> Result<UnityPackageImportReport, CommandError> {
+    let entry = HistoryStore::begin(&project_path, "import_unitypackage", None);
+
+    let result = UnityPackageArchive::import(&project_path, &archive_path, overwrite.unwrap_or(false))
+        .map_err(CommandError::from);
+
+    record_outcome(entry, &result);
+    result
 }
 
 #[command]
-pub async fn install_package(project_path: String, package: Package) -> Result<(), String> {
-    PackageManager::install_package(&project_path, &package)
-        .map_err(|e| e.to_string())
+#[instrument(skip_all, fields(project = %project_path))]
+pub async fn export_unity_package(
+    project_path: String,
+    package_name: String,
+    output_path: String,
+    assets_path: Option<String>,
+) -> Result<UnityPackageExportReport, CommandError> {
+    let package_path = PackageManager::get_package_path(&project_path, &package_name)
+        .map_err(CommandError::from)?;
+
+    let assets_path = assets_path.unwrap_or_else(|| format!("Assets/{}", package_name));
+
+    UnityPackageArchive::export(&package_path, std::path::Path::new(&output_path), &assets_path)
+        .map_err(CommandError::from)
 }
 
 #[command]
-pub async fn update_package(project_path: String, package: Package) -> Result<(), String> {
-    // For updates, we can just reinstall the package
-    PackageManager::install_package(&project_path, &package)
-        .map_err(|e| e.to_string())
+#[instrument(skip_all, fields(project = %project_path))]
+pub async fn list_unity_package_imports(project_path: String) -> Result<Vec<UnityPackageImport>, CommandError> {
+    UnityPackageArchive::list_imports(&project_path)
+        .map_err(CommandError::from)
 }
 
 #[command]
-pub async fn remove_package(project_path: String, package_name: String) -> Result<(), String> {
-    PackageManager::remove_package(&project_path, &package_name)
-        .map_err(|e| e.to_string())
+#[instrument(skip_all, fields(project = %project_path))]
+pub async fn remove_unity_package_import(project_path: String, import_id: String) -> Result<(), CommandError> {
+    let entry = HistoryStore::begin(&project_path, "remove_unitypackage_import", None);
+
+    let result = UnityPackageArchive::remove_import(&project_path, &import_id)
+        .map_err(CommandError::from);
+
+    record_outcome(entry, &result);
+    result
 }
 
 #[command]
-pub async fn check_package_conflicts(project_path: String, package_name: String) -> Result<Option<String>, String> {
+#[instrument(skip_all, fields(project = %project_path))]
+pub async fn check_package_conflicts(project_path: String, package_name: String) -> Result<Option<String>, CommandError> {
     PackageManager::check_package_conflicts(&project_path, &package_name)
-        .map_err(|e| e.to_string())
+        .map_err(CommandError::from)
 }
 
 #[command]
-pub async fn get_installed_package_info(project_path: String, package_name: String) -> Result<Option<(String, String)>, String> {
+#[instrument(skip_all, fields(project = %project_path))]
+pub async fn get_installed_package_info(project_path: String, package_name: String) -> Result<Option<(String, String)>, CommandError> {
     PackageManager::get_installed_package_info(&project_path, &package_name)
-        .map_err(|e| e.to_string())
+        .map_err(CommandError::from)
 }
 
 #[command]
-pub async fn get_project_info(project_path: String) -> Result<ProjectInfo, String> {
+#[instrument(skip_all, fields(project = %project_path))]
+pub async fn get_project_info(project_path: String) -> Result<ProjectInfo, CommandError> {
     PackageManager::get_project_info(&project_path)
-        .map_err(|e| e.to_string())
+        .map_err(CommandError::from)
+}
+
+/// Records provenance for embedded git packages installed before CUPM tracked it,
+/// by matching their origin URL and name against the enabled registries.
+/// Fetches every enabled registry, so the frontend runs it once when a project is opened rather than on every refresh.
+/// Returns the names of the packages that were adopted.
+#[command]
+#[instrument(skip_all, fields(project = %project_path))]
+pub async fn adopt_unmanaged_packages(project_path: String) -> Result<Vec<String>, CommandError> {
+    let mut adopted = Vec::new();
+
+    let unmanaged = PackageManager::get_unmanaged_git_packages(&project_path)
+        .map_err(CommandError::from)?;
+
+    if unmanaged.is_empty() {
+        return Ok(adopted);
+    }
+
+    let config = Config::load().map_err(CommandError::from)?;
+
+    for registry in config.registries.iter().filter(|r| r.enabled) {
+        let registry_data = match RegistryClient::fetch_registry(&registry.url, Some(&registry.id)).await {
+            Ok(registry_data) => registry_data,
+            Err(e) => {
+                warn!("Skipping registry {} while adopting packages: {}", registry.name, e);
+                continue;
+            }
+        };
+
+        for (package_name, origin_url) in &unmanaged {
+            let matches = registry_data.packages.iter().any(|p| {
+                &p.name == package_name && GitOperations::urls_match(&p.git_url, origin_url)
+            });
+
+            if matches && ProvenanceStore::get(&project_path, package_name).map_err(CommandError::from)?.is_none() {
+                PackageManager::adopt_package(&project_path, package_name, &registry.id)
+                    .map_err(CommandError::from)?;
+                adopted.push(package_name.clone());
+            }
+        }
+    }
+
+    Ok(adopted)
 }
 
 #[command]
-pub async fn set_theme(theme: String) -> Result<(), String> {
-    let mut config = Config::load().map_err(|e| e.to_string())?;
+pub async fn set_theme(theme: String) -> Result<(), CommandError> {
+    let mut config = Config::load().map_err(CommandError::from)?;
     config.theme = theme;
-    Config::save(&config).map_err(|e| e.to_string())?;
+    Config::save(&config).map_err(CommandError::from)?;
     Ok(())
 }
 
 #[command]
-pub async fn show_open_dialog(options: serde_json::Value) -> Result<Option<String>, String> {
+pub async fn show_open_dialog(options: serde_json::Value) -> Result<Option<String>, CommandError> {
     use tauri::api::dialog::blocking::FileDialogBuilder;
 
     let dialog = FileDialogBuilder::new();
@@ -215,3 +797,59 @@ pub async fn show_open_dialog(options: serde_json::Value) -> Result<Option<Strin
 
     Ok(dialog.map(|path| path.to_string_lossy().to_string()))
 }
+
+#[command]
+pub async fn get_log_entries(
+    operation: Option<String>,
+    project_path: Option<String>,
+    level: Option<String>,
+    limit: Option<usize>,
+) -> Result<Vec<LogEntry>, CommandError> {
+    Logging::read_entries(operation.as_deref(), project_path.as_deref(), level.as_deref(), limit.unwrap_or(500))
+        .map_err(CommandError::from)
+}
+
+#[command]
+pub async fn export_support_bundle(output_path: String, project_path: Option<String>) -> Result<String, CommandError> {
+    let bundle_path = Logging::export_support_bundle(std::path::Path::new(&output_path), project_path.as_deref())
+        .map_err(CommandError::from)?;
+    Ok(bundle_path.to_string_lossy().to_string())
+}
+
+#[command]
+#[instrument(skip_all, fields(project = %project_path))]
+pub async fn get_operation_history(project_path: String, filter: Option<HistoryFilter>) -> Result<Vec<HistoryEntry>, CommandError> {
+    HistoryStore::query(&project_path, &filter.unwrap_or_default())
+        .map_err(CommandError::from)
+}
+
+#[command]
+#[instrument(skip_all, fields(project = %project_path))]
+pub async fn export_operation_history(
+    project_path: String,
+    output_path: String,
+    format: String,
+    filter: Option<HistoryFilter>,
+) -> Result<usize, CommandError> {
+    HistoryStore::export(&project_path, &filter.unwrap_or_default(), std::path::Path::new(&output_path), &format)
+        .map_err(CommandError::from)
+}
+
+#[command]
+#[instrument(skip_all, fields(project = %project_path))]
+pub async fn undo_last_operation(project_path: String) -> Result<HistoryEntry, CommandError> {
+    let operation = HistoryStore::get_last_undoable(&project_path)
+        .map_err(CommandError::from)?;
+
+    let mut reverted = revert_operations(&project_path, vec![operation])?;
+    Ok(reverted.remove(0))
+}
+
+#[command]
+#[instrument(skip_all, fields(project = %project_path))]
+pub async fn rollback_to(project_path: String, operation_id: String) -> Result<Vec<HistoryEntry>, CommandError> {
+    let operations = HistoryStore::get_operations_since(&project_path, &operation_id)
+        .map_err(CommandError::from)?;
+
+    revert_operations(&project_path, operations)
+}
@@ -2,6 +2,7 @@ use anyhow::{Context, Result};
 use serde_json;
 use std::fs;
 use std::path::PathBuf;
+use crate::errors::CupmError;
 use crate::models::AppConfig;
 
 pub struct Config;
@@ -32,7 +33,10 @@ impl Config {
             .context("Failed to read config file")?;
 
         let config: AppConfig = serde_json::from_str(&content)
-            .context("Failed to parse config file")?;
+            .map_err(|e| CupmError::ConfigInvalid {
+                path: config_path.to_string_lossy().to_string(),
+                reason: e.to_string(),
+            })?;
 
         Ok(config)
     }
@@ -0,0 +1,320 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fmt;
+use std::path::Path;
+use tracing::error;
+use crate::advisories::AdvisoryBlockedError;
+use crate::git_url_rules::GitUrlPolicyError;
+use crate::integrity::IntegrityError;
+use crate::registry_trust::RegistryTrustError;
+use crate::safety_scan::SafetyScanError;
+use crate::security::SecurityError;
+
+/// Failures the frontend needs to tell apart, raised with `.into()` wherever an `anyhow::Error` is returned.
+#[derive(Debug, Clone)]
+pub enum CupmError {
+    ConfigInvalid { path: String, reason: String },
+    RegistryNotFound { registry_id: String },
+    RegistryUnreachable { url: String, reason: String },
+    RegistryInvalid { url: String, reason: String },
+    PackageNotInRegistry { package_name: String, registry: String },
+    CloneFailed { url: String, reason: String },
+    RefNotFound { kind: String, reference: String, path: String }, // kind is "tag" or "branch"
+    LocalChanges { package_name: String, files: Vec<String> },
+    ManifestNotFound { path: String },
+    PackageNotInstalled { package_name: String },
+    PackageNotEmbedded { package_name: String, reference: String },
+    PackageLinked { package_name: String, source_path: String },
+    PathNotFound { path: String },
+    FolderOccupied { path: String, occupant: Option<String> },
+    InvalidPackageName { name: String, reason: String },
+    NotAUnityPackage { path: String },
+    UnsupportedFormat { format: String, supported: Vec<String> },
+    NothingToUndo,
+    OperationNotFound { operation_id: String },
+    OperationNotRevertible { operation_id: String, reason: String },
+}
+
+impl CupmError {
+    pub fn manifest_not_found(path: &Path) -> Self {
+        CupmError::ManifestNotFound { path: path.to_string_lossy().to_string() }
+    }
+
+    /// Stable identifier the frontend can match on, independent of the message wording.
+    pub fn code(&self) -> &'static str {
+        match self {
+            CupmError::ConfigInvalid { .. } => "config_invalid",
+            CupmError::RegistryNotFound { .. } => "registry_not_found",
+            CupmError::RegistryUnreachable { .. } => "registry_unreachable",
+            CupmError::RegistryInvalid { .. } => "registry_invalid",
+            CupmError::PackageNotInRegistry { .. } => "package_not_in_registry",
+            CupmError::CloneFailed { .. } => "git_clone_failed",
+            CupmError::RefNotFound { .. } => "git_ref_not_found",
+            CupmError::LocalChanges { .. } => "local_changes",
+            CupmError::ManifestNotFound { .. } => "manifest_not_found",
+            CupmError::PackageNotInstalled { .. } => "package_not_installed",
+            CupmError::PackageNotEmbedded { .. } => "package_not_embedded",
+            CupmError::PackageLinked { .. } => "package_linked",
+            CupmError::PathNotFound { .. } => "path_not_found",
+            CupmError::FolderOccupied { .. } => "folder_occupied",
+            CupmError::InvalidPackageName { .. } => "invalid_package_name",
+            CupmError::NotAUnityPackage { .. } => "not_a_unity_package",
+            CupmError::UnsupportedFormat { .. } => "unsupported_format",
+            CupmError::NothingToUndo => "nothing_to_undo",
+            CupmError::OperationNotFound { .. } => "operation_not_found",
+            CupmError::OperationNotRevertible { .. } => "operation_not_revertible",
+        }
+    }
+
+    pub fn category(&self) -> &'static str {
+        match self {
+            CupmError::ConfigInvalid { .. } | CupmError::RegistryNotFound { .. } => "config",
+            CupmError::RegistryUnreachable { .. }
+            | CupmError::RegistryInvalid { .. }
+            | CupmError::PackageNotInRegistry { .. } => "registry",
+            CupmError::CloneFailed { .. } | CupmError::RefNotFound { .. } | CupmError::LocalChanges { .. } => "git",
+            CupmError::ManifestNotFound { .. }
+            | CupmError::PackageNotInstalled { .. }
+            | CupmError::PackageNotEmbedded { .. }
+            | CupmError::PackageLinked { .. } => "manifest",
+            CupmError::PathNotFound { .. } | CupmError::FolderOccupied { .. } => "filesystem",
+            CupmError::InvalidPackageName { .. }
+            | CupmError::NotAUnityPackage { .. }
+            | CupmError::UnsupportedFormat { .. } => "validation",
+            CupmError::NothingToUndo
+            | CupmError::OperationNotFound { .. }
+            | CupmError::OperationNotRevertible { .. } => "history",
+        }
+    }
+
+    pub fn fields(&self) -> Map<String, Value> {
+        let mut fields = Map::new();
+        let mut set = |key: &str, value: Value| {
+            fields.insert(key.to_string(), value);
+        };
+
+        match self {
+            CupmError::ConfigInvalid { path, reason } => {
+                set("path", path.as_str().into());
+                set("reason", reason.as_str().into());
+            }
+            CupmError::RegistryNotFound { registry_id } => set("registry_id", registry_id.as_str().into()),
+            CupmError::RegistryUnreachable { url, reason } | CupmError::RegistryInvalid { url, reason } => {
+                set("url", url.as_str().into());
+                set("reason", reason.as_str().into());
+            }
+            CupmError::PackageNotInRegistry { package_name, registry } => {
+                set("package_name", package_name.as_str().into());
+                set("registry", registry.as_str().into());
+            }
+            CupmError::CloneFailed { url, reason } => {
+                set("url", url.as_str().into());
+                set("reason", reason.as_str().into());
+            }
+            CupmError::RefNotFound { kind, reference, path } => {
+                set("ref_kind", kind.as_str().into());
+                set(kind, reference.as_str().into());
+                set("path", path.as_str().into());
+            }
+            CupmError::LocalChanges { package_name, files } => {
+                set("package_name", package_name.as_str().into());
+                set("files", files.clone().into());
+            }
+            CupmError::ManifestNotFound { path } | CupmError::PathNotFound { path } | CupmError::NotAUnityPackage { path } => {
+                set("path", path.as_str().into());
+            }
+            CupmError::PackageNotInstalled { package_name } => set("package_name", package_name.as_str().into()),
+            CupmError::PackageNotEmbedded { package_name, reference } => {
+                set("package_name", package_name.as_str().into());
+                set("reference", reference.as_str().into());
+            }
+            CupmError::PackageLinked { package_name, source_path } => {
+                set("package_name", package_name.as_str().into());
+                set("source_path", source_path.as_str().into());
+            }
+            CupmError::FolderOccupied { path, occupant } => {
+                set("path", path.as_str().into());
+                set("occupant", occupant.clone().into());
+            }
+            CupmError::InvalidPackageName { name, reason } => {
+                set("name", name.as_str().into());
+                set("reason", reason.as_str().into());
+            }
+            CupmError::UnsupportedFormat { format, supported } => {
+                set("format", format.as_str().into());
+                set("supported", supported.clone().into());
+            }
+            CupmError::NothingToUndo => {}
+            CupmError::OperationNotFound { operation_id } => set("operation_id", operation_id.as_str().into()),
+            CupmError::OperationNotRevertible { operation_id, reason } => {
+                set("operation_id", operation_id.as_str().into());
+                set("reason", reason.as_str().into());
+            }
+        }
+
+        fields
+    }
+}
+
+impl fmt::Display for CupmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CupmError::ConfigInvalid { path, reason } => write!(f, "Config file {} is invalid: {}", path, reason),
+            CupmError::RegistryNotFound { registry_id } => write!(f, "Registry {} not found", registry_id),
+            CupmError::RegistryUnreachable { url, reason } => write!(f, "Failed to fetch registry {}: {}", url, reason),
+            CupmError::RegistryInvalid { url, reason } => write!(f, "Failed to parse registry JSON from {}: {}", url, reason),
+            CupmError::PackageNotInRegistry { package_name, registry } => {
+                write!(f, "Package {} not found in registry {}", package_name, registry)
+            }
+            CupmError::CloneFailed { url, reason } => write!(f, "Failed to clone repository from {}: {}", url, reason),
+            CupmError::RefNotFound { kind, reference, .. } => {
+                write!(f, "Failed to find {} '{}' even after fetching", kind, reference)
+            }
+            CupmError::LocalChanges { package_name, files } => {
+                write!(f, "Package {} has local changes: {}", package_name, files.join(", "))
+            }
+            CupmError::ManifestNotFound { .. } => write!(f, "Project manifest.json not found"),
+            CupmError::PackageNotInstalled { package_name } => {
+                write!(f, "Package {} is not installed in this project", package_name)
+            }
+            CupmError::PackageNotEmbedded { package_name, reference } => {
+                write!(f, "Package {} is not an embedded package ({})", package_name, reference)
+            }
+            CupmError::PackageLinked { package_name, source_path } => {
+                write!(f, "Package {} is linked to the working copy at {}, unlink it first", package_name, source_path)
+            }
+            CupmError::PathNotFound { path } => write!(f, "{} not found", path),
+            CupmError::FolderOccupied { path, occupant: Some(occupant) } => {
+                write!(f, "Folder {} is occupied by a different package ({}), remove or move it first", path, occupant)
+            }
+            CupmError::FolderOccupied { path, occupant: None } => {
+                write!(f, "Folder {} already exists and isn't a copy of the package, remove or move it first", path)
+            }
+            CupmError::InvalidPackageName { name, reason } => write!(f, "Invalid package name '{}', {}", name, reason),
+            CupmError::NotAUnityPackage { path } => {
+                write!(f, "No package.json found in {}, this is not a Unity package", path)
+            }
+            CupmError::UnsupportedFormat { format, supported } => {
+                write!(f, "Unsupported format '{}', expected one of: {}", format, supported.join(", "))
+            }
+            CupmError::NothingToUndo => write!(f, "There is no operation left to undo in this project"),
+            CupmError::OperationNotFound { operation_id } => write!(f, "Operation {} not found in the project history", operation_id),
+            CupmError::OperationNotRevertible { operation_id, reason } => {
+                write!(f, "Operation {} can't be undone: {}", operation_id, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CupmError {}
+
+/// What commands send to the frontend when they fail.
+/// `code` is stable across releases, `message` and `context` are for display and may change wording.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommandError {
+    pub code: String,
+    pub category: String, // "config", "registry", "git", "manifest", "filesystem", "validation", "history", "security", "internal"
+    pub message: String,
+    pub context: Vec<String>, // Causes below the message, outermost first
+    pub fields: Map<String, Value>,
+}
+
+impl CommandError {
+    fn new(code: &str, category: &str, fields: Map<String, Value>, e: &anyhow::Error) -> Self {
+        Self {
+            code: code.to_string(),
+            category: category.to_string(),
+            message: e.to_string(),
+            context: e.chain().skip(1).map(|cause| cause.to_string()).collect(),
+            fields,
+        }
+    }
+
+    /// Uses a policy error's own fields, minus the kind and message that already make up the code and message.
+    fn from_policy_error<T: Serialize>(error: &T, category: &str, e: &anyhow::Error) -> Self {
+        let mut fields = match serde_json::to_value(error) {
+            Ok(Value::Object(fields)) => fields,
+            _ => Map::new(),
+        };
+
+        let code = fields.remove("kind")
+            .and_then(|kind| kind.as_str().map(|kind| kind.to_string()))
+            .unwrap_or_else(|| "policy_violation".to_string());
+        fields.remove("message");
+
+        Self::new(&code, category, fields, e)
+    }
+
+    fn classify(e: &anyhow::Error) -> Self {
+        if let Some(error) = find::<CupmError>(e) {
+            return Self::new(error.code(), error.category(), error.fields(), e);
+        }
+
+        if let Some(error) = find::<SecurityError>(e) {
+            return Self::from_policy_error(error, "security", e);
+        }
+        if let Some(error) = find::<SafetyScanError>(e) {
+            return Self::from_policy_error(error, "security", e);
+        }
+        if let Some(error) = find::<AdvisoryBlockedError>(e) {
+            return Self::from_policy_error(error, "security", e);
+        }
+        if let Some(error) = find::<IntegrityError>(e) {
+            return Self::from_policy_error(error, "security", e);
+        }
+        if let Some(error) = find::<RegistryTrustError>(e) {
+            return Self::from_policy_error(error, "security", e);
+        }
+        if let Some(error) = find::<GitUrlPolicyError>(e) {
+            return Self::from_policy_error(error, "security", e);
+        }
+
+        // Errors without a dedicated type are classified by the library they came from
+        let mut fields = Map::new();
+
+        if let Some(error) = find::<git2::Error>(e) {
+            fields.insert("git_class".to_string(), format!("{:?}", error.class()).into());
+            fields.insert("git_code".to_string(), format!("{:?}", error.code()).into());
+            return Self::new("git_error", "git", fields, e);
+        }
+
+        if let Some(error) = find::<reqwest::Error>(e) {
+            if let Some(url) = error.url() {
+                fields.insert("url".to_string(), url.as_str().into());
+            }
+            return Self::new("network_error", "registry", fields, e);
+        }
+
+        if let Some(error) = find::<serde_json::Error>(e) {
+            fields.insert("line".to_string(), error.line().into());
+            fields.insert("column".to_string(), error.column().into());
+            return Self::new("invalid_json", "validation", fields, e);
+        }
+
+        if let Some(error) = find::<std::io::Error>(e) {
+            fields.insert("io_kind".to_string(), format!("{:?}", error.kind()).into());
+            return Self::new("filesystem_error", "filesystem", fields, e);
+        }
+
+        Self::new("unexpected_error", "internal", fields, e)
+    }
+}
+
+fn find<T: std::error::Error + Send + Sync + 'static>(e: &anyhow::Error) -> Option<&T> {
+    e.chain().find_map(|cause| cause.downcast_ref::<T>())
+}
+
+impl From<anyhow::Error> for CommandError {
+    fn from(e: anyhow::Error) -> Self {
+        // Logged here so every failed command ends up in the log under its operation, with the full cause chain
+        let command_error = Self::classify(&e);
+        error!(code = %command_error.code, "{:#}", e);
+        command_error
+    }
+}
+
+impl From<CupmError> for CommandError {
+    fn from(e: CupmError) -> Self {
+        anyhow::Error::from(e).into()
+    }
+}
@@ -1,28 +1,51 @@
 use anyhow::{Context, Result};
-use git2::Repository;
+use git2::{Direction, Remote, Repository};
+use std::collections::HashMap;
+use std::fs;
 use std::path::Path;
+use tracing::{debug, info};
+use crate::errors::CupmError;
+use crate::git_url_rules::GitUrlRules;
+use crate::models::{IncomingCommit, RemoteRef};
+use crate::security::PathGuard;
 
 pub struct GitOperations;
 
+/// A git dependency as written in manifest.json, e.g. `https://host/repo.git?path=/sub#v1.0`
+pub struct GitDependency {
+    pub url: String,
+    pub path: Option<String>,
+    pub reference: Option<String>,
+}
+
 impl GitOperations {
     pub fn clone_repository(url: &str, path: &Path) -> Result<()> {
-        println!("Cloning repository {} to {}", url, path.display());
+        let resolved_url = GitUrlRules::resolve(url)?;
+
+        info!("Cloning repository {} to {}", resolved_url, path.display());
 
         // Simple clone first
-        let repo = Repository::clone(url, path)
-            .with_context(|| format!("Failed to clone repository from {}", url))?;
+        let repo = Repository::clone(&resolved_url, path)
+            .map_err(|e| CupmError::CloneFailed { url: resolved_url.clone(), reason: e.message().to_string() })?;
+
+        // Like insteadOf, origin keeps the URL the package was asked for and is rewritten again on every fetch,
+        // so changing the rules later takes effect and installs still match their registry URL
+        if resolved_url != url {
+            repo.remote_set_url("origin", url)
+                .context("Failed to set origin URL after clone")?;
+        }
 
-        println!("Successfully cloned repository");
+        debug!("Successfully cloned repository");
 
         // Fetch all tags explicitly
-        println!("Fetching all tags...");
-        let mut remote = repo.find_remote("origin")
+        debug!("Fetching all tags...");
+        let mut remote = Self::get_origin_remote(&repo)
             .context("Failed to find origin remote after clone")?;
 
         remote.fetch(&["+refs/tags/*:refs/tags/*"], None, None)
             .context("Failed to fetch tags")?;
 
-        println!("Successfully fetched tags");
+        debug!("Successfully fetched tags");
 
         Ok(())
     }
@@ -31,32 +54,36 @@ impl GitOperations {
         let repo = Repository::open(path)
             .context("Failed to open repository")?;
 
-        println!("Attempting to checkout tag: {}", tag);
+        debug!("Attempting to checkout tag: {}", tag);
 
         // First, try to find the tag reference
         let tag_ref_result = repo.find_reference(&format!("refs/tags/{}", tag));
 
         if let Err(_) = tag_ref_result {
             // Tag not found locally, try to fetch all refs from origin
-            println!("Tag not found locally, fetching from origin...");
-            let mut remote = repo.find_remote("origin")
+            debug!("Tag not found locally, fetching from origin...");
+            let mut remote = Self::get_origin_remote(&repo)
                 .context("Failed to find origin remote")?;
 
             // Fetch all refs including tags
             remote.fetch(&["+refs/*:refs/*"], None, None)
                 .context("Failed to fetch all refs from remote")?;
 
-            println!("Fetched refs from origin, retrying tag checkout...");
+            debug!("Fetched refs from origin, retrying tag checkout...");
         }
 
         // Try to find the tag reference again
         let tag_ref = repo.find_reference(&format!("refs/tags/{}", tag))
-            .with_context(|| format!("Failed to find tag '{}' even after fetching. Available tags may not include this version.", tag))?;
+            .map_err(|_| CupmError::RefNotFound {
+                kind: "tag".to_string(),
+                reference: tag.to_string(),
+                path: path.to_string_lossy().to_string(),
+            })?;
 
         let tag_commit = tag_ref.peel_to_commit()
             .context("Failed to get commit from tag")?;
 
-        println!("Found tag {}, commit: {}", tag, tag_commit.id());
+        debug!("Found tag {}, commit: {}", tag, tag_commit.id());
 
         // Checkout the tag (detached HEAD)
         repo.set_head_detached(tag_commit.id())
@@ -66,7 +93,7 @@ impl GitOperations {
         repo.reset(&tag_commit.as_object(), git2::ResetType::Hard, None)
             .context("Failed to reset working directory to tag")?;
 
-        println!("Successfully checked out tag: {}", tag);
+        debug!("Successfully checked out tag: {}", tag);
         Ok(())
     }
 
@@ -74,30 +101,34 @@ impl GitOperations {
         let repo = Repository::open(path)
             .context("Failed to open repository")?;
 
-        println!("Attempting to checkout branch: {}", branch);
+        debug!("Attempting to checkout branch: {}", branch);
 
         // First try to find the remote branch reference
         let branch_ref_result = repo.find_reference(&format!("refs/remotes/origin/{}", branch));
 
         if let Err(_) = branch_ref_result {
             // Branch not found locally, try to fetch from origin
-            println!("Branch not found locally, fetching from origin...");
-            let mut remote = repo.find_remote("origin")
+            debug!("Branch not found locally, fetching from origin...");
+            let mut remote = Self::get_origin_remote(&repo)
                 .context("Failed to find origin remote")?;
 
             remote.fetch(&["+refs/heads/*:refs/remotes/origin/*"], None, None)
                 .context("Failed to fetch branches from remote")?;
 
-            println!("Fetched branches from origin, retrying branch checkout...");
+            debug!("Fetched branches from origin, retrying branch checkout...");
         }
 
         let branch_ref = repo.find_reference(&format!("refs/remotes/origin/{}", branch))
-            .with_context(|| format!("Failed to find branch '{}' even after fetching", branch))?;
+            .map_err(|_| CupmError::RefNotFound {
+                kind: "branch".to_string(),
+                reference: branch.to_string(),
+                path: path.to_string_lossy().to_string(),
+            })?;
 
         let branch_commit = branch_ref.peel_to_commit()
             .context("Failed to get commit from branch")?;
 
-        println!("Found branch {}, commit: {}", branch, branch_commit.id());
+        debug!("Found branch {}, commit: {}", branch, branch_commit.id());
 
         // Checkout the branch (detached HEAD)
         repo.set_head_detached(branch_commit.id())
@@ -107,10 +138,333 @@ impl GitOperations {
         repo.reset(&branch_commit.as_object(), git2::ResetType::Hard, None)
             .context("Failed to reset working directory to branch")?;
 
-        println!("Successfully checked out branch: {}", branch);
+        debug!("Successfully checked out branch: {}", branch);
+        Ok(())
+    }
+
+    pub fn checkout_commit(path: &Path, commit: &str) -> Result<()> {
+        let repo = Repository::open(path)
+            .context("Failed to open repository")?;
+
+        debug!("Attempting to checkout commit: {}", commit);
+
+        let object = repo.revparse_single(commit)
+            .with_context(|| format!("Failed to find commit '{}'", commit))?;
+
+        let commit_object = object.peel_to_commit()
+            .with_context(|| format!("'{}' does not refer to a commit", commit))?;
+
+        repo.set_head_detached(commit_object.id())
+            .context("Failed to checkout commit")?;
+
+        repo.reset(commit_object.as_object(), git2::ResetType::Hard, None)
+            .context("Failed to reset working directory to commit")?;
+
+        debug!("Successfully checked out commit: {}", commit_object.id());
+        Ok(())
+    }
+
+    /// Resolves a tag, or a branch of origin, to the commit it points at as of the last fetch.
+    pub fn resolve_reference(path: &Path, kind: &str, reference: &str) -> Result<String> {
+        let repo = Repository::open(path)
+            .context("Failed to open repository")?;
+
+        let ref_name = match kind {
+            "tag" => format!("refs/tags/{}", reference),
+            _ => format!("refs/remotes/origin/{}", reference),
+        };
+
+        let commit = repo.find_reference(&ref_name)
+            .and_then(|r| r.peel_to_commit())
+            .map_err(|_| CupmError::RefNotFound {
+                kind: kind.to_string(),
+                reference: reference.to_string(),
+                path: path.to_string_lossy().to_string(),
+            })?;
+
+        Ok(commit.id().to_string())
+    }
+
+    /// Writes the files of a commit to a separate folder, leaving the repository's HEAD, index and working tree alone.
+    pub fn export_commit(path: &Path, commit: &str, destination: &Path) -> Result<()> {
+        let repo = Repository::open(path)
+            .context("Failed to open repository")?;
+
+        let commit_object = repo.revparse_single(commit)
+            .and_then(|object| object.peel_to_commit())
+            .with_context(|| format!("Failed to find commit '{}'", commit))?;
+
+        fs::create_dir_all(destination)
+            .with_context(|| format!("Failed to create directory: {}", destination.display()))?;
+
+        let mut checkout = git2::build::CheckoutBuilder::new();
+        checkout.target_dir(destination).update_index(false).force();
+
+        repo.checkout_tree(commit_object.as_object(), Some(&mut checkout))
+            .with_context(|| format!("Failed to export commit {} to {}", commit, destination.display()))?;
+
+        debug!("Exported commit {} to {}", commit_object.id(), destination.display());
+        Ok(())
+    }
+
+    /// Returns the origin URL of an existing repository, or None if the path isn't a usable git repository.
+    pub fn get_origin_url(path: &Path) -> Option<String> {
+        let repo = Repository::open(path).ok()?;
+
+        // A repository without a resolvable HEAD is treated as corrupt
+        repo.head().ok()?.peel_to_commit().ok()?;
+
+        let remote = repo.find_remote("origin").ok()?;
+        remote.url().map(|url| url.to_string())
+    }
+
+    pub fn get_head_commit(path: &Path) -> Result<String> {
+        let repo = Repository::open(path)
+            .context("Failed to open repository")?;
+
+        let commit = repo.head()
+            .and_then(|head| head.peel_to_commit())
+            .context("Failed to resolve HEAD commit")?;
+
+        Ok(commit.id().to_string())
+    }
+
+    /// Returns a tag pointing at the checked out commit, if there is one.
+    pub fn get_head_tag(path: &Path) -> Option<String> {
+        let repo = Repository::open(path).ok()?;
+        let head_commit = repo.head().ok()?.peel_to_commit().ok()?.id();
+
+        let mut tags = Vec::new();
+        repo.tag_foreach(|oid, name| {
+            let tag_commit = repo.find_object(oid, None).ok().and_then(|o| o.peel_to_commit().ok());
+            if tag_commit.map(|c| c.id()) == Some(head_commit) {
+                if let Some(tag) = std::str::from_utf8(name).ok().and_then(|n| n.strip_prefix("refs/tags/")) {
+                    tags.push(tag.to_string());
+                }
+            }
+            true
+        }).ok()?;
+
+        tags.sort();
+        tags.pop()
+    }
+
+    pub fn tag_points_at_head(path: &Path, tag: &str) -> bool {
+        let resolve = || -> Option<bool> {
+            let repo = Repository::open(path).ok()?;
+            let head_commit = repo.head().ok()?.peel_to_commit().ok()?.id();
+            let tag_commit = repo.find_reference(&format!("refs/tags/{}", tag)).ok()?.peel_to_commit().ok()?.id();
+            Some(head_commit == tag_commit)
+        };
+
+        resolve().unwrap_or(false)
+    }
+
+    /// Splits a manifest git dependency into its clone URL, optional `?path=` subfolder and `#` revision.
+    pub fn parse_dependency_url(spec: &str) -> GitDependency {
+        let spec = spec.trim().strip_prefix("git+").unwrap_or(spec.trim());
+
+        let (rest, reference) = match spec.split_once('#') {
+            Some((rest, reference)) if !reference.is_empty() => (rest, Some(reference.to_string())),
+            Some((rest, _)) => (rest, None),
+            None => (spec, None),
+        };
+
+        let (url, path) = match rest.split_once("?path=") {
+            Some((url, path)) => {
+                let path = path.trim_matches('/');
+                (url, if path.is_empty() { None } else { Some(path.to_string()) })
+            }
+            None => (rest, None),
+        };
+
+        GitDependency {
+            url: url.to_string(),
+            path,
+            reference,
+        }
+    }
+
+    /// Formats a clone URL the way Unity recognises a git dependency: `git+https://host/owner/repo.git`,
+    /// `git@host:owner/repo.git`, or `git+file:///path/to/repo` for a repository on disk.
+    /// Without the prefix or suffix Unity would resolve the reference as a registry or plain URL dependency.
+    pub fn to_dependency_url(url: &str) -> String {
+        let url = url.trim().trim_start_matches("git+").trim_end_matches('/');
+        let with_suffix = || if url.ends_with(".git") { url.to_string() } else { format!("{}.git", url) };
+
+        match reqwest::Url::parse(url) {
+            Ok(parsed) if parsed.scheme() == "file" => format!("git+{}", url),
+            // Single letter schemes are Windows drive letters, those are paths
+            Ok(parsed) if parsed.scheme().len() > 1 => format!("git+{}", with_suffix()),
+            _ if Self::get_url_host(url).is_some() => with_suffix(),
+            _ => {
+                let path = url.replace('\\', "/");
+                let separator = if path.starts_with('/') { "" } else { "/" };
+                format!("git+file://{}{}", separator, path)
+            }
+        }
+    }
+
+    /// Compares two git URLs ignoring case, trailing slashes and a trailing ".git".
+    pub fn urls_match(a: &str, b: &str) -> bool {
+        fn normalize(url: &str) -> String {
+            let url = url.trim().trim_start_matches("git+").trim_end_matches('/');
+            url.strip_suffix(".git").unwrap_or(url).to_lowercase()
+        }
+
+        normalize(a) == normalize(b)
+    }
+
+    /// Returns the lowercase host of a git URL, for both URL and scp-like (`git@host:owner/repo.git`) syntax.
+    /// Local paths have no host.
+    pub fn get_url_host(url: &str) -> Option<String> {
+        let url = url.trim().trim_start_matches("git+");
+
+        if let Ok(parsed) = reqwest::Url::parse(url) {
+            return parsed.host_str().map(|host| host.to_lowercase());
+        }
+
+        // scp-like syntax has a colon before the first slash
+        let (authority, _) = url.split_once(':')?;
+        if authority.contains('/') || authority.contains('\\') {
+            return None;
+        }
+
+        let host = authority.rsplit('@').next().unwrap_or(authority);
+        if host.is_empty() {
+            None
+        } else {
+            Some(host.to_lowercase())
+        }
+    }
+
+    /// Returns a remote for fetching from origin, with its URL passed through the git URL rules.
+    /// Fetches must name their refspecs since the returned remote has none configured.
+    fn get_origin_remote(repo: &Repository) -> Result<Remote<'_>> {
+        let origin = repo.find_remote("origin")?;
+        let url = origin.url().context("Origin remote has no URL")?;
+        let resolved_url = GitUrlRules::resolve(url)?;
+
+        Ok(repo.remote_anonymous(&resolved_url)?)
+    }
+
+    /// Fetches all branches and tags of origin into an existing repository.
+    pub fn fetch_updates(path: &Path) -> Result<()> {
+        let repo = Repository::open(path)
+            .context("Failed to open repository")?;
+
+        debug!("Fetching updates for {}", path.display());
+
+        let mut remote = Self::get_origin_remote(&repo)
+            .context("Failed to find origin remote")?;
+
+        remote.fetch(&["+refs/heads/*:refs/remotes/origin/*", "+refs/tags/*:refs/tags/*"], None, None)
+            .context("Failed to fetch updates from remote")?;
+
+        debug!("Successfully fetched updates");
+        Ok(())
+    }
+
+    /// Compares HEAD with `origin/<branch>` as of the last fetch.
+    /// Returns how many commits HEAD is ahead and behind, plus the commits it is missing, newest first.
+    pub fn compare_with_remote_branch(path: &Path, branch: &str) -> Result<(usize, usize, Vec<IncomingCommit>)> {
+        let repo = Repository::open(path)
+            .context("Failed to open repository")?;
+
+        let head = repo.head()
+            .and_then(|head| head.peel_to_commit())
+            .context("Failed to resolve checked out commit")?;
+
+        let upstream = repo.find_reference(&format!("refs/remotes/origin/{}", branch))
+            .and_then(|reference| reference.peel_to_commit())
+            .with_context(|| format!("Branch '{}' not found on origin", branch))?;
+
+        let (ahead, behind) = repo.graph_ahead_behind(head.id(), upstream.id())
+            .context("Failed to compare with upstream branch")?;
+
+        let mut revwalk = repo.revwalk()
+            .context("Failed to walk upstream history")?;
+        revwalk.push(upstream.id())?;
+        revwalk.hide(head.id())?;
+
+        let mut incoming = Vec::new();
+        for oid in revwalk {
+            let commit = repo.find_commit(oid?)
+                .context("Failed to read upstream commit")?;
+
+            incoming.push(IncomingCommit {
+                id: commit.id().to_string(),
+                summary: commit.summary().unwrap_or("").to_string(),
+                author: commit.author().name().unwrap_or("unknown").to_string(),
+                time: commit.time().seconds(),
+            });
+        }
+
+        Ok((ahead, behind, incoming))
+    }
+
+    /// Deletes files that aren't tracked by the checked out commit, e.g. files added by a previously applied patch,
+    /// and the folders that leaves empty, like `git clean -d`. Removal is confined to `root`, the Packages folder
+    /// the repository is installed in.
+    pub fn remove_untracked_files(root: &Path, path: &Path) -> Result<()> {
+        let repo = Repository::open(path)
+            .context("Failed to open repository")?;
+
+        let mut status_options = git2::StatusOptions::new();
+        status_options.include_untracked(true).recurse_untracked_dirs(true);
+
+        let statuses = repo.statuses(Some(&mut status_options))
+            .context("Failed to read repository status")?;
+
+        for entry in statuses.iter() {
+            if !entry.status().contains(git2::Status::WT_NEW) {
+                continue;
+            }
+
+            if let Some(file) = entry.path() {
+                let file_path = path.join(file);
+                debug!("Removing untracked file: {}", file_path.display());
+                PathGuard::remove_file(root, &file_path, "remove untracked file")
+                    .with_context(|| format!("Failed to remove untracked file: {}", file_path.display()))?;
+
+                // Unity warns about folders without a .meta file, so empty ones don't stay behind
+                let mut folder = file_path.parent();
+                while let Some(current) = folder.filter(|f| f.starts_with(path) && *f != path) {
+                    let is_empty = fs::read_dir(current).map(|mut entries| entries.next().is_none()).unwrap_or(false);
+                    if !is_empty {
+                        break;
+                    }
+
+                    debug!("Removing empty untracked folder: {}", current.display());
+                    PathGuard::remove_file(root, current, "remove untracked folder")
+                        .with_context(|| format!("Failed to remove untracked folder: {}", current.display()))?;
+                    folder = current.parent();
+                }
+            }
+        }
+
         Ok(())
     }
 
+    /// Lists files that differ from the checked out commit, including untracked files.
+    pub fn get_local_modifications(path: &Path) -> Result<Vec<String>> {
+        let repo = Repository::open(path)
+            .context("Failed to open repository")?;
+
+        let mut status_options = git2::StatusOptions::new();
+        status_options.include_untracked(true).recurse_untracked_dirs(true).include_ignored(false);
+
+        let statuses = repo.statuses(Some(&mut status_options))
+            .context("Failed to read repository status")?;
+
+        let modified = statuses.iter()
+            .filter(|entry| entry.status() != git2::Status::CURRENT)
+            .filter_map(|entry| entry.path().map(|p| p.to_string()))
+            .collect();
+
+        Ok(modified)
+    }
+
     pub fn list_available_tags(path: &Path) -> Result<Vec<String>> {
         let repo = Repository::open(path)
             .context("Failed to open repository")?;
@@ -131,6 +485,62 @@ impl GitOperations {
         Ok(tags)
     }
 
+    /// Lists the tags and branches of a remote repository without cloning or downloading any objects.
+    pub fn list_remote_refs(url: &str) -> Result<Vec<RemoteRef>> {
+        let url = GitUrlRules::resolve(url)?;
+        let url = url.as_str();
+
+        debug!("Listing remote refs for {}", url);
+
+        let mut remote = Remote::create_detached(url)
+            .with_context(|| format!("Invalid remote URL: {}", url))?;
+
+        remote.connect(Direction::Fetch)
+            .with_context(|| format!("Failed to connect to remote {}", url))?;
+
+        let heads = remote.list()
+            .with_context(|| format!("Failed to list refs of remote {}", url))?;
+
+        // Annotated tags are advertised twice, the "^{}" entry carries the commit the tag points at
+        let mut peeled_tags = HashMap::new();
+        for head in heads {
+            if let Some(tag_name) = head.name().strip_suffix("^{}") {
+                peeled_tags.insert(tag_name.to_string(), head.oid().to_string());
+            }
+        }
+
+        let mut refs = Vec::new();
+        for head in heads {
+            let ref_name = head.name();
+            if ref_name.ends_with("^{}") {
+                continue;
+            }
+
+            if let Some(tag) = ref_name.strip_prefix("refs/tags/") {
+                let commit_id = peeled_tags.get(ref_name)
+                    .cloned()
+                    .unwrap_or_else(|| head.oid().to_string());
+                refs.push(RemoteRef {
+                    name: tag.to_string(),
+                    kind: "tag".to_string(),
+                    commit_id,
+                });
+            } else if let Some(branch) = ref_name.strip_prefix("refs/heads/") {
+                refs.push(RemoteRef {
+                    name: branch.to_string(),
+                    kind: "branch".to_string(),
+                    commit_id: head.oid().to_string(),
+                });
+            }
+        }
+
+        remote.disconnect()
+            .context("Failed to disconnect from remote")?;
+
+        debug!("Found {} remote refs for {}", refs.len(), url);
+        Ok(refs)
+    }
+
     pub fn get_current_version_from_package_json(path: &Path) -> Result<String> {
         let package_json_path = path.join("package.json");
         if !package_json_path.exists() {
@@ -151,3 +561,52 @@ impl GitOperations {
         Ok(version)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, TempDir};
+
+    #[test]
+    fn remove_untracked_files_removes_the_folders_it_empties() {
+        let dir = TempDir::new("untracked");
+        let package_path = dir.join("Packages").join("com.test.pkg");
+        let repo = test_support::init_package_repo(&package_path, "com.test.pkg", "1.0.0");
+        fs::create_dir_all(package_path.join("Runtime")).unwrap();
+        fs::write(package_path.join("Runtime").join("a.cs"), "class A {}").unwrap();
+        test_support::commit_all(&repo, "Add A", None);
+
+        fs::write(package_path.join("Runtime").join("b.cs"), "class B {}").unwrap();
+        fs::create_dir_all(package_path.join("Editor").join("Tools")).unwrap();
+        fs::write(package_path.join("Editor").join("Tools").join("c.cs"), "class C {}").unwrap();
+        fs::write(package_path.join("Editor.meta"), "guid: 1").unwrap();
+
+        GitOperations::remove_untracked_files(&dir.join("Packages"), &package_path).unwrap();
+
+        assert!(package_path.join("Runtime").join("a.cs").exists());
+        assert!(!package_path.join("Runtime").join("b.cs").exists());
+        assert!(!package_path.join("Editor").exists());
+        assert!(!package_path.join("Editor.meta").exists());
+        assert!(GitOperations::get_local_modifications(&package_path).unwrap().is_empty());
+    }
+
+    #[test]
+    fn to_dependency_url_uses_forms_unity_resolves_from_git() {
+        let cases = [
+            ("https://github.com/owner/repo", "git+https://github.com/owner/repo.git"),
+            ("https://github.com/owner/repo/", "git+https://github.com/owner/repo.git"),
+            ("https://github.com/owner/repo.git", "git+https://github.com/owner/repo.git"),
+            ("git+https://github.com/owner/repo.git", "git+https://github.com/owner/repo.git"),
+            ("ssh://git@github.com/owner/repo", "git+ssh://git@github.com/owner/repo.git"),
+            ("git@github.com:owner/repo", "git@github.com:owner/repo.git"),
+            ("git@github.com:owner/repo.git", "git@github.com:owner/repo.git"),
+            ("file:///srv/repos/pkg", "git+file:///srv/repos/pkg"),
+            ("/srv/repos/pkg", "git+file:///srv/repos/pkg"),
+            ("C:\\Repos\\pkg", "git+file:///C:/Repos/pkg"),
+        ];
+
+        for (url, expected) in cases {
+            assert_eq!(GitOperations::to_dependency_url(url), expected, "{}", url);
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use tracing::info;
+use crate::config::Config;
+use crate::git_operations::GitOperations;
+use crate::models::GitUrlPolicy;
+
+/// Raised when a git URL isn't allowed by the configured scheme and host rules.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GitUrlPolicyError {
+    pub kind: String, // "git_url_not_allowed"
+    pub message: String,
+    pub url: String,
+    pub resolved_url: String,
+}
+
+impl fmt::Display for GitUrlPolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for GitUrlPolicyError {}
+
+/// Applies the configured rewrites and allowlists to git URLs before anything is cloned or fetched from them.
+pub struct GitUrlRules;
+
+impl GitUrlRules {
+    /// Resolves a URL against the rules in the app config.
+    pub fn resolve(url: &str) -> Result<String> {
+        // The default rules allow everything, so a config that can't be read must not silently drop the studio's rules
+        let config = Config::load().context("Failed to load git URL rules")?;
+        Self::apply(&config.git_url_policy, url)
+    }
+
+    /// Rewrites a URL like git's `insteadOf`, the longest matching prefix wins, then checks the result
+    /// against the allowed schemes and hosts. Rewriting first lets blocked hosts be redirected to an allowed mirror.
+    pub fn apply(policy: &GitUrlPolicy, url: &str) -> Result<String> {
+        let rewrite = policy.rewrites.iter()
+            .filter(|rewrite| !rewrite.from.is_empty() && url.starts_with(&rewrite.from))
+            .max_by_key(|rewrite| rewrite.from.len());
+
+        let resolved = match rewrite {
+            Some(rewrite) => {
+                let resolved = format!("{}{}", rewrite.to, &url[rewrite.from.len()..]);
+                info!("Rewrote git URL {} to {}", url, resolved);
+                resolved
+            }
+            None => url.to_string(),
+        };
+
+        let violation = |message: String| GitUrlPolicyError {
+            kind: "git_url_not_allowed".to_string(),
+            message,
+            url: url.to_string(),
+            resolved_url: resolved.clone(),
+        };
+
+        let scheme = Self::get_scheme(&resolved);
+        if !policy.allowed_schemes.is_empty()
+            && !policy.allowed_schemes.iter().any(|allowed| allowed.eq_ignore_ascii_case(&scheme))
+        {
+            return Err(violation(format!(
+                "Git URL {} uses the {} scheme, allowed schemes are: {}",
+                resolved, scheme, policy.allowed_schemes.join(", ")
+            )).into());
+        }
+
+        // Local paths have no host, whether they are allowed at all is up to the scheme rules
+        if let Some(host) = GitOperations::get_url_host(&resolved) {
+            if !policy.allowed_hosts.is_empty() && !policy.allowed_hosts.iter().any(|allowed| Self::host_matches(allowed, &host)) {
+                return Err(violation(format!(
+                    "Git URL {} points at {}, allowed hosts are: {}",
+                    resolved, host, policy.allowed_hosts.join(", ")
+                )).into());
+            }
+        }
+
+        Ok(resolved)
+    }
+
+    /// Returns the scheme of a git URL: scp-like URLs count as `ssh` and plain paths as `file`.
+    fn get_scheme(url: &str) -> String {
+        let url = url.trim().trim_start_matches("git+");
+
+        match reqwest::Url::parse(url) {
+            // A single letter is a Windows drive, not a scheme
+            Ok(parsed) if parsed.scheme().len() > 1 => parsed.scheme().to_lowercase(),
+            _ => {
+                match url.split_once(':') {
+                    Some((authority, _)) if authority.len() > 1 && !authority.contains('/') && !authority.contains('\\') => {
+                        "ssh".to_string()
+                    }
+                    _ => "file".to_string(),
+                }
+            }
+        }
+    }
+
+    /// Matches a host against an allowed entry, `*.example.com` allows every subdomain of example.com.
+    fn host_matches(allowed: &str, host: &str) -> bool {
+        let allowed = allowed.trim().to_lowercase();
+
+        match allowed.strip_prefix("*.") {
+            Some(domain) => host.ends_with(&format!(".{}", domain)),
+            None => host == allowed,
+        }
+    }
+}
@@ -0,0 +1,539 @@
+use anyhow::{Context, Result};
+use std::collections::HashSet;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::warn;
+use uuid::Uuid;
+use crate::errors::{CommandError, CupmError};
+use crate::models::{HistoryEntry, HistoryFilter, PackageSnapshot};
+use crate::package_manager::PackageManager;
+use crate::provenance::ProvenanceStore;
+use crate::snapshots::SnapshotStore;
+
+const CSV_COLUMNS: &[&str] = &[
+    "id",
+    "timestamp",
+    "project_path",
+    "operation",
+    "package_name",
+    "from_version",
+    "to_version",
+    "commit",
+    "registry_id",
+    "outcome",
+    "error_code",
+    "error_message",
+    "snapshot",
+    "reverts",
+];
+
+// Operations on files outside of installed packages. They are recorded, but undo passes over them
+const NON_PACKAGE_OPERATIONS: &[&str] = &[
+    "repair",
+    "migrate_folders",
+    "delete_orphan",
+    "register_orphan",
+    "import_unitypackage",
+    "remove_unitypackage_import",
+];
+
+/// Append-only record of the package operations run on a project, kept as one JSON object per line
+/// so an entry is never lost to a rewrite of the whole file.
+pub struct HistoryStore;
+
+impl HistoryStore {
+    pub fn get_store_path(project_path: &str) -> PathBuf {
+        Path::new(project_path).join(".cupm").join("history.jsonl")
+    }
+
+    /// Reads all entries, oldest first. Lines that can't be parsed are skipped rather than hiding the rest.
+    pub fn load(project_path: &str) -> Result<Vec<HistoryEntry>> {
+        let store_path = Self::get_store_path(project_path);
+
+        if !store_path.exists() {
+            return Ok(Vec::new());
+        }
+
+        let content = fs::read_to_string(&store_path)
+            .context("Failed to read operation history")?;
+
+        let entries = content.lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .filter_map(|(index, line)| match serde_json::from_str(line) {
+                Ok(entry) => Some(entry),
+                Err(e) => {
+                    warn!("Skipping line {} of {}: {}", index + 1, store_path.display(), e);
+                    None
+                }
+            })
+            .collect();
+
+        Ok(entries)
+    }
+
+    pub fn append(project_path: &str, entry: &HistoryEntry) -> Result<()> {
+        let store_path = Self::get_store_path(project_path);
+
+        if let Some(parent) = store_path.parent() {
+            if !parent.exists() {
+                fs::create_dir_all(parent)
+                    .context("Failed to create project metadata directory")?;
+            }
+        }
+
+        let line = serde_json::to_string(entry)
+            .context("Failed to serialize history entry")?;
+
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&store_path)
+            .context("Failed to open operation history")?;
+
+        writeln!(file, "{}", line)
+            .context("Failed to write operation history")
+    }
+
+    /// Returns the entries matching a filter, newest first.
+    pub fn query(project_path: &str, filter: &HistoryFilter) -> Result<Vec<HistoryEntry>> {
+        let entries = Self::load(project_path)?
+            .into_iter()
+            .rev()
+            .filter(|entry| filter.package_name.is_none() || entry.package_name == filter.package_name)
+            .filter(|entry| filter.operation.is_none() || filter.operation.as_deref() == Some(entry.operation.as_str()))
+            .filter(|entry| filter.outcome.is_none() || filter.outcome.as_deref() == Some(entry.outcome.as_str()))
+            .filter(|entry| entry.timestamp >= filter.since.unwrap_or(0))
+            .filter(|entry| entry.timestamp <= filter.until.unwrap_or(u64::MAX))
+            .take(filter.limit.unwrap_or(usize::MAX))
+            .collect();
+
+        Ok(entries)
+    }
+
+    /// Writes the matching entries to a file as "json" or "csv" and returns how many were written.
+    pub fn export(project_path: &str, filter: &HistoryFilter, output_path: &Path, format: &str) -> Result<usize> {
+        let entries = Self::query(project_path, filter)?;
+
+        let content = match format.to_lowercase().as_str() {
+            "json" => serde_json::to_string_pretty(&entries)
+                .context("Failed to serialize operation history")?,
+            "csv" => Self::to_csv(&entries),
+            _ => return Err(CupmError::UnsupportedFormat {
+                format: format.to_string(),
+                supported: vec!["json".to_string(), "csv".to_string()],
+            }.into()),
+        };
+
+        fs::write(output_path, content)
+            .with_context(|| format!("Failed to write {}", output_path.display()))?;
+
+        Ok(entries.len())
+    }
+
+    /// Starts an entry for an operation, with the version, commit and registry the package is at before it runs.
+    pub fn begin(project_path: &str, operation: &str, package_name: Option<&str>) -> HistoryEntry {
+        let mut entry = HistoryEntry {
+            id: Uuid::new_v4().to_string(),
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+            project_path: project_path.to_string(),
+            operation: operation.to_string(),
+            package_name: package_name.map(|name| name.to_string()),
+            from_version: None,
+            to_version: None,
+            commit: None,
+            registry_id: None,
+            outcome: "pending".to_string(),
+            error_code: None,
+            error_message: None,
+            snapshot: false,
+            reverts: None,
+        };
+
+        if let Some(package_name) = package_name {
+            if let Ok(Some(provenance)) = ProvenanceStore::get(project_path, package_name) {
+                entry.commit = provenance.commit;
+                entry.registry_id = provenance.registry_id;
+            }
+            if let Ok(Some((version, _))) = PackageManager::get_installed_package_info(project_path, package_name) {
+                entry.from_version = Some(version);
+            }
+        }
+
+        entry
+    }
+
+    /// Starts an entry like `begin` and keeps the package's current state so the operation can be undone.
+    /// An operation still runs when its snapshot can't be taken, it just can't be undone afterwards.
+    pub fn begin_with_snapshot(project_path: &str, operation: &str, package_name: &str) -> HistoryEntry {
+        let mut entry = Self::begin(project_path, operation, Some(package_name));
+
+        match PackageManager::snapshot_package(project_path, &entry.id, package_name) {
+            Ok(_) => entry.snapshot = true,
+            Err(e) => {
+                warn!("Could not snapshot {} before {}, it won't be possible to undo: {:#}", package_name, operation, e);
+                let _ = SnapshotStore::remove(project_path, &entry.id);
+            }
+        }
+
+        entry
+    }
+
+    /// Installs from a URL, folder or tarball only learn the package name once the package is read, so nothing
+    /// can be snapshotted up front. A package that is new to the project is undone by removing it again.
+    pub fn snapshot_new_package(entry: &mut HistoryEntry, package_name: &str) {
+        let snapshot = PackageSnapshot {
+            operation_id: entry.id.clone(),
+            package_name: package_name.to_string(),
+            created_at: entry.timestamp,
+            manifest_entry: None,
+            tree_kind: "none".to_string(),
+            install_folder: None,
+            git_url: None,
+            commit: None,
+            link_target: None,
+            provenance: None,
+            link: None,
+        };
+
+        match SnapshotStore::save(&entry.project_path, &snapshot) {
+            Ok(()) => entry.snapshot = true,
+            Err(e) => warn!("Could not snapshot {}, it won't be possible to undo its install: {:#}", package_name, e),
+        }
+    }
+
+    /// Returns the newest operation that hasn't been undone yet.
+    pub fn get_last_undoable(project_path: &str) -> Result<HistoryEntry> {
+        let entries = Self::load(project_path)?;
+
+        let operation = Self::get_outstanding(&entries)
+            .into_iter()
+            .next()
+            .ok_or(CupmError::NothingToUndo)?;
+
+        Self::check_revertible(project_path, &operation)?;
+        Ok(operation)
+    }
+
+    /// Returns an operation and every operation after it that hasn't been undone yet, newest first.
+    /// Fails if any of them can't be undone, since skipping one would leave the project in a state it was never in.
+    pub fn get_operations_since(project_path: &str, operation_id: &str) -> Result<Vec<HistoryEntry>> {
+        let entries = Self::load(project_path)?;
+
+        let position = entries.iter()
+            .position(|entry| entry.id == operation_id)
+            .ok_or_else(|| CupmError::OperationNotFound { operation_id: operation_id.to_string() })?;
+
+        let since: HashSet<&str> = entries[position..].iter().map(|entry| entry.id.as_str()).collect();
+
+        let operations: Vec<HistoryEntry> = Self::get_outstanding(&entries)
+            .into_iter()
+            .filter(|operation| since.contains(operation.id.as_str()))
+            .collect();
+
+        if operations.is_empty() {
+            return Err(CupmError::NothingToUndo.into());
+        }
+
+        for operation in &operations {
+            Self::check_revertible(project_path, operation)?;
+        }
+
+        Ok(operations)
+    }
+
+    /// Successful operations that changed a package and haven't been reverted, newest first.
+    fn get_outstanding(entries: &[HistoryEntry]) -> Vec<HistoryEntry> {
+        let reverted: HashSet<&str> = entries.iter()
+            .filter(|entry| entry.outcome == "success")
+            .filter_map(|entry| entry.reverts.as_deref())
+            .collect();
+
+        entries.iter()
+            .rev()
+            .filter(|entry| !NON_PACKAGE_OPERATIONS.contains(&entry.operation.as_str()))
+            .filter(|entry| entry.outcome == "success" && entry.reverts.is_none() && !reverted.contains(entry.id.as_str()))
+            .cloned()
+            .collect()
+    }
+
+    fn check_revertible(project_path: &str, operation: &HistoryEntry) -> Result<()> {
+        let reason = if !operation.snapshot {
+            "the package's previous state wasn't recorded"
+        } else if !SnapshotStore::exists(project_path, &operation.id) {
+            "its snapshot was removed to save space"
+        } else {
+            return Ok(());
+        };
+
+        Err(CupmError::OperationNotRevertible {
+            operation_id: operation.id.clone(),
+            reason: reason.to_string(),
+        }.into())
+    }
+
+    /// Completes an entry with the outcome of its operation and appends it.
+    /// A history that can't be written is logged rather than failing an operation that already happened.
+    pub fn finish(mut entry: HistoryEntry, package_name: Option<&str>, to_version: Option<&str>, error: Option<&CommandError>) {
+        if let Some(package_name) = package_name {
+            entry.package_name = Some(package_name.to_string());
+        }
+        if let Some(to_version) = to_version {
+            entry.to_version = Some(to_version.to_string());
+        }
+
+        match error {
+            Some(error) => {
+                entry.outcome = "failed".to_string();
+                entry.error_code = Some(error.code.clone());
+                entry.error_message = Some(error.message.clone());
+
+                // A failed operation is never undone, so its snapshot isn't needed
+                if entry.snapshot {
+                    entry.snapshot = false;
+                    if let Err(e) = SnapshotStore::remove(&entry.project_path, &entry.id) {
+                        warn!("Could not remove the snapshot of failed operation {}: {:#}", entry.id, e);
+                    }
+                }
+            }
+            None => {
+                entry.outcome = "success".to_string();
+
+                // Removals leave no provenance behind, the entry keeps the commit and registry that were removed
+                let provenance = entry.package_name.as_deref()
+                    .and_then(|name| ProvenanceStore::get(&entry.project_path, name).ok().flatten());
+                if let Some(provenance) = provenance {
+                    entry.commit = provenance.commit;
+                    entry.registry_id = provenance.registry_id;
+                }
+
+                if let Err(e) = SnapshotStore::prune(&entry.project_path) {
+                    warn!("Could not remove old package snapshots: {:#}", e);
+                }
+            }
+        }
+
+        if let Err(e) = Self::append(&entry.project_path, &entry) {
+            warn!("Could not record {} in the operation history: {:#}", entry.operation, e);
+        }
+    }
+
+    fn to_csv(entries: &[HistoryEntry]) -> String {
+        let mut csv = CSV_COLUMNS.join(",");
+        csv.push_str("\r\n");
+
+        for entry in entries {
+            let fields = [
+                entry.id.clone(),
+                entry.timestamp.to_string(),
+                entry.project_path.clone(),
+                entry.operation.clone(),
+                entry.package_name.clone().unwrap_or_default(),
+                entry.from_version.clone().unwrap_or_default(),
+                entry.to_version.clone().unwrap_or_default(),
+                entry.commit.clone().unwrap_or_default(),
+                entry.registry_id.clone().unwrap_or_default(),
+                entry.outcome.clone(),
+                entry.error_code.clone().unwrap_or_default(),
+                entry.error_message.clone().unwrap_or_default(),
+                entry.snapshot.to_string(),
+                entry.reverts.clone().unwrap_or_default(),
+            ];
+
+            let row: Vec<String> = fields.iter().map(|field| Self::escape_csv_field(field)).collect();
+            csv.push_str(&row.join(","));
+            csv.push_str("\r\n");
+        }
+
+        csv
+    }
+
+    /// Quotes a field when it contains a separator, quote or line break, as RFC 4180 describes.
+    /// Fields that spreadsheets would run as a formula, such as error messages quoting a package's
+    /// own text, are prefixed with `'` so they are shown as text instead.
+    fn escape_csv_field(field: &str) -> String {
+        let field = if field.starts_with(['=', '+', '-', '@']) {
+            format!("'{}", field)
+        } else {
+            field.to_string()
+        };
+
+        if field.contains([',', '"', '\r', '\n']) {
+            format!("\"{}\"", field.replace('"', "\"\""))
+        } else {
+            field
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git_operations::GitOperations;
+    use crate::models::Package;
+    use crate::test_support::{self, TempDir};
+
+    /// The package in `remote` as a registry would list it at `tag`.
+    fn tagged_package(remote: &Path, tag: &str) -> Package {
+        Package {
+            git_url: remote.to_string_lossy().to_string(),
+            git_tag: Some(tag.to_string()),
+            ..PackageManager::read_package_json(remote, "").unwrap()
+        }
+    }
+
+    /// Installs or updates a package the way the install and update commands do and returns the operation id.
+    fn record_install(project_path: &str, operation: &str, package: &Package) -> String {
+        let entry = HistoryStore::begin_with_snapshot(project_path, operation, &package.name);
+        let id = entry.id.clone();
+
+        let result = match operation {
+            "update" => PackageManager::update_package(project_path, package),
+            _ => PackageManager::install_package(project_path, package),
+        }.unwrap();
+
+        HistoryStore::finish(entry, Some(&result.package_name), Some(&result.version), None);
+        id
+    }
+
+    /// Reverts operations newest first the way the undo and rollback commands do.
+    fn revert(project_path: &str, operations: Vec<HistoryEntry>) -> Result<Vec<HistoryEntry>> {
+        for operation in &operations {
+            let mut entry = HistoryStore::begin(project_path, "undo", operation.package_name.as_deref());
+            entry.reverts = Some(operation.id.clone());
+            let version = PackageManager::revert_operation(project_path, operation)?;
+            HistoryStore::finish(entry, None, version.as_deref(), None);
+        }
+
+        Ok(operations)
+    }
+
+    fn undo_last(project_path: &str) -> Result<HistoryEntry> {
+        let operation = HistoryStore::get_last_undoable(project_path)?;
+        Ok(revert(project_path, vec![operation])?.remove(0))
+    }
+
+    fn installed_version(project_path: &str) -> Option<String> {
+        PackageManager::get_installed_package_info(project_path, "com.test.pkg").unwrap().map(|(version, _)| version)
+    }
+
+    #[test]
+    fn escape_csv_field_quotes_separators() {
+        assert_eq!(HistoryStore::escape_csv_field("plain"), "plain");
+        assert_eq!(HistoryStore::escape_csv_field("a, b"), "\"a, b\"");
+        assert_eq!(HistoryStore::escape_csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(HistoryStore::escape_csv_field("line\nbreak"), "\"line\nbreak\"");
+    }
+
+    #[test]
+    fn escape_csv_field_neutralizes_formulas() {
+        assert_eq!(HistoryStore::escape_csv_field("=HYPERLINK(\"x\")"), "\"'=HYPERLINK(\"\"x\"\")\"");
+        assert_eq!(HistoryStore::escape_csv_field("+1"), "'+1");
+        assert_eq!(HistoryStore::escape_csv_field("-cmd"), "'-cmd");
+        assert_eq!(HistoryStore::escape_csv_field("@SUM(A1)"), "'@SUM(A1)");
+        assert_eq!(HistoryStore::escape_csv_field("a=b"), "a=b");
+    }
+
+    #[test]
+    fn undo_passes_over_project_wide_operations() {
+        let dir = TempDir::new("history-undo");
+        let remote = dir.join("remote");
+        let repo = test_support::init_package_repo(&remote, "com.test.pkg", "1.0.0");
+        let project_path = test_support::create_project(&dir.join("project"));
+
+        for operation in ["repair", "migrate_folders"] {
+            HistoryStore::finish(HistoryStore::begin(&project_path, operation, None), None, None, None);
+        }
+
+        record_install(&project_path, "install", &tagged_package(&remote, "v1.0.0"));
+
+        HistoryStore::finish(HistoryStore::begin(&project_path, "register_orphan", None), Some("com.test.orphan"), None, None);
+
+        test_support::write_package_json(&remote, "com.test.pkg", "2.0.0");
+        test_support::commit_all(&repo, "Release 2.0.0", Some("v2.0.0"));
+        record_install(&project_path, "update", &tagged_package(&remote, "v2.0.0"));
+
+        assert_eq!(undo_last(&project_path).unwrap().operation, "update");
+        assert_eq!(installed_version(&project_path).as_deref(), Some("1.0.0"));
+
+        assert_eq!(undo_last(&project_path).unwrap().operation, "install");
+        assert_eq!(installed_version(&project_path), None);
+
+        let error = undo_last(&project_path).unwrap_err();
+        assert!(matches!(error.downcast_ref::<CupmError>(), Some(CupmError::NothingToUndo)));
+    }
+
+    #[test]
+    fn undo_brings_back_a_removed_package() {
+        let dir = TempDir::new("history-remove");
+        let remote = dir.join("remote");
+        test_support::init_package_repo(&remote, "com.test.pkg", "1.0.0");
+        let project_path = test_support::create_project(&dir.join("project"));
+        record_install(&project_path, "install", &tagged_package(&remote, "v1.0.0"));
+        let package_path = PackageManager::get_package_repository_path(&project_path, "com.test.pkg").unwrap();
+        let installed_commit = GitOperations::get_head_commit(&package_path).unwrap();
+
+        let entry = HistoryStore::begin_with_snapshot(&project_path, "remove", "com.test.pkg");
+        PackageManager::remove_package(&project_path, "com.test.pkg").unwrap();
+        HistoryStore::finish(entry, None, None, None);
+        assert_eq!(installed_version(&project_path), None);
+
+        assert_eq!(undo_last(&project_path).unwrap().operation, "remove");
+
+        assert_eq!(installed_version(&project_path).as_deref(), Some("1.0.0"));
+        assert_eq!(GitOperations::get_head_commit(&package_path).unwrap(), installed_commit);
+        assert!(ProvenanceStore::get(&project_path, "com.test.pkg").unwrap().is_some());
+    }
+
+    #[test]
+    fn rollback_reverts_an_operation_and_everything_after_it() {
+        let dir = TempDir::new("history-rollback");
+        let remote = dir.join("remote");
+        let repo = test_support::init_package_repo(&remote, "com.test.pkg", "1.0.0");
+        let project_path = test_support::create_project(&dir.join("project"));
+
+        let install_id = record_install(&project_path, "install", &tagged_package(&remote, "v1.0.0"));
+        let mut update_ids = Vec::new();
+        for version in ["2.0.0", "3.0.0"] {
+            test_support::write_package_json(&remote, "com.test.pkg", version);
+            test_support::commit_all(&repo, &format!("Release {}", version), Some(&format!("v{}", version)));
+            update_ids.push(record_install(&project_path, "update", &tagged_package(&remote, &format!("v{}", version))));
+        }
+        assert_eq!(installed_version(&project_path).as_deref(), Some("3.0.0"));
+
+        let operations = HistoryStore::get_operations_since(&project_path, &update_ids[0]).unwrap();
+        let reverted: Vec<String> = revert(&project_path, operations).unwrap().into_iter().map(|operation| operation.id).collect();
+
+        assert_eq!(reverted, vec![update_ids[1].clone(), update_ids[0].clone()]);
+        assert_eq!(installed_version(&project_path).as_deref(), Some("1.0.0"));
+        assert_eq!(HistoryStore::get_last_undoable(&project_path).unwrap().id, install_id);
+    }
+
+    #[test]
+    fn undo_refuses_a_package_edited_after_the_operation() {
+        let dir = TempDir::new("history-edited");
+        let remote = dir.join("remote");
+        let repo = test_support::init_package_repo(&remote, "com.test.pkg", "1.0.0");
+        let project_path = test_support::create_project(&dir.join("project"));
+        record_install(&project_path, "install", &tagged_package(&remote, "v1.0.0"));
+
+        test_support::write_package_json(&remote, "com.test.pkg", "2.0.0");
+        test_support::commit_all(&repo, "Release 2.0.0", Some("v2.0.0"));
+        let update_id = record_install(&project_path, "update", &tagged_package(&remote, "v2.0.0"));
+
+        let package_path = PackageManager::get_package_repository_path(&project_path, "com.test.pkg").unwrap();
+        fs::write(package_path.join("Local.cs"), "class Local {}").unwrap();
+
+        let error = undo_last(&project_path).unwrap_err();
+        assert!(matches!(error.downcast_ref::<CupmError>(), Some(CupmError::LocalChanges { .. })));
+        assert!(package_path.join("Local.cs").exists());
+        assert_eq!(installed_version(&project_path).as_deref(), Some("2.0.0"));
+
+        // Once the edit is gone the operation can be undone after all
+        fs::remove_file(package_path.join("Local.cs")).unwrap();
+        assert_eq!(undo_last(&project_path).unwrap().id, update_id);
+        assert_eq!(installed_version(&project_path).as_deref(), Some("1.0.0"));
+    }
+}
@@ -0,0 +1,301 @@
+use anyhow::{Context, Result};
+use git2::{ObjectType, Repository, TreeWalkMode, TreeWalkResult};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use tracing::info;
+
+const HASH_PREFIX: &str = "sha256:";
+
+/// Raised when a package's contents don't match the hash its registry declared.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IntegrityError {
+    pub kind: String, // "content_hash_mismatch"
+    pub message: String,
+    pub package_name: String,
+    pub expected_hash: String,
+    pub actual_hash: String,
+}
+
+impl fmt::Display for IntegrityError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for IntegrityError {}
+
+/// Hashes package trees so what ends up in Packages can be checked independently of git.
+pub struct IntegrityChecker;
+
+impl IntegrityChecker {
+    /// Computes a `sha256:` hash over the relative paths and bytes of every file in a package, skipping `.git`.
+    /// Files are visited in path order with `/` separators and text files are hashed with LF line endings,
+    /// so the hash is the same on every platform whatever `core.autocrlf` the checkout used.
+    pub fn hash_tree(root: &Path) -> Result<String> {
+        let mut files = Vec::new();
+        Self::collect_files(root, root, &mut files)?;
+        files.sort();
+
+        let mut hasher = Sha256::new();
+
+        for relative in files {
+            let path = root.join(&relative);
+            let metadata = fs::symlink_metadata(&path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+
+            // Symlinks are hashed by where they point, not by what they point at
+            let content = if metadata.file_type().is_symlink() {
+                fs::read_link(&path)
+                    .with_context(|| format!("Failed to read link {}", path.display()))?
+                    .to_string_lossy()
+                    .replace('\\', "/")
+                    .into_bytes()
+            } else {
+                fs::read(&path)
+                    .with_context(|| format!("Failed to read {}", path.display()))?
+            };
+
+            Self::hash_file(&mut hasher, &relative, &content);
+        }
+
+        Ok(Self::finish(hasher))
+    }
+
+    /// Hashes the files of a commit the way `hash_tree` hashes a checkout of it. Only the committed blobs are read,
+    /// so edits and applied patches in the working tree don't change the result.
+    pub fn hash_commit(repository_path: &Path, commit: &str) -> Result<String> {
+        let repo = Repository::open(repository_path)
+            .with_context(|| format!("Failed to open repository {}", repository_path.display()))?;
+
+        let tree = repo.revparse_single(commit)
+            .and_then(|object| object.peel_to_tree())
+            .with_context(|| format!("Failed to find the files of commit {}", commit))?;
+
+        // Submodules are commits rather than blobs, their files aren't part of this repository
+        let mut files = Vec::new();
+        tree.walk(TreeWalkMode::PreOrder, |folder, entry| {
+            if entry.kind() == Some(ObjectType::Blob) {
+                files.push((format!("{}{}", folder, String::from_utf8_lossy(entry.name_bytes())), entry.id()));
+            }
+            TreeWalkResult::Ok
+        }).context("Failed to list the files of a commit")?;
+        files.sort();
+
+        let mut hasher = Sha256::new();
+
+        for (relative, id) in files {
+            let blob = repo.find_blob(id)
+                .with_context(|| format!("Failed to read {} from commit {}", relative, commit))?;
+            Self::hash_file(&mut hasher, &relative, blob.content());
+        }
+
+        Ok(Self::finish(hasher))
+    }
+
+    /// Compares two hashes, accepting either with or without the `sha256:` prefix and in either case.
+    pub fn hashes_match(expected: &str, actual: &str) -> bool {
+        match (Self::normalize_hash(expected), Self::normalize_hash(actual)) {
+            (Ok(expected), Ok(actual)) => expected == actual,
+            _ => false,
+        }
+    }
+
+    fn hash_file(hasher: &mut Sha256, relative: &str, content: &[u8]) {
+        let content = Self::normalize_line_endings(content);
+
+        // Length prefixes keep a path and the start of its contents from running into each other
+        hasher.update((relative.len() as u64).to_le_bytes());
+        hasher.update(relative.as_bytes());
+        hasher.update((content.len() as u64).to_le_bytes());
+        hasher.update(&content);
+    }
+
+    /// Turns CRLF into LF in text files. A file counts as binary the way git decides it,
+    /// when its first 8000 bytes contain a NUL byte, and binary files are left as they are.
+    fn normalize_line_endings(content: &[u8]) -> Cow<'_, [u8]> {
+        let is_binary = content.iter().take(8000).any(|&byte| byte == 0);
+        if is_binary || !content.windows(2).any(|pair| pair == b"\r\n") {
+            return Cow::Borrowed(content);
+        }
+
+        let mut normalized = Vec::with_capacity(content.len());
+        for (index, &byte) in content.iter().enumerate() {
+            if byte == b'\r' && content.get(index + 1) == Some(&b'\n') {
+                continue;
+            }
+            normalized.push(byte);
+        }
+
+        Cow::Owned(normalized)
+    }
+
+    fn finish(hasher: Sha256) -> String {
+        let digest = hasher.finalize();
+        let hex: String = digest.iter().map(|byte| format!("{:02x}", byte)).collect();
+
+        format!("{}{}", HASH_PREFIX, hex)
+    }
+
+    /// Hashes a checked out package and fails if it doesn't match the expected hash.
+    pub fn verify(package_name: &str, package_path: &Path, expected_hash: &str) -> Result<String> {
+        let expected = Self::normalize_hash(expected_hash)
+            .with_context(|| format!("Invalid content hash for {}: {}", package_name, expected_hash))?;
+
+        let actual_hash = Self::hash_tree(package_path)?;
+
+        if actual_hash != expected {
+            return Err(IntegrityError {
+                kind: "content_hash_mismatch".to_string(),
+                message: format!(
+                    "Contents of {} don't match the expected hash (expected {}, found {})",
+                    package_name, expected, actual_hash
+                ),
+                package_name: package_name.to_string(),
+                expected_hash: expected,
+                actual_hash,
+            }.into());
+        }
+
+        info!("Verified contents of {} ({})", package_name, actual_hash);
+        Ok(actual_hash)
+    }
+
+    /// Accepts hashes with or without the `sha256:` prefix and in either case.
+    fn normalize_hash(hash: &str) -> Result<String> {
+        let hash = hash.trim().to_lowercase();
+        let hex = hash.strip_prefix(HASH_PREFIX).unwrap_or(&hash);
+
+        if hex.len() != 64 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(anyhow::anyhow!("Expected a sha256 hash of 64 hex characters"));
+        }
+
+        Ok(format!("{}{}", HASH_PREFIX, hex))
+    }
+
+    fn collect_files(root: &Path, dir: &Path, files: &mut Vec<String>) -> Result<()> {
+        let entries = fs::read_dir(dir)
+            .with_context(|| format!("Failed to read {}", dir.display()))?;
+
+        for entry in entries {
+            let entry = entry.with_context(|| format!("Failed to read {}", dir.display()))?;
+            let path = entry.path();
+
+            // Submodules have a .git file rather than a folder, both are left out
+            if entry.file_name() == ".git" {
+                continue;
+            }
+
+            let file_type = entry.file_type()
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+
+            if file_type.is_dir() {
+                Self::collect_files(root, &path, files)?;
+            } else {
+                let relative = path.strip_prefix(root).unwrap_or(&path).to_string_lossy().replace('\\', "/");
+                files.push(relative);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, TempDir};
+
+    fn temp_package(name: &str) -> TempDir {
+        let root = TempDir::new(&format!("hash-{}", name));
+        fs::create_dir_all(root.join("Runtime")).unwrap();
+        root
+    }
+
+    #[test]
+    fn hash_tree_is_deterministic() {
+        // Same contents written in a different order give the same hash
+        let first = temp_package("first");
+        fs::write(first.join("package.json"), "{}").unwrap();
+        fs::write(first.join("Runtime").join("a.cs"), "class A {}").unwrap();
+        fs::write(first.join("Runtime").join("b.cs"), "class B {}").unwrap();
+
+        let second = temp_package("second");
+        fs::write(second.join("Runtime").join("b.cs"), "class B {}").unwrap();
+        fs::write(second.join("Runtime").join("a.cs"), "class A {}").unwrap();
+        fs::write(second.join("package.json"), "{}").unwrap();
+
+        let hash = IntegrityChecker::hash_tree(first.path()).unwrap();
+        assert!(hash.starts_with(HASH_PREFIX));
+        assert_eq!(hash, IntegrityChecker::hash_tree(first.path()).unwrap());
+        assert_eq!(hash, IntegrityChecker::hash_tree(second.path()).unwrap());
+    }
+
+    #[test]
+    fn hash_tree_ignores_git_metadata() {
+        let package = temp_package("git");
+        fs::write(package.join("package.json"), "{}").unwrap();
+        let hash = IntegrityChecker::hash_tree(package.path()).unwrap();
+
+        fs::create_dir_all(package.join(".git")).unwrap();
+        fs::write(package.join(".git").join("HEAD"), "ref: refs/heads/main").unwrap();
+        assert_eq!(hash, IntegrityChecker::hash_tree(package.path()).unwrap());
+    }
+
+    #[test]
+    fn hash_tree_changes_with_paths_and_contents() {
+        let package = temp_package("changes");
+        fs::write(package.join("package.json"), "{}").unwrap();
+        let hash = IntegrityChecker::hash_tree(package.path()).unwrap();
+
+        fs::write(package.join("package.json"), "{ }").unwrap();
+        let edited = IntegrityChecker::hash_tree(package.path()).unwrap();
+        assert_ne!(hash, edited);
+
+        fs::rename(package.join("package.json"), package.join("Runtime").join("package.json")).unwrap();
+        assert_ne!(edited, IntegrityChecker::hash_tree(package.path()).unwrap());
+    }
+
+    #[test]
+    fn hash_tree_ignores_line_endings_of_text_files() {
+        let lf = temp_package("lf");
+        fs::write(lf.join("Runtime").join("a.cs"), "class A\n{\n}\n").unwrap();
+        let crlf = temp_package("crlf");
+        fs::write(crlf.join("Runtime").join("a.cs"), "class A\r\n{\r\n}\r\n").unwrap();
+
+        assert_eq!(IntegrityChecker::hash_tree(lf.path()).unwrap(), IntegrityChecker::hash_tree(crlf.path()).unwrap());
+
+        // Binary files are hashed byte for byte
+        fs::write(lf.join("image.png"), b"\x89PNG\0\n").unwrap();
+        fs::write(crlf.join("image.png"), b"\x89PNG\0\r\n").unwrap();
+        assert_ne!(IntegrityChecker::hash_tree(lf.path()).unwrap(), IntegrityChecker::hash_tree(crlf.path()).unwrap());
+    }
+
+    #[test]
+    fn hash_commit_matches_a_clean_checkout_and_ignores_later_edits() {
+        let dir = TempDir::new("hash-commit");
+        let package_path = dir.join("package");
+        let repo = test_support::init_package_repo(&package_path, "com.test.pkg", "1.0.0");
+        fs::create_dir_all(package_path.join("Runtime")).unwrap();
+        fs::write(package_path.join("Runtime").join("a.cs"), "class A {}\n").unwrap();
+        let commit = test_support::commit_all(&repo, "Add A", None);
+
+        let checkout_hash = IntegrityChecker::hash_tree(&package_path).unwrap();
+        assert_eq!(IntegrityChecker::hash_commit(&package_path, &commit).unwrap(), checkout_hash);
+
+        fs::write(package_path.join("Runtime").join("a.cs"), "class A { int x; }\n").unwrap();
+        assert_ne!(IntegrityChecker::hash_tree(&package_path).unwrap(), checkout_hash);
+        assert_eq!(IntegrityChecker::hash_commit(&package_path, &commit).unwrap(), checkout_hash);
+    }
+
+    #[test]
+    fn hashes_match_ignores_prefix_and_case() {
+        let hex = "ab".repeat(32);
+        assert!(IntegrityChecker::hashes_match(&format!("sha256:{}", hex), &hex.to_uppercase()));
+        assert!(!IntegrityChecker::hashes_match(&hex, &"cd".repeat(32)));
+        assert!(!IntegrityChecker::hashes_match("not a hash", "not a hash"));
+    }
+}
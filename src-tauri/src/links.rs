@@ -0,0 +1,18 @@
+use crate::models::PackageLink;
+use crate::package_store::{PackageStore, PackageStoreEntry};
+
+/// Project-local record of packages that are linked to a developer's working copy.
+pub type LinkStore = PackageStore<PackageLink>;
+
+impl PackageStoreEntry for PackageLink {
+    const FILE_NAME: &'static str = "links.json";
+    const DESCRIPTION: &'static str = "package links";
+
+    fn package_name(&self) -> &str {
+        &self.package_name
+    }
+
+    fn set_package_name(&mut self, package_name: &str) {
+        self.package_name = package_name.to_string();
+    }
+}
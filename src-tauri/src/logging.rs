@@ -0,0 +1,238 @@
+use anyhow::{Context, Result};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde_json::{Map, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{warn, Level};
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::Layer;
+use crate::config::Config;
+use crate::models::LogEntry;
+
+const LOG_FILE_PREFIX: &str = "cupm";
+const LOG_FILE_SUFFIX: &str = "log";
+const MAX_LOG_FILES: usize = 14; // Two weeks of daily files
+
+// Project metadata that helps reproduce a failed install, relative to the project folder
+const PROJECT_SUPPORT_FILES: &[&str] = &[
+    "Packages/manifest.json",
+    "Packages/packages-lock.json",
+    ".cupm/installed.json",
+    ".cupm/links.json",
+];
+
+/// Writes diagnostics as JSON lines to daily log files, since the Windows release build has no console.
+/// Every command runs in a span named after it, with the project it works on, so entries can be filtered later.
+pub struct Logging;
+
+impl Logging {
+    pub fn get_log_dir() -> Result<PathBuf> {
+        let log_dir = dirs::data_local_dir()
+            .context("Failed to get app data directory")?
+            .join("cvr-unity-package-manager")
+            .join("logs");
+
+        if !log_dir.exists() {
+            fs::create_dir_all(&log_dir)
+                .context("Failed to create log directory")?;
+        }
+
+        Ok(log_dir)
+    }
+
+    /// Installs the global subscriber. The returned guard flushes the log file when dropped,
+    /// so it has to live until the app exits.
+    pub fn init() -> Option<WorkerGuard> {
+        // Dependencies such as reqwest and hyper log their own internals at debug level
+        let targets = Targets::new()
+            .with_target(env!("CARGO_CRATE_NAME"), Level::DEBUG)
+            .with_default(Level::WARN);
+
+        let console_layer = tracing_subscriber::fmt::layer()
+            .with_target(false)
+            .with_filter(targets.clone().with_target(env!("CARGO_CRATE_NAME"), Level::INFO));
+
+        let appender = Self::get_log_dir().and_then(|log_dir| {
+            RollingFileAppender::builder()
+                .rotation(Rotation::DAILY)
+                .filename_prefix(LOG_FILE_PREFIX)
+                .filename_suffix(LOG_FILE_SUFFIX)
+                .max_log_files(MAX_LOG_FILES)
+                .build(&log_dir)
+                .with_context(|| format!("Failed to open log files in {}", log_dir.display()))
+        });
+
+        match appender {
+            Ok(appender) => {
+                let (writer, guard) = tracing_appender::non_blocking(appender);
+
+                let file_layer = tracing_subscriber::fmt::layer()
+                    .json()
+                    .with_current_span(false)
+                    .with_span_list(true)
+                    .with_writer(writer)
+                    .with_filter(targets);
+
+                tracing_subscriber::registry().with(console_layer).with(file_layer).init();
+                Some(guard)
+            }
+            Err(e) => {
+                tracing_subscriber::registry().with(console_layer).init();
+                warn!("Logging to the console only: {:#}", e);
+                None
+            }
+        }
+    }
+
+    /// Returns the most recent log entries, oldest first, optionally limited to one operation, project or minimum level.
+    pub fn read_entries(
+        operation: Option<&str>,
+        project_path: Option<&str>,
+        min_level: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<LogEntry>> {
+        let min_level = match min_level {
+            Some(level) => Some(level.parse::<Level>().map_err(|_| anyhow::anyhow!("Unknown log level: {}", level))?),
+            None => None,
+        };
+
+        let mut entries: Vec<LogEntry> = Vec::new();
+
+        for log_file in Self::list_log_files()? {
+            let content = fs::read_to_string(&log_file)
+                .with_context(|| format!("Failed to read {}", log_file.display()))?;
+
+            entries.extend(
+                content.lines()
+                    .filter_map(Self::parse_entry)
+                    .filter(|entry| operation.is_none() || entry.operation.as_deref() == operation)
+                    .filter(|entry| project_path.is_none() || entry.project.as_deref() == project_path)
+                    // Levels compare by verbosity, so more severe levels are "less than" the minimum
+                    .filter(|entry| match (&min_level, entry.level.parse::<Level>()) {
+                        (Some(min_level), Ok(level)) => level <= *min_level,
+                        _ => true,
+                    }),
+            );
+        }
+
+        let skip = entries.len().saturating_sub(limit);
+        Ok(entries.split_off(skip))
+    }
+
+    /// Writes a .tar.gz with the log files, the app config and, when given, a project's package metadata.
+    pub fn export_support_bundle(output_path: &Path, project_path: Option<&str>) -> Result<PathBuf> {
+        let file = fs::File::create(output_path)
+            .with_context(|| format!("Failed to create {}", output_path.display()))?;
+
+        let mut builder = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+
+        for log_file in Self::list_log_files()? {
+            if let Some(file_name) = log_file.file_name().and_then(|n| n.to_str()) {
+                builder.append_path_with_name(&log_file, format!("logs/{}", file_name))
+                    .with_context(|| format!("Failed to add {} to support bundle", log_file.display()))?;
+            }
+        }
+
+        let config_path = Config::get_config_path()?;
+        if config_path.exists() {
+            builder.append_path_with_name(&config_path, "config.json")
+                .context("Failed to add config to support bundle")?;
+        }
+
+        if let Some(project_path) = project_path {
+            for relative in PROJECT_SUPPORT_FILES {
+                let path = Path::new(project_path).join(relative);
+                if path.exists() {
+                    builder.append_path_with_name(&path, format!("project/{}", relative))
+                        .with_context(|| format!("Failed to add {} to support bundle", path.display()))?;
+                }
+            }
+        }
+
+        let system = serde_json::json!({
+            "app_version": env!("CARGO_PKG_VERSION"),
+            "os": std::env::consts::OS,
+            "arch": std::env::consts::ARCH,
+            "created_at": SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+            "project_path": project_path,
+        });
+        let system = serde_json::to_vec_pretty(&system)
+            .context("Failed to serialize system information")?;
+
+        let mut header = tar::Header::new_gnu();
+        header.set_size(system.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append_data(&mut header, "system.json", system.as_slice())
+            .context("Failed to add system information to support bundle")?;
+
+        builder.into_inner()
+            .and_then(|encoder| encoder.finish())
+            .with_context(|| format!("Failed to finish writing {}", output_path.display()))?;
+
+        Ok(output_path.to_path_buf())
+    }
+
+    /// Log files oldest first; the date in their names sorts chronologically.
+    fn list_log_files() -> Result<Vec<PathBuf>> {
+        let log_dir = Self::get_log_dir()?;
+
+        let mut log_files: Vec<PathBuf> = fs::read_dir(&log_dir)
+            .with_context(|| format!("Failed to read {}", log_dir.display()))?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| {
+                path.file_name()
+                    .and_then(|n| n.to_str())
+                    .map(|name| name.starts_with(LOG_FILE_PREFIX) && name.ends_with(LOG_FILE_SUFFIX))
+                    .unwrap_or(false)
+            })
+            .collect();
+        log_files.sort();
+
+        Ok(log_files)
+    }
+
+    /// Reads one line written by the JSON layer. The outermost span is the command that was running.
+    fn parse_entry(line: &str) -> Option<LogEntry> {
+        let mut value: Map<String, Value> = serde_json::from_str(line).ok()?;
+
+        let mut fields = match value.remove("fields") {
+            Some(Value::Object(fields)) => fields,
+            _ => Map::new(),
+        };
+        let message = fields.remove("message")
+            .and_then(|message| message.as_str().map(|m| m.to_string()))
+            .unwrap_or_default();
+
+        let spans = match value.remove("spans") {
+            Some(Value::Array(spans)) => spans,
+            _ => Vec::new(),
+        };
+        let operation = spans.first()
+            .and_then(|span| span.get("name"))
+            .and_then(|name| name.as_str())
+            .map(|name| name.to_string());
+        let project = spans.iter()
+            .find_map(|span| span.get("project"))
+            .and_then(|project| project.as_str())
+            .map(|project| project.to_string());
+
+        let get_str = |key: &str| value.get(key).and_then(|v| v.as_str()).unwrap_or_default().to_string();
+
+        Some(LogEntry {
+            timestamp: get_str("timestamp"),
+            level: get_str("level"),
+            target: get_str("target"),
+            message,
+            operation,
+            project,
+            fields,
+        })
+    }
+}
@@ -5,31 +5,96 @@ mod commands;
 mod models;
 mod package_manager;
 mod git_operations;
+mod git_url_rules;
 mod config;
+mod errors;
+mod history;
+mod advisories;
+mod integrity;
+mod links;
+mod logging;
+mod package_store;
+mod patches;
+mod provenance;
+mod registry;
+mod registry_trust;
+mod safety_scan;
+mod security;
+mod snapshots;
+mod unity_package;
+#[cfg(test)]
+mod test_support;
 
 use commands::*;
+use logging::Logging;
+use tauri::RunEvent;
 
 fn main() {
+    // Flushes buffered log lines when dropped. The event loop never returns from `run`,
+    // so it is dropped explicitly once the app exits
+    let mut log_guard = Logging::init();
+
     tauri::Builder::default()
         .invoke_handler(tauri::generate_handler![
             get_config,
             save_config,
             add_registry,
             remove_registry,
+            confirm_registry_changes,
             add_project_path,
             remove_project_path,
             get_packages_from_registry,
+            get_remote_versions,
             get_installed_packages,
             install_package,
+            install_from_git_url,
+            install_from_local_folder,
+            install_from_tarball,
+            find_adoptable_git_dependencies,
+            adopt_git_dependency,
+            convert_to_git_dependency,
+            link_package,
+            unlink_package,
+            get_branch_status,
+            fast_forward_package,
+            acknowledge_safety_report,
+            audit_project,
+            verify_package,
+            diagnose_project,
+            repair_project,
+            find_orphaned_packages,
+            delete_orphaned_package,
+            register_orphaned_package,
+            migrate_package_folders,
             update_package,
             remove_package,
             get_project_info,
+            adopt_unmanaged_packages,
             get_system_theme,
             set_theme,
             show_open_dialog,
             check_package_conflicts,
-            get_installed_package_info
+            get_installed_package_info,
+            create_package_patch,
+            apply_package_patch,
+            list_package_patches,
+            remove_package_patch,
+            import_unity_package,
+            export_unity_package,
+            list_unity_package_imports,
+            remove_unity_package_import,
+            get_log_entries,
+            export_support_bundle,
+            get_operation_history,
+            export_operation_history,
+            undo_last_operation,
+            rollback_to
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(move |_app_handle, event| {
+            if let RunEvent::Exit = event {
+                drop(log_guard.take());
+            }
+        });
 }
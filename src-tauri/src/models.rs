@@ -1,5 +1,5 @@
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AppConfig {
@@ -7,6 +7,12 @@ pub struct AppConfig {
     pub project_paths: Vec<String>,
     pub selected_project_path: Option<String>,
     pub theme: String, // "dark", "light", "auto"
+    #[serde(default)]
+    pub safety_policy: SafetyPolicy,
+    #[serde(default)]
+    pub advisory_policy: AdvisoryPolicy,
+    #[serde(default)]
+    pub git_url_policy: GitUrlPolicy,
 }
 
 impl Default for AppConfig {
@@ -16,10 +22,29 @@ impl Default for AppConfig {
             project_paths: Vec::new(),
             selected_project_path: None,
             theme: "dark".to_string(), // Default to dark mode
+            safety_policy: SafetyPolicy::default(),
+            advisory_policy: AdvisoryPolicy::default(),
+            git_url_policy: GitUrlPolicy::default(),
         }
     }
 }
 
+/// Rules applied to every git URL before it is cloned or fetched. Empty lists allow everything.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GitUrlPolicy {
+    pub allowed_schemes: Vec<String>, // e.g. "https", "ssh", "file"
+    pub allowed_hosts: Vec<String>, // Exact hosts, or "*.example.com" for subdomains
+    pub rewrites: Vec<GitUrlRewrite>,
+}
+
+/// Replaces a URL prefix like git's `url.<to>.insteadOf <from>`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GitUrlRewrite {
+    pub from: String,
+    pub to: String,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Registry {
     pub id: String,
@@ -37,6 +62,9 @@ pub struct PackageVersion {
     pub is_prerelease: bool,
     pub unity_version: Option<String>,
     pub dependencies: Option<HashMap<String, String>>,
+    // Hash of the package tree at this version, see IntegrityChecker::hash_tree
+    #[serde(default)]
+    pub content_hash: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -59,6 +87,12 @@ pub struct Package {
     pub unity_version: Option<String>,
     #[serde(default)]
     pub is_prerelease: bool,
+    // Set when the package was fetched from a configured registry
+    #[serde(default)]
+    pub registry_id: Option<String>,
+    // Hash the registry declared for this version, checked before install
+    #[serde(default)]
+    pub content_hash: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -66,6 +100,36 @@ pub struct PackageRegistry {
     pub name: String,
     pub description: Option<String>,
     pub packages: Vec<Package>,
+    // Advisory feed for this registry, absolute or relative to the registry URL
+    #[serde(default)]
+    pub advisories_url: Option<String>,
+    // Public key the registry publishes to identify its owner
+    #[serde(default)]
+    pub signing_key: Option<String>,
+    // Differences from the fingerprint pinned when the registry was added, filled in on fetch
+    #[serde(default)]
+    pub trust_changes: Vec<RegistryTrustChange>,
+}
+
+/// What a registry looked like when it was first trusted, compared against on every later fetch.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegistryPin {
+    pub registry_id: String,
+    pub url: String,
+    pub signing_key: Option<String>,
+    pub package_hosts: BTreeMap<String, String>, // Package name to the host of its git_url
+    pub pinned_at: u64, // Unix timestamp in seconds
+    #[serde(default)]
+    pub pending_changes: Vec<RegistryTrustChange>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RegistryTrustChange {
+    pub registry_id: String,
+    pub package_name: Option<String>, // None for changes that affect every package of the registry
+    pub field: String, // "signing_key", "git_host"
+    pub previous: Option<String>,
+    pub current: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -74,6 +138,15 @@ pub struct InstalledPackage {
     pub version: String,
     pub git_url: String,
     pub installed_from_registry: Option<String>,
+    #[serde(default)]
+    pub source: String, // "embedded", "local", "tarball", "linked", "git", "registry"
+    #[serde(default)]
+    pub managed_by_cupm: bool,
+    // Working copy the package is linked to in developer link mode
+    #[serde(default)]
+    pub linked_path: Option<String>,
+    #[serde(default)]
+    pub tracking_branch: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -83,3 +156,348 @@ pub struct ProjectInfo {
     pub unity_version: Option<String>,
     pub packages: Vec<InstalledPackage>,
 }
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PackagePatch {
+    pub package_name: String,
+    pub patch_path: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PatchResult {
+    pub package_name: String,
+    pub patch_path: String,
+    pub applied: bool,
+    pub conflicts: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstallResult {
+    pub package_name: String,
+    pub version: String,
+    pub patch: Option<PatchResult>,
+    // Advisories for the installed version that the policy only warns about
+    #[serde(default)]
+    pub advisories: Vec<Advisory>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RemoteRef {
+    pub name: String,
+    pub kind: String, // "tag", "branch"
+    pub commit_id: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RemoteVersionReport {
+    pub git_url: String,
+    pub tags: Vec<RemoteRef>,
+    pub branches: Vec<RemoteRef>,
+    // Tags that exist upstream but are not listed in the registry
+    pub unlisted_tags: Vec<String>,
+    // Registry entries that point at tags or branches the remote doesn't have
+    pub missing_tags: Vec<String>,
+    pub missing_branches: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportedAsset {
+    pub path: String,
+    pub guid: String,
+    pub is_folder: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UnityPackageImport {
+    pub id: String,
+    pub archive_name: String,
+    pub imported_at: u64, // Unix timestamp in seconds
+    pub files: Vec<ImportedAsset>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportConflict {
+    pub path: String,
+    pub guid: String,
+    pub kind: String, // "file_exists", "guid_in_use"
+    pub existing_path: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UnityPackageImportReport {
+    pub imported: bool,
+    pub import: Option<UnityPackageImport>,
+    pub conflicts: Vec<ImportConflict>,
+    pub skipped: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UnityPackageExportReport {
+    pub output_path: String,
+    pub assets_path: String,
+    pub exported: usize,
+    pub skipped: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PackageProvenance {
+    pub package_name: String,
+    pub registry_id: Option<String>,
+    pub version: String,
+    pub git_url: String,
+    pub git_ref: Option<String>, // Tag or branch the package was installed from
+    pub commit: Option<String>,
+    pub installed_at: u64, // Unix timestamp in seconds
+    // Branch followed by the install, set for branch installs so upstream changes can be reported
+    #[serde(default)]
+    pub tracking_branch: Option<String>,
+    // Hash of the installed folder right after install, including applied patches
+    #[serde(default)]
+    pub content_hash: Option<String>,
+    // Hash the registry declared for the version, which covers the commit without patches
+    #[serde(default)]
+    pub registry_content_hash: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PackageVerification {
+    pub package_name: String,
+    pub path: String,
+    pub expected_hash: Option<String>, // Recorded at install, None for packages installed before hashes were kept
+    pub actual_hash: String,
+    pub verified: bool,
+    pub registry_hash: Option<String>, // Declared by the registry the package was installed from, if it declared one
+    pub registry_verified: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AdoptionCandidate {
+    pub package_name: String,
+    pub manifest_entry: String,
+    pub git_url: String,
+    pub git_ref: Option<String>,
+    pub subfolder: Option<String>,
+    // Commit Unity resolved the dependency to, from packages-lock.json
+    pub locked_commit: Option<String>,
+    pub registry_id: String,
+    pub registry_name: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PackageLink {
+    pub package_name: String,
+    pub source_path: String,
+    pub mode: String, // "path", "symlink"
+    // What the project used before the link, restored on unlink
+    pub previous_entry: Option<String>,
+    pub previous_provenance: Option<PackageProvenance>,
+    pub linked_at: u64, // Unix timestamp in seconds
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IncomingCommit {
+    pub id: String,
+    pub summary: String,
+    pub author: String,
+    pub time: i64, // Unix timestamp in seconds
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BranchStatus {
+    pub package_name: String,
+    pub branch: String,
+    pub installed_commit: String,
+    pub upstream_commit: String,
+    pub behind: usize,
+    pub ahead: usize, // Non-zero when upstream history was rewritten since the install
+    pub can_fast_forward: bool,
+    pub incoming_commits: Vec<IncomingCommit>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProjectIssue {
+    pub package_name: String,
+    pub kind: String, // "missing_path", "missing_package_json", "invalid_package_json", "name_mismatch"
+    pub message: String,
+    pub path: String,
+    // Name found in package.json when it differs from the manifest key
+    pub actual_name: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RepairReport {
+    pub reinstalled: Vec<String>,
+    pub removed: Vec<String>,
+    pub renamed: Vec<String>, // "old -> new"
+    pub unresolved: Vec<ProjectIssue>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrphanedPackage {
+    pub folder_name: String,
+    pub path: String,
+    pub package_name: Option<String>,
+    pub version: Option<String>,
+    pub size: u64, // Bytes on disk
+    // What the manifest currently uses for the same package name, if anything
+    pub manifest_entry: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FolderMigrationReport {
+    pub migrated: Vec<String>, // "old_folder -> new_folder"
+    pub skipped: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SafetyPolicy {
+    pub enabled: bool,
+    // Installs whose findings need reviewing are stopped until the user acknowledges them
+    pub require_acknowledgement: bool,
+    // Finding categories that stop an install outright, e.g. "native_binary"
+    pub blocked_categories: Vec<String>,
+}
+
+impl Default for SafetyPolicy {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            require_acknowledgement: true,
+            blocked_categories: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SafetyFinding {
+    // "native_binary", "auto_run_editor_code", "network_api", "process_api", "registry_api", "obfuscated_assembly"
+    pub category: String,
+    pub path: String, // Relative to the package root
+    pub detail: String,
+    pub line: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SafetyReport {
+    pub package_name: String,
+    pub findings: Vec<SafetyFinding>,
+    pub blocked_categories: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SafetyAcknowledgement {
+    pub package_name: String,
+    pub findings: Vec<SafetyFinding>,
+    pub acknowledged_at: u64, // Unix timestamp in seconds
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Advisory {
+    pub id: String,
+    pub package: String,
+    pub affected: String, // Semver range, e.g. ">=1.2.0, <1.2.4"
+    pub severity: String, // "low", "medium", "high", "critical"
+    pub message: String,
+    #[serde(default)]
+    pub url: Option<String>,
+    // Registry whose feed published the advisory, filled in when the feed is fetched
+    #[serde(default)]
+    pub registry_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AdvisoryFeed {
+    pub advisories: Vec<Advisory>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AdvisoryCache {
+    // Keyed by the registry whose feed published them
+    pub advisories: BTreeMap<String, Vec<Advisory>>,
+    pub fetched_at: BTreeMap<String, u64>, // Unix timestamp in seconds
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AdvisoryMatch {
+    pub package_name: String,
+    pub installed_version: String,
+    pub advisory: Advisory,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AdvisoryPolicy {
+    // Installs of versions with advisories of these severities are refused, others only warn
+    pub blocked_severities: Vec<String>,
+}
+
+impl Default for AdvisoryPolicy {
+    fn default() -> Self {
+        Self {
+            blocked_severities: vec!["critical".to_string(), "high".to_string()],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogEntry {
+    pub timestamp: String,
+    pub level: String, // "ERROR", "WARN", "INFO", "DEBUG" or "TRACE"
+    pub target: String,
+    pub message: String,
+    pub operation: Option<String>, // Command the entry was logged under
+    pub project: Option<String>,
+    pub fields: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub id: String,
+    pub timestamp: u64, // Unix timestamp in seconds
+    pub project_path: String,
+    pub operation: String, // "install", "update", "fast_forward", "adopt", "convert", "link", "unlink", "remove", "repair", "migrate_folders", "delete_orphan", "register_orphan", "import_unitypackage", "remove_unitypackage_import" or "undo"
+    pub package_name: Option<String>, // None when an install failed before the package was known
+    pub from_version: Option<String>,
+    pub to_version: Option<String>,
+    pub commit: Option<String>,
+    pub registry_id: Option<String>,
+    pub outcome: String, // "success" or "failed"
+    pub error_code: Option<String>,
+    pub error_message: Option<String>,
+    // Whether the state before the operation was kept so it can be undone
+    #[serde(default)]
+    pub snapshot: bool,
+    // Set on "undo" entries, the operation they reverted
+    #[serde(default)]
+    pub reverts: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HistoryFilter {
+    pub package_name: Option<String>,
+    pub operation: Option<String>,
+    pub outcome: Option<String>,
+    pub since: Option<u64>, // Unix timestamps in seconds, both inclusive
+    pub until: Option<u64>,
+    pub limit: Option<usize>,
+}
+
+/// A package as it was before an operation, kept under `.cupm/snapshots` so the operation can be undone.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PackageSnapshot {
+    pub operation_id: String,
+    pub package_name: String,
+    pub created_at: u64, // Unix timestamp in seconds
+    pub manifest_entry: Option<String>, // None when the package wasn't installed
+    // How the package folder comes back: "none" when the entry doesn't point at a folder in Packages,
+    // "commit" to check out `commit` from `git_url`, "copy" for a retained copy, "symlink" to relink `link_target`
+    pub tree_kind: String,
+    pub install_folder: Option<String>,
+    pub git_url: Option<String>,
+    pub commit: Option<String>,
+    pub link_target: Option<String>,
+    pub provenance: Option<PackageProvenance>,
+    pub link: Option<PackageLink>,
+}
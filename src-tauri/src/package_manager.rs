@@ -1,12 +1,36 @@
 use anyhow::{Context, Result};
 use serde_json::Value;
+use std::collections::HashMap;
 use std::fs;
-use std::path::Path;
-use crate::git_operations::GitOperations;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{debug, info, warn};
+use crate::advisories::AdvisoryStore;
+use crate::config::Config;
+use crate::errors::CupmError;
+use crate::git_operations::{GitDependency, GitOperations};
+use crate::integrity::IntegrityChecker;
+use crate::links::LinkStore;
 use crate::models::*;
+use crate::patches::PatchManager;
+use crate::provenance::ProvenanceStore;
+use crate::registry_trust::RegistryTrust;
+use crate::safety_scan::SafetyScanner;
+use crate::security::{PathGuard, SecurityError};
+use crate::snapshots::SnapshotStore;
 
 pub struct PackageManager;
 
+struct InstallTarget {
+    existing_entry: Option<String>,
+    dir_name: String,
+    path: PathBuf,
+    // Package root inside the install directory, for repositories that keep the package in a subfolder
+    subfolder: Option<String>,
+    // Previous installs moved into staging, with the folder each came from
+    set_aside: Vec<(PathBuf, PathBuf)>,
+}
+
 impl PackageManager {
     pub fn get_project_info(project_path: &str) -> Result<ProjectInfo> {
         let project_settings_path = Path::new(project_path)
@@ -39,12 +63,28 @@ impl PackageManager {
         };
 
         // Get installed packages
-        let packages = if packages_path.exists() {
+        let mut packages = if packages_path.exists() {
             Self::parse_manifest(&packages_path)?
         } else {
             Vec::new()
         };
 
+        // Mark packages CUPM installed, along with the registry they came from
+        let provenance = ProvenanceStore::load(project_path)?;
+        let links = LinkStore::load(project_path)?;
+        for package in &mut packages {
+            if let Some(entry) = provenance.get(&package.name) {
+                package.installed_from_registry = entry.registry_id.clone();
+                package.managed_by_cupm = true;
+                package.tracking_branch = entry.tracking_branch.clone();
+            }
+
+            if let Some(link) = links.get(&package.name) {
+                package.source = "linked".to_string();
+                package.linked_path = Some(link.source_path.clone());
+            }
+        }
+
         Ok(ProjectInfo {
             path: project_path.to_string(),
             name: project_name,
@@ -53,12 +93,16 @@ impl PackageManager {
         })
     }
 
-    fn parse_manifest(manifest_path: &Path) -> Result<Vec<InstalledPackage>> {
+    fn read_manifest(manifest_path: &Path) -> Result<Value> {
         let content = fs::read_to_string(manifest_path)
             .context("Failed to read manifest.json")?;
 
-        let manifest: Value = serde_json::from_str(&content)
-            .context("Failed to parse manifest.json")?;
+        serde_json::from_str(&content)
+            .context("Failed to parse manifest.json")
+    }
+
+    fn parse_manifest(manifest_path: &Path) -> Result<Vec<InstalledPackage>> {
+        let manifest = Self::read_manifest(manifest_path)?;
 
         let mut packages = Vec::new();
         let packages_dir = manifest_path.parent().unwrap(); // This should be the Packages directory
@@ -66,38 +110,26 @@ impl PackageManager {
         if let Some(dependencies) = manifest.get("dependencies").and_then(|d| d.as_object()) {
             for (name, version_value) in dependencies {
                 if let Some(version_str) = version_value.as_str() {
-                    if version_str.starts_with("file:") {
-                        // Local package - read version from package.json
-                        let package_dir_name = version_str.strip_prefix("file:").unwrap_or("");
-                        let package_dir = packages_dir.join(package_dir_name);
-                        let package_json_path = package_dir.join("package.json");
-
-                        let (actual_version, git_url) = if package_json_path.exists() {
-                            match fs::read_to_string(&package_json_path) {
-                                Ok(package_content) => {
-                                    match serde_json::from_str::<Value>(&package_content) {
-                                        Ok(package_data) => {
-                                            let version = package_data.get("version")
-                                                .and_then(|v| v.as_str())
-                                                .unwrap_or("unknown")
-                                                .to_string();
-
-                                            // Try to get git URL from repository field
-                                            let git_url = package_data.get("repository")
-                                                .and_then(|r| r.get("url"))
-                                                .and_then(|u| u.as_str())
-                                                .unwrap_or("")
-                                                .to_string();
-
-                                            (version, git_url)
-                                        }
-                                        Err(_) => ("unknown".to_string(), String::new())
-                                    }
-                                }
-                                Err(_) => ("unknown".to_string(), String::new())
+                    if let Some(file_ref) = version_str.strip_prefix("file:") {
+                        // Local package - read version from package.json, either from a folder or inside a tarball
+                        let (actual_version, git_url) = match Self::read_local_package_json(packages_dir, file_ref) {
+                            Some(Ok(package_data)) => {
+                                let version = package_data.get("version")
+                                    .and_then(|v| v.as_str())
+                                    .unwrap_or("unknown")
+                                    .to_string();
+
+                                // Try to get git URL from repository field
+                                let git_url = package_data.get("repository")
+                                    .and_then(|r| r.get("url"))
+                                    .and_then(|u| u.as_str())
+                                    .unwrap_or("")
+                                    .to_string();
+
+                                (version, git_url)
                             }
-                        } else {
-                            ("local".to_string(), String::new())
+                            Some(Err(_)) => ("unknown".to_string(), String::new()),
+                            None => ("local".to_string(), String::new()),
                         };
 
                         packages.push(InstalledPackage {
@@ -105,6 +137,10 @@ impl PackageManager {
                             version: actual_version,
                             git_url,
                             installed_from_registry: None,
+                            managed_by_cupm: false,
+                            linked_path: None,
+                            tracking_branch: None,
+                            source: Self::get_file_reference_source(file_ref).to_string(),
                         });
                     } else if version_str.starts_with("https://") || version_str.starts_with("git+") {
                         // Git URL (legacy format)
@@ -113,6 +149,10 @@ impl PackageManager {
                             version: "git".to_string(),
                             git_url: version_str.to_string(),
                             installed_from_registry: None,
+                            managed_by_cupm: false,
+                            linked_path: None,
+                            tracking_branch: None,
+                            source: "git".to_string(),
                         });
                     } else {
                         // Regular version number (Unity Package Manager registry)
@@ -121,6 +161,10 @@ impl PackageManager {
                             version: version_str.to_string(),
                             git_url: String::new(),
                             installed_from_registry: None,
+                            managed_by_cupm: false,
+                            linked_path: None,
+                            tracking_branch: None,
+                            source: "registry".to_string(),
                         });
                     }
                 }
@@ -130,12 +174,17 @@ impl PackageManager {
         Ok(packages)
     }
 
-    pub fn install_package(project_path: &str, package: &Package) -> Result<()> {
+    pub fn install_package(project_path: &str, package: &Package) -> Result<InstallResult> {
+        Self::install_registry_package(project_path, package, None)
+    }
+
+    /// Installs a registry package, pinned to `commit` when one is given rather than the tip of its tag or branch.
+    fn install_registry_package(project_path: &str, package: &Package, commit: Option<&str>) -> Result<InstallResult> {
         let packages_dir = Path::new(project_path).join("Packages");
         let manifest_path = packages_dir.join("manifest.json");
 
         if !manifest_path.exists() {
-            return Err(anyhow::anyhow!("Project manifest.json not found"));
+            return Err(CupmError::manifest_not_found(&manifest_path).into());
         }
 
         // Read current manifest to check for conflicts
@@ -145,122 +194,338 @@ impl PackageManager {
         let mut manifest: Value = serde_json::from_str(&content)
             .context("Failed to parse manifest.json")?;
 
-        // Ensure dependencies object exists
-        if !manifest.get("dependencies").is_some() {
-            manifest["dependencies"] = serde_json::json!({});
+        if let Some(registry_id) = &package.registry_id {
+            RegistryTrust::check_package(registry_id, &package.name)?;
         }
 
-        // Check if package already exists and handle conflicts
-        let existing_entry_info = manifest.get("dependencies")
-            .and_then(|d| d.get(&package.name))
-            .and_then(|v| v.as_str())
-            .map(|s| s.to_string());
-
-        if let Some(existing_ref) = &existing_entry_info {
-            println!("Found existing package entry: {} -> {}", package.name, existing_ref);
+        // Clone outside of Packages first so nothing changes in the project until the safety scan has passed
+        let staging_path = Self::get_staging_path(project_path);
 
-            // If it's a Git URL, we need to remove it before installing our local version
-            if existing_ref.starts_with("https://") || existing_ref.starts_with("git+") || existing_ref.contains(".git") {
-                println!("Removing Unity-managed Git package to avoid conflicts: {}", existing_ref);
-                // The package will be replaced with our local version below
-            }
-            // If it's already a local file reference, we'll replace it (upgrade/downgrade scenario)
-            else if existing_ref.starts_with("file:") {
-                let existing_package_dir_name = existing_ref.strip_prefix("file:").unwrap_or("");
-                let existing_package_path = packages_dir.join(existing_package_dir_name);
-                if existing_package_path.exists() {
-                    println!("Removing existing local package directory for upgrade/downgrade: {}", existing_package_path.display());
-                    fs::remove_dir_all(&existing_package_path)
-                        .with_context(|| format!("Failed to remove existing package directory: {}", existing_package_path.display()))?;
-                }
-            }
+        let staged = Self::stage_registry_package(&staging_path, package, commit).and_then(|_| {
+            Self::verify_content_hash(package, &staging_path)?;
+            let version = GitOperations::get_current_version_from_package_json(&staging_path).ok();
+            Self::check_before_install(project_path, &package.name, &staging_path, version.as_deref(), package.registry_id.as_deref())
+        });
+        if let Err(e) = staged {
+            Self::discard_staging(project_path, &staging_path);
+            return Err(e);
         }
 
-        // Create package directory name from package name (sanitize for filesystem)
-        let package_dir_name = package.name.replace("/", "_").replace("\\", "_").replace(".", "_");
-        let package_install_path = packages_dir.join(&package_dir_name);
+        let installed = Self::prepare_install_target(project_path, &mut manifest, &package.name).and_then(|target| {
+            Self::place_package(project_path, &target, |path| Self::move_from_staging(&staging_path, path))?;
+            Self::complete_install(project_path, &manifest_path, manifest, package, target)
+        });
 
-        // Remove existing package directory if it exists (additional safety check)
-        if package_install_path.exists() {
-            println!("Removing existing package directory: {}", package_install_path.display());
-            fs::remove_dir_all(&package_install_path)
-                .with_context(|| format!("Failed to remove existing package directory: {}", package_install_path.display()))?;
+        // Once the package has been moved into Packages there is nothing left in staging to discard
+        if installed.is_err() {
+            Self::discard_staging(project_path, &staging_path);
         }
 
-        // Clone the repository directly into the Packages folder
-        println!("Cloning repository {} to {}", package.git_url, package_install_path.display());
-        GitOperations::clone_repository(&package.git_url, &package_install_path)
-            .with_context(|| format!("Failed to clone repository {} to {}", package.git_url, package_install_path.display()))?;
+        installed
+    }
+
+    fn stage_registry_package(staging_path: &Path, package: &Package, commit: Option<&str>) -> Result<()> {
+        info!("Cloning repository {} to {}", package.git_url, staging_path.display());
+        GitOperations::clone_repository(&package.git_url, staging_path)
+            .with_context(|| format!("Failed to clone repository {} to {}", package.git_url, staging_path.display()))?;
 
         // Checkout specific tag or branch if specified
         if let Some(tag) = &package.git_tag {
-            println!("Checking out tag: {}", tag);
+            info!("Checking out tag: {}", tag);
 
             // List available tags for debugging
-            match GitOperations::list_available_tags(&package_install_path) {
+            match GitOperations::list_available_tags(staging_path) {
                 Ok(tags) => {
-                    println!("Available tags: {:?}", tags);
+                    debug!("Available tags: {:?}", tags);
                     if !tags.contains(tag) {
-                        println!("Warning: Requested tag '{}' not found in available tags", tag);
+                        warn!("Requested tag '{}' not found in available tags", tag);
                     }
                 }
-                Err(e) => println!("Could not list tags: {}", e),
+                Err(e) => warn!("Could not list tags: {}", e),
             }
 
-            GitOperations::checkout_tag(&package_install_path, tag)
+            GitOperations::checkout_tag(staging_path, tag)
                 .with_context(|| format!("Failed to checkout tag: {}", tag))?;
 
             // Verify the checkout worked by reading the version again
-            let version_after_checkout = GitOperations::get_current_version_from_package_json(&package_install_path)
+            let version_after_checkout = GitOperations::get_current_version_from_package_json(staging_path)
                 .unwrap_or_else(|_| "unknown".to_string());
-            println!("Version after checkout: {}", version_after_checkout);
+            debug!("Version after checkout: {}", version_after_checkout);
         } else if let Some(branch) = &package.git_branch {
-            println!("Checking out branch: {}", branch);
-            GitOperations::checkout_branch(&package_install_path, branch)
+            info!("Checking out branch: {}", branch);
+            GitOperations::checkout_branch(staging_path, branch)
                 .with_context(|| format!("Failed to checkout branch: {}", branch))?;
 
             // Verify the checkout worked
-            let version_after_checkout = GitOperations::get_current_version_from_package_json(&package_install_path)
+            let version_after_checkout = GitOperations::get_current_version_from_package_json(staging_path)
                 .unwrap_or_else(|_| "unknown".to_string());
-            println!("Version after checkout: {}", version_after_checkout);
+            debug!("Version after checkout: {}", version_after_checkout);
         }
 
-        // Read the package.json from the cloned repository to get actual version info
-        // This should be done AFTER checkout to get the correct version
-        let actual_version = GitOperations::get_current_version_from_package_json(&package_install_path)
-            .unwrap_or_else(|e| {
-                println!("Warning: Could not read version from package.json: {}", e);
-                "unknown".to_string()
+        if let Some(commit) = commit {
+            info!("Checking out recorded commit: {}", commit);
+            GitOperations::checkout_commit(staging_path, commit)
+                .with_context(|| format!("Failed to checkout commit: {}", commit))?;
+        }
+
+        Ok(())
+    }
+
+    fn get_staging_dir(project_path: &str) -> PathBuf {
+        Path::new(project_path).join(".cupm").join("staging")
+    }
+
+    /// Folder new packages are cloned or extracted to before they are moved into Packages.
+    fn get_staging_path(project_path: &str) -> PathBuf {
+        Self::get_staging_dir(project_path).join(uuid::Uuid::new_v4().to_string())
+    }
+
+    /// Removes what a failed install left in staging. Failing to clean up is only logged, the install error matters more.
+    fn discard_staging(project_path: &str, staging_path: &Path) {
+        if !staging_path.exists() {
+            return;
+        }
+
+        let staging_dir = Self::get_staging_dir(project_path);
+        if let Err(e) = PathGuard::remove_dir_all(&staging_dir, staging_path, "remove staging folder") {
+            warn!("Could not remove staging folder {}: {:#}", staging_path.display(), e);
+        }
+    }
+
+    /// Checks a pristine checkout against the hash its registry declared, if it declared one.
+    fn verify_content_hash(package: &Package, package_path: &Path) -> Result<()> {
+        match &package.content_hash {
+            Some(expected_hash) => IntegrityChecker::verify(&package.name, package_path, expected_hash).map(|_| ()),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks a package that has been checked out but not installed yet: its version against the cached advisories
+    /// of the registry it comes from, then its contents with the safety scan.
+    fn check_before_install(
+        project_path: &str,
+        package_name: &str,
+        package_path: &Path,
+        version: Option<&str>,
+        registry_id: Option<&str>,
+    ) -> Result<()> {
+        // An unreadable config falls back to the default policies rather than skipping the checks
+        let config = Config::load().unwrap_or_default();
+
+        if let Some(version) = version {
+            AdvisoryStore::check(package_name, version, registry_id, &config.advisory_policy)?;
+        }
+
+        if !config.safety_policy.enabled {
+            info!("Safety scan disabled, skipping scan of {}", package_name);
+            return Ok(());
+        }
+
+        SafetyScanner::check(project_path, package_name, package_path, &config.safety_policy).map(|_| ())
+    }
+
+    /// Clones a git repository that isn't listed in any registry and installs it as an embedded package.
+    /// The package identity is read from the repository's own package.json.
+    pub fn install_from_git_url(
+        project_path: &str,
+        git_url: &str,
+        git_tag: Option<&str>,
+        git_branch: Option<&str>,
+        git_commit: Option<&str>,
+    ) -> Result<InstallResult> {
+        let packages_dir = Path::new(project_path).join("Packages");
+        let manifest_path = packages_dir.join("manifest.json");
+
+        if !manifest_path.exists() {
+            return Err(CupmError::manifest_not_found(&manifest_path).into());
+        }
+
+        // Clone outside of Packages first, Unity would otherwise pick up the folder before it is validated
+        let staging_path = Self::get_staging_path(project_path);
+
+        let staged = Self::stage_git_package(&staging_path, git_url, git_tag, git_branch, git_commit)
+            .and_then(|package| {
+                Self::check_before_install(project_path, &package.name, &staging_path, package.version.as_deref(), None)?;
+                Ok(package)
             });
+        let package = match staged {
+            Ok(package) => package,
+            Err(e) => {
+                Self::discard_staging(project_path, &staging_path);
+                return Err(e);
+            }
+        };
 
-        // Update manifest.json to reference the local folder (this replaces any existing entry)
-        let local_path = format!("file:{}", package_dir_name);
-        manifest["dependencies"][&package.name] = Value::String(local_path);
+        info!("Installing {} ({}) from {}", package.name, package.version.as_deref().unwrap_or("unknown"), git_url);
 
-        // Write back to file
-        let updated_content = serde_json::to_string_pretty(&manifest)
-            .context("Failed to serialize manifest.json")?;
+        let installed = Self::read_manifest(&manifest_path).and_then(|mut manifest| {
+            let target = Self::prepare_install_target(project_path, &mut manifest, &package.name)?;
+            Self::place_package(project_path, &target, |path| Self::move_from_staging(&staging_path, path))?;
+            Self::complete_install(project_path, &manifest_path, manifest, &package, target)
+        });
 
-        fs::write(&manifest_path, updated_content)
-            .context("Failed to write manifest.json")?;
+        // Once the package has been moved into Packages there is nothing left in staging to discard
+        if installed.is_err() {
+            Self::discard_staging(project_path, &staging_path);
+        }
 
-        if let Some(existing_ref) = existing_entry_info {
-            println!("Successfully replaced package {} (was: {}, now: file:{} version: {})",
-                package.name, existing_ref, package_dir_name, actual_version);
-        } else {
-            println!("Successfully installed package {} (version: {}) to {}",
-                package.name, actual_version, package_dir_name);
+        installed
+    }
+
+    fn stage_git_package(
+        staging_path: &Path,
+        git_url: &str,
+        git_tag: Option<&str>,
+        git_branch: Option<&str>,
+        git_commit: Option<&str>,
+    ) -> Result<Package> {
+        GitOperations::clone_repository(git_url, staging_path)
+            .with_context(|| format!("Failed to clone repository {}", git_url))?;
+
+        if let Some(tag) = git_tag {
+            GitOperations::checkout_tag(staging_path, tag)
+                .with_context(|| format!("Failed to checkout tag: {}", tag))?;
+        } else if let Some(branch) = git_branch {
+            GitOperations::checkout_branch(staging_path, branch)
+                .with_context(|| format!("Failed to checkout branch: {}", branch))?;
+        } else if let Some(commit) = git_commit {
+            GitOperations::checkout_commit(staging_path, commit)
+                .with_context(|| format!("Failed to checkout commit: {}", commit))?;
+        }
+
+        let mut package = Self::read_package_json(staging_path, git_url)?;
+        package.git_tag = git_tag.map(|t| t.to_string());
+        package.git_branch = git_branch.map(|b| b.to_string());
+        Ok(package)
+    }
+
+    /// Reads the name a folder or tarball would be installed under, without installing it.
+    pub fn peek_package_name(source_path: &Path) -> Option<String> {
+        if Self::is_tarball_reference(&source_path.to_string_lossy()) {
+            let package_data = Self::read_tarball_package_json(source_path).ok()?;
+            return package_data.get("name").and_then(|n| n.as_str()).map(|n| n.to_string());
+        }
+
+        Self::read_package_json(source_path, "").ok().map(|package| package.name)
+    }
+
+    /// Builds a Package from a package.json, validating that it describes a usable UPM package.
+    pub fn read_package_json(package_path: &Path, git_url: &str) -> Result<Package> {
+        let package_json_path = package_path.join("package.json");
+        if !package_json_path.exists() {
+            return Err(CupmError::NotAUnityPackage { path: package_path.to_string_lossy().to_string() }.into());
+        }
+
+        let content = fs::read_to_string(&package_json_path)
+            .context("Failed to read package.json")?;
+
+        let package_data: Value = serde_json::from_str(&content)
+            .context("Failed to parse package.json")?;
+
+        let get_str = |key: &str| package_data.get(key).and_then(|v| v.as_str()).map(|s| s.to_string());
+
+        let name = get_str("name")
+            .context("package.json has no name")?;
+        Self::validate_package_name(&name)?;
+
+        let version = get_str("version")
+            .context("package.json has no version")?;
+
+        // Author can be a plain string or an object with a name
+        let author = package_data.get("author").and_then(|a| {
+            a.as_str().map(|s| s.to_string())
+                .or_else(|| a.get("name").and_then(|n| n.as_str()).map(|s| s.to_string()))
+        });
+
+        let keywords = package_data.get("keywords").and_then(|k| k.as_array()).map(|k| {
+            k.iter().filter_map(|v| v.as_str().map(|s| s.to_string())).collect()
+        });
+
+        let dependencies = package_data.get("dependencies").and_then(|d| d.as_object()).map(|d| {
+            d.iter()
+                .filter_map(|(k, v)| v.as_str().map(|v| (k.clone(), v.to_string())))
+                .collect::<HashMap<String, String>>()
+        });
+
+        Ok(Package {
+            display_name: get_str("displayName").unwrap_or_else(|| name.clone()),
+            description: get_str("description").unwrap_or_default(),
+            name,
+            git_url: git_url.to_string(),
+            author,
+            keywords,
+            category: get_str("category"),
+            license: get_str("license"),
+            versions: None,
+            version: Some(version),
+            git_branch: None,
+            git_tag: None,
+            dependencies,
+            unity_version: get_str("unity"),
+            is_prerelease: false,
+            registry_id: None,
+            content_hash: None,
+        })
+    }
+
+    /// Checks a name against the UPM naming rules (lowercase reverse domain notation).
+    pub fn validate_package_name(name: &str) -> Result<()> {
+        let valid_chars = name.chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '.' || c == '-' || c == '_');
+
+        let valid_segments = name.split('.').all(|segment| {
+            segment.chars().next().map(|c| c.is_ascii_lowercase() || c.is_ascii_digit()).unwrap_or(false)
+        });
+
+        if name.len() > 214 || !valid_chars || !valid_segments || !name.contains('.') {
+            return Err(CupmError::InvalidPackageName {
+                name: name.to_string(),
+                reason: "expected lowercase reverse domain notation such as com.company.package".to_string(),
+            }.into());
+        }
+
+        // Windows refuses these as folder names even with an extension, e.g. `con.tools`
+        let first_segment = name.split('.').next().unwrap_or_default();
+        let reserved = ["con", "prn", "aux", "nul"].contains(&first_segment)
+            || ((first_segment.starts_with("com") || first_segment.starts_with("lpt"))
+                && first_segment.len() == 4
+                && first_segment[3..].chars().all(|c| ('1'..='9').contains(&c)));
+
+        if reserved {
+            return Err(CupmError::InvalidPackageName {
+                name: name.to_string(),
+                reason: format!("'{}' is a reserved device name on Windows", first_segment),
+            }.into());
         }
 
         Ok(())
     }
 
-    pub fn remove_package(project_path: &str, package_name: &str) -> Result<()> {
+    /// Folder an embedded package is installed to. Valid package names are already safe folder names and
+    /// distinct names give distinct folders, so the name is used as-is, the way Unity names embedded packages.
+    pub fn get_package_dir_name(package_name: &str) -> String {
+        package_name.to_string()
+    }
+
+    /// Folder name older versions installed packages to, which mapped different names onto the same folder.
+    fn get_legacy_package_dir_name(package_name: &str) -> String {
+        package_name.replace("/", "_").replace("\\", "_").replace(".", "_")
+    }
+
+    /// First component of an embedded `file:` reference, the folder directly inside Packages.
+    fn get_install_folder_name(file_ref: &str) -> Option<String> {
+        Path::new(file_ref).components()
+            .find(|c| matches!(c, std::path::Component::Normal(_)))
+            .map(|c| c.as_os_str().to_string_lossy().to_string())
+    }
+
+    /// Moves packages installed under the old `com_company_package` folder names to their package name folders.
+    pub fn migrate_package_folders(project_path: &str) -> Result<FolderMigrationReport> {
         let packages_dir = Path::new(project_path).join("Packages");
         let manifest_path = packages_dir.join("manifest.json");
 
         if !manifest_path.exists() {
-            return Err(anyhow::anyhow!("Project manifest.json not found"));
+            return Err(CupmError::manifest_not_found(&manifest_path).into());
         }
 
         let content = fs::read_to_string(&manifest_path)
@@ -269,101 +534,2053 @@ impl PackageManager {
         let mut manifest: Value = serde_json::from_str(&content)
             .context("Failed to parse manifest.json")?;
 
-        // Remove package from dependencies and get the package path if it's local
-        let mut package_dir_to_remove: Option<String> = None;
-        if let Some(dependencies) = manifest.get_mut("dependencies").and_then(|d| d.as_object_mut()) {
-            if let Some(package_ref) = dependencies.get(package_name) {
-                if let Some(package_ref_str) = package_ref.as_str() {
-                    // Check if it's a local file reference
-                    if package_ref_str.starts_with("file:") {
-                        package_dir_to_remove = Some(package_ref_str.strip_prefix("file:").unwrap_or("").to_string());
-                    }
+        let mut report = FolderMigrationReport { migrated: Vec::new(), skipped: Vec::new() };
+
+        let dependencies = match manifest.get_mut("dependencies").and_then(|d| d.as_object_mut()) {
+            Some(dependencies) => dependencies,
+            None => return Ok(report),
+        };
+
+        for (name, value) in dependencies.iter_mut() {
+            let file_ref = match value.as_str().and_then(|v| v.strip_prefix("file:")) {
+                Some(file_ref) if Self::get_file_reference_source(file_ref) == "embedded" => file_ref.to_string(),
+                _ => continue,
+            };
+
+            let old_folder = match Self::get_install_folder_name(&file_ref) {
+                Some(folder) if folder == Self::get_legacy_package_dir_name(name) => folder,
+                _ => continue,
+            };
+
+            if let Err(e) = Self::validate_package_name(name) {
+                report.skipped.push(format!("{}: {}", name, e));
+                continue;
+            }
+
+            let new_folder = Self::get_package_dir_name(name);
+            let old_path = packages_dir.join(&old_folder);
+            let new_path = packages_dir.join(&new_folder);
+
+            if Self::is_symlink(&new_path) || new_path.exists() {
+                report.skipped.push(format!("{}: {} already exists", name, new_path.display()));
+                continue;
+            }
+
+            if !Self::is_symlink(&old_path) && !old_path.exists() {
+                report.skipped.push(format!("{}: {} does not exist", name, old_path.display()));
+                continue;
+            }
+
+            info!("Migrating {} from {} to {}", name, old_path.display(), new_path.display());
+            if let Err(e) = fs::rename(&old_path, &new_path) {
+                report.skipped.push(format!("{}: failed to move {}: {}", name, old_path.display(), e));
+                continue;
+            }
+
+            // Keep any subfolder the reference pointed into
+            let rest = Path::new(&file_ref).strip_prefix(&old_folder).unwrap_or(Path::new(""));
+            let new_ref = Path::new(&new_folder).join(rest).to_string_lossy().replace('\\', "/");
+            *value = Value::String(format!("file:{}", new_ref.trim_end_matches('/')));
+
+            report.migrated.push(format!("{} -> {}", old_folder, new_folder));
+        }
+
+        if !report.migrated.is_empty() {
+            let updated_content = serde_json::to_string_pretty(&manifest)
+                .context("Failed to serialize manifest.json")?;
+
+            fs::write(&manifest_path, updated_content)
+                .context("Failed to write manifest.json")?;
+        }
+
+        info!("Migrated {} package folder(s), skipped {}", report.migrated.len(), report.skipped.len());
+
+        Ok(report)
+    }
+
+    /// Works out where a package goes and sets aside any existing installation of it. Nothing is removed yet,
+    /// `place_package` drops what was set aside once the new copy is in place, or puts it back when that fails.
+    fn prepare_install_target(project_path: &str, manifest: &mut Value, package_name: &str) -> Result<InstallTarget> {
+        // Checked before anything is moved, an invalid name must leave the current install alone
+        Self::validate_package_name(package_name)?;
+
+        let packages_dir = Path::new(project_path).join("Packages");
+
+        // Ensure dependencies object exists
+        if manifest.get("dependencies").is_none() {
+            manifest["dependencies"] = serde_json::json!({});
+        }
+
+        // Check if package already exists and handle conflicts
+        let existing_entry = manifest.get("dependencies")
+            .and_then(|d| d.get(package_name))
+            .and_then(|v| v.as_str())
+            .map(|s| s.to_string());
+
+        let dir_name = Self::get_package_dir_name(package_name);
+        let path = packages_dir.join(&dir_name);
+        let mut in_the_way = Vec::new();
+
+        if let Some(existing_ref) = &existing_entry {
+            info!("Found existing package entry: {} -> {}", package_name, existing_ref);
+
+            // If it's a Git URL, we need to remove it before installing our local version
+            if existing_ref.starts_with("https://") || existing_ref.starts_with("git+") || existing_ref.contains(".git") {
+                info!("Removing Unity-managed Git package to avoid conflicts: {}", existing_ref);
+                // The package will be replaced with our local version below
+            }
+            // If it's already a local file reference, we'll replace it (upgrade/downgrade scenario)
+            else if let Some(existing_file_ref) = existing_ref.strip_prefix("file:") {
+                // Only embedded copies are ours to replace, referenced folders and tarballs belong to the user.
+                // Packages kept in a repository subfolder go with their whole install folder.
+                let is_embedded = Self::get_file_reference_source(existing_file_ref) == "embedded";
+                let existing_package_path = match Self::get_install_folder_name(existing_file_ref) {
+                    Some(folder_name) if is_embedded => packages_dir.join(folder_name),
+                    _ => packages_dir.join(existing_file_ref),
+                };
+                // Developer links point at a working copy, moving one only moves the link itself
+                if is_embedded && (Self::is_symlink(&existing_package_path) || existing_package_path.is_dir()) {
+                    info!("Setting aside existing package for upgrade/downgrade: {}", existing_package_path.display());
+                    in_the_way.push(existing_package_path);
                 }
             }
-            dependencies.remove(package_name);
         }
 
-        // Remove the local package directory if it exists
-        if let Some(package_dir_name) = package_dir_to_remove {
-            let package_path = packages_dir.join(&package_dir_name);
-            if package_path.exists() {
-                println!("Removing local package directory: {}", package_path.display());
-                fs::remove_dir_all(&package_path)
-                    .with_context(|| format!("Failed to remove package directory: {}", package_path.display()))?;
+        // Whatever else is in the way isn't referenced by this package's manifest entry,
+        // so it is only set aside when it is a leftover copy of the same package
+        if !in_the_way.contains(&path) && (Self::is_symlink(&path) || path.exists()) {
+            let occupant = fs::read_to_string(path.join("package.json"))
+                .ok()
+                .and_then(|content| serde_json::from_str::<Value>(&content).ok())
+                .and_then(|data| data.get("name").and_then(|n| n.as_str()).map(|n| n.to_string()));
+
+            match occupant {
+                Some(name) if name == package_name => {}
+                occupant => return Err(CupmError::FolderOccupied {
+                    path: path.to_string_lossy().to_string(),
+                    occupant,
+                }.into()),
+            }
+
+            info!("Setting aside leftover package directory: {}", path.display());
+            in_the_way.push(path.clone());
+        }
+
+        let mut set_aside = Vec::new();
+
+        for original in in_the_way {
+            let backup = Self::get_staging_path(project_path);
+
+            let moved = PathGuard::confine(&packages_dir, &original, "set aside package directory")
+                .and_then(|_| fs::create_dir_all(Self::get_staging_dir(project_path)).context("Failed to create staging folder"))
+                .and_then(|_| {
+                    fs::rename(&original, &backup)
+                        .with_context(|| format!("Failed to move {} out of the way", original.display()))
+                });
+
+            if let Err(e) = moved {
+                Self::restore_set_aside(&set_aside);
+                return Err(e);
+            }
+
+            set_aside.push((original, backup));
+        }
+
+        Ok(InstallTarget { existing_entry, dir_name, path, subfolder: None, set_aside })
+    }
+
+    /// Moves folders set aside by `prepare_install_target` back to where they were.
+    fn restore_set_aside(set_aside: &[(PathBuf, PathBuf)]) {
+        for (original, backup) in set_aside.iter().rev() {
+            if let Err(e) = fs::rename(backup, original) {
+                warn!("Could not move {} back to {}: {}", backup.display(), original.display(), e);
+            }
+        }
+    }
+
+    /// Fills the install folder of a prepared target with `place`. When that fails, any partial copy is removed
+    /// and the previous install is put back, otherwise the previous install is dropped.
+    fn place_package(project_path: &str, target: &InstallTarget, place: impl FnOnce(&Path) -> Result<()>) -> Result<()> {
+        let packages_dir = Path::new(project_path).join("Packages");
+
+        if let Err(e) = place(&target.path) {
+            if Self::is_symlink(&target.path) || target.path.exists() {
+                if let Err(cleanup_error) = PathGuard::remove_dir_all(&packages_dir, &target.path, "remove partial install") {
+                    warn!("Could not remove partial install {}: {:#}", target.path.display(), cleanup_error);
+                }
             }
+
+            Self::restore_set_aside(&target.set_aside);
+            return Err(e);
+        }
+
+        for (_, backup) in &target.set_aside {
+            Self::discard_staging(project_path, backup);
         }
 
+        Ok(())
+    }
+
+    /// Moves a checked out package from staging into its install folder.
+    fn move_from_staging(staging_path: &Path, path: &Path) -> Result<()> {
+        fs::rename(staging_path, path)
+            .with_context(|| format!("Failed to move package into {}", path.display()))
+    }
+
+    /// Applies local patches and points manifest.json at a package that has been placed in its install directory.
+    fn complete_install(
+        project_path: &str,
+        manifest_path: &Path,
+        mut manifest: Value,
+        package: &Package,
+        target: InstallTarget,
+    ) -> Result<InstallResult> {
+        let package_name = package.name.as_str();
+        let package_root = match &target.subfolder {
+            Some(subfolder) => target.path.join(subfolder),
+            None => target.path.clone(),
+        };
+
+        // Read the package.json from the cloned repository to get actual version info
+        // This should be done AFTER checkout to get the correct version
+        let actual_version = GitOperations::get_current_version_from_package_json(&package_root)
+            .unwrap_or_else(|e| {
+                warn!("Could not read version from package.json: {}", e);
+                "unknown".to_string()
+            });
+
+        // Update manifest.json to reference the local folder (this replaces any existing entry).
+        // Written first, so the folder that is now in Packages is referenced even if a later step fails
+        let local_path = match &target.subfolder {
+            Some(subfolder) => format!("file:{}/{}", target.dir_name, subfolder),
+            None => format!("file:{}", target.dir_name),
+        };
+        manifest["dependencies"][package_name] = Value::String(local_path);
+
         // Write back to file
         let updated_content = serde_json::to_string_pretty(&manifest)
             .context("Failed to serialize manifest.json")?;
 
-        fs::write(&manifest_path, updated_content)
+        fs::write(manifest_path, updated_content)
             .context("Failed to write manifest.json")?;
 
-        println!("Successfully removed package: {}", package_name);
-        Ok(())
+        // Re-apply any local patches captured for this package, conflicts are reported back rather than failing the install
+        let patch = PatchManager::apply_patch(project_path, package_name, &target.path)
+            .with_context(|| format!("Failed to apply local patch for {}", package_name))?;
+
+        Self::record_provenance(project_path, package, &actual_version, Some(&target.path))?;
+
+        if let Some(existing_ref) = target.existing_entry {
+            info!("Successfully replaced package {} (was: {}, now: file:{} version: {})",
+                package_name, existing_ref, target.dir_name, actual_version);
+        } else {
+            info!("Successfully installed package {} (version: {}) to {}",
+                package_name, actual_version, target.dir_name);
+        }
+
+        Ok(InstallResult {
+            package_name: package_name.to_string(),
+            version: actual_version,
+            patch,
+            advisories: Vec::new(),
+        })
     }
 
-    pub fn check_package_conflicts(project_path: &str, package_name: &str) -> Result<Option<String>> {
-        let manifest_path = Path::new(project_path)
-            .join("Packages")
-            .join("manifest.json");
+    /// Installs a package from a folder on disk, either copied into Packages or referenced where it is.
+    /// References use a path relative to Packages when `relative_reference` is set, otherwise an absolute path.
+    pub fn install_from_local_folder(
+        project_path: &str,
+        source_path: &str,
+        copy: bool,
+        relative_reference: bool,
+    ) -> Result<InstallResult> {
+        let packages_dir = Path::new(project_path).join("Packages");
+        let manifest_path = packages_dir.join("manifest.json");
 
         if !manifest_path.exists() {
-            return Ok(None);
+            return Err(CupmError::manifest_not_found(&manifest_path).into());
         }
 
+        let source_path = fs::canonicalize(source_path)
+            .with_context(|| format!("Package folder not found: {}", source_path))?;
+
+        let package = Self::read_package_json(&source_path, "")?;
+        Self::check_before_install(project_path, &package.name, &source_path, package.version.as_deref(), None)?;
+
         let content = fs::read_to_string(&manifest_path)
             .context("Failed to read manifest.json")?;
 
-        let manifest: Value = serde_json::from_str(&content)
+        let mut manifest: Value = serde_json::from_str(&content)
             .context("Failed to parse manifest.json")?;
 
-        if let Some(dependencies) = manifest.get("dependencies").and_then(|d| d.as_object()) {
-            if let Some(existing_ref) = dependencies.get(package_name).and_then(|v| v.as_str()) {
-                return Ok(Some(existing_ref.to_string()));
-            }
+        let target = Self::prepare_install_target(project_path, &mut manifest, &package.name)?;
+
+        if copy {
+            info!("Copying local package {} from {}", package.name, source_path.display());
+            Self::place_package(project_path, &target, |path| {
+                Self::copy_package_dir(&source_path, path)
+                    .with_context(|| format!("Failed to copy package to {}", path.display()))
+            })?;
+
+            return Self::complete_install(project_path, &manifest_path, manifest, &package, target);
         }
 
-        Ok(None)
+        // A reference leaves the folder in Packages unused
+        Self::place_package(project_path, &target, |_| Ok(()))?;
+
+        let reference = if relative_reference {
+            let packages_dir = fs::canonicalize(&packages_dir)
+                .context("Failed to resolve Packages directory")?;
+            Self::get_relative_path(&packages_dir, &source_path)
+        } else {
+            source_path.clone()
+        };
+
+        // Unity expects forward slashes in file: references on every platform
+        let reference = reference.to_string_lossy().replace("\\", "/");
+        manifest["dependencies"][&package.name] = Value::String(format!("file:{}", reference));
+
+        let updated_content = serde_json::to_string_pretty(&manifest)
+            .context("Failed to serialize manifest.json")?;
+
+        fs::write(&manifest_path, updated_content)
+            .context("Failed to write manifest.json")?;
+
+        let version = package.version.clone().unwrap_or_else(|| "unknown".to_string());
+        Self::record_provenance(project_path, &package, &version, None)?;
+
+        info!("Successfully referenced package {} (version: {}) at file:{}", package.name, version, reference);
+
+        Ok(InstallResult {
+            package_name: package.name,
+            version,
+            patch: None,
+            advisories: Vec::new(),
+        })
     }
 
-    pub fn get_installed_package_info(project_path: &str, package_name: &str) -> Result<Option<(String, String)>> {
+    /// Extracts an npm style UPM tarball (.tgz) into Packages and installs it as an embedded package.
+    pub fn install_from_tarball(project_path: &str, tarball_path: &str) -> Result<InstallResult> {
         let packages_dir = Path::new(project_path).join("Packages");
         let manifest_path = packages_dir.join("manifest.json");
 
         if !manifest_path.exists() {
-            return Ok(None);
+            return Err(CupmError::manifest_not_found(&manifest_path).into());
         }
 
-        let content = fs::read_to_string(&manifest_path)
-            .context("Failed to read manifest.json")?;
+        let staging_path = Self::get_staging_path(project_path);
 
-        let manifest: Value = serde_json::from_str(&content)
-            .context("Failed to parse manifest.json")?;
+        let staged = Self::extract_tarball(Path::new(tarball_path), &staging_path)
+            .and_then(|_| Self::read_package_json(&staging_path, ""))
+            .and_then(|package| {
+                Self::check_before_install(project_path, &package.name, &staging_path, package.version.as_deref(), None)?;
+                Ok(package)
+            });
 
-        if let Some(dependencies) = manifest.get("dependencies").and_then(|d| d.as_object()) {
-            if let Some(package_ref) = dependencies.get(package_name).and_then(|v| v.as_str()) {
-                if package_ref.starts_with("file:") {
-                    // Local package - read version from package.json
-                    let package_dir_name = package_ref.strip_prefix("file:").unwrap_or("");
-                    let package_dir = packages_dir.join(package_dir_name);
-                    let package_json_path = package_dir.join("package.json");
+        let package = match staged {
+            Ok(package) => package,
+            Err(e) => {
+                Self::discard_staging(project_path, &staging_path);
+                return Err(e.context(format!("Failed to install tarball {}", tarball_path)));
+            }
+        };
 
-                    if package_json_path.exists() {
-                        let package_content = fs::read_to_string(&package_json_path)
+        info!("Installing {} from tarball {}", package.name, tarball_path);
+
+        let installed = Self::read_manifest(&manifest_path).and_then(|mut manifest| {
+            let target = Self::prepare_install_target(project_path, &mut manifest, &package.name)?;
+            Self::place_package(project_path, &target, |path| Self::move_from_staging(&staging_path, path))?;
+            Self::complete_install(project_path, &manifest_path, manifest, &package, target)
+        });
+
+        // Once the package has been moved into Packages there is nothing left in staging to discard
+        if installed.is_err() {
+            Self::discard_staging(project_path, &staging_path);
+        }
+
+        installed
+    }
+
+    fn is_git_reference(package_ref: &str) -> bool {
+        package_ref.starts_with("https://")
+            || package_ref.starts_with("git+")
+            || package_ref.starts_with("ssh://")
+            || package_ref.starts_with("git@")
+            || package_ref.contains(".git")
+    }
+
+    /// Lists manifest dependencies that Unity resolves from git, with the commit Unity locked them to.
+    pub fn get_git_dependencies(project_path: &str) -> Result<Vec<(String, GitDependency, Option<String>)>> {
+        let packages_dir = Path::new(project_path).join("Packages");
+        let manifest_path = packages_dir.join("manifest.json");
+
+        if !manifest_path.exists() {
+            return Err(CupmError::manifest_not_found(&manifest_path).into());
+        }
+
+        let content = fs::read_to_string(&manifest_path)
+            .context("Failed to read manifest.json")?;
+
+        let manifest: Value = serde_json::from_str(&content)
+            .context("Failed to parse manifest.json")?;
+
+        let lock = Self::read_packages_lock(&packages_dir)?;
+        let mut git_dependencies = Vec::new();
+
+        if let Some(dependencies) = manifest.get("dependencies").and_then(|d| d.as_object()) {
+            for (name, value) in dependencies {
+                let package_ref = match value.as_str() {
+                    Some(package_ref) if Self::is_git_reference(package_ref) => package_ref,
+                    _ => continue,
+                };
+
+                let locked_commit = lock.as_ref()
+                    .and_then(|l| l.get("dependencies"))
+                    .and_then(|d| d.get(name))
+                    .and_then(|entry| entry.get("hash"))
+                    .and_then(|h| h.as_str())
+                    .map(|h| h.to_string());
+
+                git_dependencies.push((name.clone(), GitOperations::parse_dependency_url(package_ref), locked_commit));
+            }
+        }
+
+        Ok(git_dependencies)
+    }
+
+    fn read_packages_lock(packages_dir: &Path) -> Result<Option<Value>> {
+        let lock_path = packages_dir.join("packages-lock.json");
+        if !lock_path.exists() {
+            return Ok(None);
+        }
+
+        let content = fs::read_to_string(&lock_path)
+            .context("Failed to read packages-lock.json")?;
+
+        let lock = serde_json::from_str(&content)
+            .context("Failed to parse packages-lock.json")?;
+
+        Ok(Some(lock))
+    }
+
+    /// Converts a Unity-managed git dependency into a CUPM embedded install at the exact same revision.
+    /// The commit from packages-lock.json is preferred so the conversion never silently changes versions.
+    pub fn adopt_git_dependency(project_path: &str, package: &Package) -> Result<InstallResult> {
+        let (_, dependency, locked_commit) = Self::get_git_dependencies(project_path)?
+            .into_iter()
+            .find(|(name, _, _)| name == &package.name)
+            .with_context(|| format!("Package {} is not a git dependency in manifest.json", package.name))?;
+
+        let packages_dir = Path::new(project_path).join("Packages");
+        let manifest_path = packages_dir.join("manifest.json");
+
+        let staging_path = Self::get_staging_path(project_path);
+
+        info!("Adopting git dependency {} ({}) at {}", package.name, dependency.url,
+            locked_commit.as_deref().or(dependency.reference.as_deref()).unwrap_or("default branch"));
+
+        let staged = Self::stage_git_dependency(&staging_path, &dependency, locked_commit.as_deref()).and_then(|_| {
+            let package_root = match &dependency.path {
+                Some(subfolder) => staging_path.join(subfolder),
+                None => staging_path.clone(),
+            };
+            let version = GitOperations::get_current_version_from_package_json(&package_root).ok();
+            Self::check_before_install(project_path, &package.name, &staging_path, version.as_deref(), package.registry_id.as_deref())
+        });
+        if let Err(e) = staged {
+            Self::discard_staging(project_path, &staging_path);
+            return Err(e);
+        }
+
+        let installed = Self::classify_adopted_revision(package, &dependency).and_then(|adopted| {
+            let mut manifest = Self::read_manifest(&manifest_path)?;
+            let mut target = Self::prepare_install_target(project_path, &mut manifest, &package.name)?;
+            target.subfolder = dependency.path.clone();
+            Self::place_package(project_path, &target, |path| Self::move_from_staging(&staging_path, path))?;
+            Self::complete_install(project_path, &manifest_path, manifest, &adopted, target)
+        });
+
+        // Once the package has been moved into Packages there is nothing left in staging to discard
+        if installed.is_err() {
+            Self::discard_staging(project_path, &staging_path);
+        }
+
+        installed
+    }
+
+    /// Unity revisions can be a tag, a branch or a commit. A commit is kept as neither,
+    /// provenance records it from the checked out HEAD.
+    fn classify_adopted_revision(package: &Package, dependency: &GitDependency) -> Result<Package> {
+        let mut adopted = package.clone();
+        adopted.git_url = dependency.url.clone();
+        adopted.git_tag = None;
+        adopted.git_branch = None;
+
+        if let Some(reference) = &dependency.reference {
+            let remote_refs = GitOperations::list_remote_refs(&dependency.url)
+                .with_context(|| format!("Failed to look up revision {} of {}", reference, dependency.url))?;
+            let has_ref = |kind: &str| remote_refs.iter().any(|r| r.kind == kind && &r.name == reference);
+
+            // Same order as the checkout in stage_git_dependency
+            if has_ref("tag") {
+                adopted.git_tag = Some(reference.clone());
+            } else if has_ref("branch") {
+                adopted.git_branch = Some(reference.clone());
+            }
+        }
+
+        Ok(adopted)
+    }
+
+    /// Replaces a CUPM embedded package with the equivalent git dependency so Unity resolves it itself.
+    /// Refuses when the embedded copy has local changes, since those would be lost. Returns the new manifest entry.
+    pub fn convert_to_git_dependency(project_path: &str, package_name: &str) -> Result<String> {
+        let packages_dir = Path::new(project_path).join("Packages");
+        let manifest_path = packages_dir.join("manifest.json");
+
+        let current_ref = Self::check_package_conflicts(project_path, package_name)?.unwrap_or_default();
+        let file_ref = current_ref.strip_prefix("file:").unwrap_or("");
+        if Self::get_file_reference_source(file_ref) != "embedded" {
+            return Err(CupmError::PackageNotEmbedded {
+                package_name: package_name.to_string(),
+                reference: current_ref,
+            }.into());
+        }
+
+        let package_path = Self::get_package_path(project_path, package_name)?;
+        let repository_path = Self::get_package_repository_path(project_path, package_name)?;
+
+        let git_url = GitOperations::get_origin_url(&repository_path)
+            .with_context(|| format!("Package {} is not a git repository with an origin remote", package_name))?;
+
+        let modifications = GitOperations::get_local_modifications(&repository_path)?;
+        if !modifications.is_empty() {
+            return Err(anyhow::Error::from(CupmError::LocalChanges {
+                package_name: package_name.to_string(),
+                files: modifications,
+            }).context(format!("Package {} has local modifications, commit or discard them first", package_name)));
+        }
+
+        if PatchManager::get_patch_path(project_path, package_name).exists() {
+            return Err(anyhow::anyhow!(
+                "Package {} has a local patch, which Unity can't apply to git dependencies", package_name
+            ));
+        }
+
+        // Prefer the tag it was installed from when it still matches, then any tag on the commit, then the commit itself
+        let head_commit = GitOperations::get_head_commit(&repository_path)?;
+        let provenance = ProvenanceStore::get(project_path, package_name)?;
+        let installed_tag = provenance
+            .and_then(|p| p.git_ref)
+            .filter(|git_ref| GitOperations::tag_points_at_head(&repository_path, git_ref));
+
+        let revision = installed_tag
+            .or_else(|| GitOperations::get_head_tag(&repository_path))
+            .unwrap_or(head_commit);
+
+        let subfolder = package_path.strip_prefix(&repository_path)
+            .ok()
+            .map(|p| p.to_string_lossy().replace('\\', "/"))
+            .filter(|p| !p.is_empty());
+
+        let dependency_url = GitOperations::to_dependency_url(&git_url);
+        let git_reference = match subfolder {
+            Some(subfolder) => format!("{}?path=/{}#{}", dependency_url, subfolder, revision),
+            None => format!("{}#{}", dependency_url, revision),
+        };
+
+        let content = fs::read_to_string(&manifest_path)
+            .context("Failed to read manifest.json")?;
+
+        let mut manifest: Value = serde_json::from_str(&content)
+            .context("Failed to parse manifest.json")?;
+
+        manifest["dependencies"][package_name] = Value::String(git_reference.clone());
+
+        let updated_content = serde_json::to_string_pretty(&manifest)
+            .context("Failed to serialize manifest.json")?;
+
+        fs::write(&manifest_path, updated_content)
+            .context("Failed to write manifest.json")?;
+
+        info!("Removing embedded package directory: {}", repository_path.display());
+        PathGuard::remove_dir_all(&packages_dir, &repository_path, "remove package directory")?;
+
+        ProvenanceStore::remove(project_path, package_name)?;
+
+        info!("Converted {} to git dependency {}", package_name, git_reference);
+        Ok(git_reference)
+    }
+
+    fn stage_git_dependency(staging_path: &Path, dependency: &GitDependency, locked_commit: Option<&str>) -> Result<()> {
+        GitOperations::clone_repository(&dependency.url, staging_path)
+            .with_context(|| format!("Failed to clone repository {}", dependency.url))?;
+
+        if let Some(commit) = locked_commit {
+            GitOperations::checkout_commit(staging_path, commit)
+                .with_context(|| format!("Failed to checkout locked commit: {}", commit))?;
+        } else if let Some(reference) = &dependency.reference {
+            // Unity revisions can be a tag, a branch or a commit
+            GitOperations::checkout_tag(staging_path, reference)
+                .or_else(|_| GitOperations::checkout_branch(staging_path, reference))
+                .or_else(|_| GitOperations::checkout_commit(staging_path, reference))
+                .with_context(|| format!("Failed to checkout revision: {}", reference))?;
+        }
+
+        let package_root = match &dependency.path {
+            Some(path) => staging_path.join(path),
+            None => staging_path.to_path_buf(),
+        };
+
+        Self::read_package_json(&package_root, &dependency.url)?;
+        Ok(())
+    }
+
+    fn record_provenance(project_path: &str, package: &Package, version: &str, package_path: Option<&Path>) -> Result<()> {
+        let provenance = PackageProvenance {
+            package_name: package.name.clone(),
+            registry_id: package.registry_id.clone(),
+            version: version.to_string(),
+            git_url: package.git_url.clone(),
+            git_ref: package.git_tag.clone().or_else(|| package.git_branch.clone()),
+            commit: package_path.and_then(|path| GitOperations::get_head_commit(path).ok()),
+            installed_at: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+            tracking_branch: if package.git_tag.is_none() { package.git_branch.clone() } else { None },
+            content_hash: package_path.and_then(|path| IntegrityChecker::hash_tree(path).ok()),
+            registry_content_hash: package.content_hash.clone(),
+        };
+
+        ProvenanceStore::record(project_path, provenance)
+            .with_context(|| format!("Failed to record provenance for {}", package.name))?;
+
+        // A fresh install replaces any developer link the package had
+        LinkStore::remove(project_path, &package.name)
+    }
+
+    /// Rebuilds the registry package a recorded install came from, as far as provenance knows it.
+    fn package_from_provenance(provenance: &PackageProvenance) -> Package {
+        let (git_tag, git_branch) = match &provenance.tracking_branch {
+            Some(branch) => (None, Some(branch.clone())),
+            None => (provenance.git_ref.clone(), None),
+        };
+
+        Package {
+            name: provenance.package_name.clone(),
+            display_name: provenance.package_name.clone(),
+            description: String::new(),
+            git_url: provenance.git_url.clone(),
+            author: None,
+            keywords: None,
+            category: None,
+            license: None,
+            versions: None,
+            version: Some(provenance.version.clone()),
+            git_branch,
+            git_tag,
+            dependencies: None,
+            unity_version: None,
+            is_prerelease: false,
+            registry_id: provenance.registry_id.clone(),
+            content_hash: None,
+        }
+    }
+
+    /// Reinstalls a package exactly as recorded, pinned to the recorded commit when there is one.
+    pub fn reinstall_from_provenance(project_path: &str, provenance: &PackageProvenance) -> Result<InstallResult> {
+        if provenance.git_url.is_empty() {
+            return Err(anyhow::anyhow!(
+                "Package {} was not installed from git and can't be reinstalled automatically", provenance.package_name
+            ));
+        }
+
+        info!("Reinstalling {} from {} ({})", provenance.package_name, provenance.git_url,
+            provenance.commit.as_deref().or(provenance.git_ref.as_deref()).unwrap_or("default branch"));
+
+        let result = match (&provenance.registry_id, &provenance.commit) {
+            // Registry packages go through the registry's trust, content hash and advisory checks again
+            (Some(_), commit) => {
+                let package = Package {
+                    content_hash: provenance.registry_content_hash.clone(),
+                    ..Self::package_from_provenance(provenance)
+                };
+                Self::install_registry_package(project_path, &package, commit.as_deref())?
+            }
+            (None, Some(commit)) => Self::install_from_git_url(project_path, &provenance.git_url, None, None, Some(commit))?,
+            (None, None) => Self::install_package(project_path, &Self::package_from_provenance(provenance))?,
+        };
+
+        if result.package_name != provenance.package_name {
+            return Err(anyhow::anyhow!(
+                "Reinstalled package is named {} instead of {}", result.package_name, provenance.package_name
+            ));
+        }
+
+        // Keep the original registry and ref rather than what a commit install records
+        let mut restored = provenance.clone();
+        restored.installed_at = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        ProvenanceStore::record(project_path, restored)?;
+
+        Ok(result)
+    }
+
+    /// Points a project's dependency at a local working copy, either through an absolute `file:` path
+    /// or a symlink inside Packages. The previous install is remembered so `unlink_package` can restore it.
+    pub fn link_package(project_path: &str, source_path: &str, use_symlink: bool) -> Result<InstallResult> {
+        let packages_dir = Path::new(project_path).join("Packages");
+        let manifest_path = packages_dir.join("manifest.json");
+
+        if !manifest_path.exists() {
+            return Err(CupmError::manifest_not_found(&manifest_path).into());
+        }
+
+        let source_path = fs::canonicalize(source_path)
+            .with_context(|| format!("Package folder not found: {}", source_path))?;
+
+        let package = Self::read_package_json(&source_path, "")?;
+
+        let content = fs::read_to_string(&manifest_path)
+            .context("Failed to read manifest.json")?;
+
+        let mut manifest: Value = serde_json::from_str(&content)
+            .context("Failed to parse manifest.json")?;
+
+        // Relinking keeps what was there before the first link
+        let (previous_entry, previous_provenance) = match LinkStore::get(project_path, &package.name)? {
+            Some(link) => (link.previous_entry, link.previous_provenance),
+            None => (
+                manifest.get("dependencies").and_then(|d| d.get(&package.name)).and_then(|v| v.as_str()).map(|s| s.to_string()),
+                ProvenanceStore::get(project_path, &package.name)?,
+            ),
+        };
+
+        let target = Self::prepare_install_target(project_path, &mut manifest, &package.name)?;
+
+        let reference = if use_symlink {
+            info!("Linking {} to {}", target.path.display(), source_path.display());
+            Self::place_package(project_path, &target, |path| Self::create_dir_symlink(&source_path, path))?;
+            format!("file:{}", target.dir_name)
+        } else {
+            Self::place_package(project_path, &target, |_| Ok(()))?;
+            format!("file:{}", source_path.to_string_lossy().replace('\\', "/"))
+        };
+
+        manifest["dependencies"][&package.name] = Value::String(reference.clone());
+
+        let updated_content = serde_json::to_string_pretty(&manifest)
+            .context("Failed to serialize manifest.json")?;
+
+        fs::write(&manifest_path, updated_content)
+            .context("Failed to write manifest.json")?;
+
+        ProvenanceStore::remove(project_path, &package.name)?;
+        LinkStore::record(project_path, PackageLink {
+            package_name: package.name.clone(),
+            source_path: source_path.to_string_lossy().to_string(),
+            mode: if use_symlink { "symlink" } else { "path" }.to_string(),
+            previous_entry,
+            previous_provenance,
+            linked_at: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+        })?;
+
+        let version = package.version.unwrap_or_else(|| "unknown".to_string());
+        info!("Linked package {} (version: {}) as {}", package.name, version, reference);
+
+        Ok(InstallResult {
+            package_name: package.name,
+            version,
+            patch: None,
+            advisories: Vec::new(),
+        })
+    }
+
+    /// Removes a developer link and restores whatever was installed before it.
+    pub fn unlink_package(project_path: &str, package_name: &str) -> Result<Option<InstallResult>> {
+        let link = LinkStore::get(project_path, package_name)?
+            .with_context(|| format!("Package {} is not linked", package_name))?;
+
+        let packages_dir = Path::new(project_path).join("Packages");
+        let manifest_path = packages_dir.join("manifest.json");
+
+        Self::remove_link(project_path, package_name)?;
+
+        if let Some(provenance) = &link.previous_provenance {
+            if !provenance.git_url.is_empty() {
+                return Self::reinstall_from_provenance(project_path, provenance).map(Some);
+            }
+        }
+
+        let content = fs::read_to_string(&manifest_path)
+            .context("Failed to read manifest.json")?;
+
+        let mut manifest: Value = serde_json::from_str(&content)
+            .context("Failed to parse manifest.json")?;
+
+        // Embedded copies without provenance can't be brought back, so their entry is dropped too
+        let restorable_entry = link.previous_entry.filter(|entry| {
+            entry.strip_prefix("file:")
+                .map(|file_ref| Self::read_local_package_json(&packages_dir, file_ref).is_some())
+                .unwrap_or(true)
+        });
+
+        if let Some(dependencies) = manifest.get_mut("dependencies").and_then(|d| d.as_object_mut()) {
+            match &restorable_entry {
+                Some(entry) => {
+                    dependencies.insert(package_name.to_string(), Value::String(entry.clone()));
+                }
+                None => {
+                    dependencies.remove(package_name);
+                }
+            }
+        }
+
+        let updated_content = serde_json::to_string_pretty(&manifest)
+            .context("Failed to serialize manifest.json")?;
+
+        fs::write(&manifest_path, updated_content)
+            .context("Failed to write manifest.json")?;
+
+        match restorable_entry {
+            Some(entry) => info!("Unlinked {}, restored {}", package_name, entry),
+            None => info!("Unlinked {}, nothing to restore so it was removed", package_name),
+        }
+
+        Ok(None)
+    }
+
+    /// Removes a package's symlink from Packages, if it has one, and forgets the link. The manifest entry is left as is.
+    fn remove_link(project_path: &str, package_name: &str) -> Result<()> {
+        let packages_dir = Path::new(project_path).join("Packages");
+
+        if let Ok(package_path) = Self::get_package_path(project_path, package_name) {
+            // Only the link itself is removed, never the working copy it points at
+            if Self::is_symlink(&package_path) {
+                info!("Removing package link: {}", package_path.display());
+                PathGuard::remove_dir_all(&packages_dir, &package_path, "remove package link")?;
+            }
+        }
+
+        LinkStore::remove(project_path, package_name)
+    }
+
+    fn create_dir_symlink(source: &Path, link: &Path) -> Result<()> {
+        #[cfg(windows)]
+        let result = std::os::windows::fs::symlink_dir(source, link);
+
+        #[cfg(not(windows))]
+        let result = std::os::unix::fs::symlink(source, link);
+
+        result.with_context(|| {
+            format!(
+                "Failed to create symlink {} -> {} (on Windows this needs Developer Mode or administrator rights)",
+                link.display(),
+                source.display()
+            )
+        })
+    }
+
+    fn is_symlink(path: &Path) -> bool {
+        fs::symlink_metadata(path)
+            .map(|m| m.file_type().is_symlink())
+            .unwrap_or(false)
+    }
+
+    /// Lists embedded git packages without provenance, along with their origin URL, so they can be adopted.
+    pub fn get_unmanaged_git_packages(project_path: &str) -> Result<Vec<(String, String)>> {
+        let manifest_path = Path::new(project_path).join("Packages").join("manifest.json");
+        if !manifest_path.exists() {
+            return Ok(Vec::new());
+        }
+
+        let provenance = ProvenanceStore::load(project_path)?;
+        let links = LinkStore::load(project_path)?;
+        let mut unmanaged = Vec::new();
+
+        for package in Self::parse_manifest(&manifest_path)? {
+            let is_tracked = provenance.contains_key(&package.name) || links.contains_key(&package.name);
+            if package.source != "embedded" || is_tracked {
+                continue;
+            }
+
+            let package_path = Self::get_package_path(project_path, &package.name)?;
+            if let Some(origin_url) = GitOperations::get_origin_url(&package_path) {
+                unmanaged.push((package.name, origin_url));
+            }
+        }
+
+        Ok(unmanaged)
+    }
+
+    /// Records provenance for an embedded git package that was installed before CUPM tracked it.
+    pub fn adopt_package(project_path: &str, package_name: &str, registry_id: &str) -> Result<()> {
+        let package_path = Self::get_package_path(project_path, package_name)?;
+
+        let git_url = GitOperations::get_origin_url(&package_path)
+            .with_context(|| format!("Package {} is not a git repository", package_name))?;
+
+        let version = GitOperations::get_current_version_from_package_json(&package_path)?;
+
+        info!("Adopting existing package {} from registry {}", package_name, registry_id);
+
+        ProvenanceStore::record(project_path, PackageProvenance {
+            package_name: package_name.to_string(),
+            registry_id: Some(registry_id.to_string()),
+            version,
+            git_url,
+            git_ref: GitOperations::get_head_tag(&package_path),
+            commit: GitOperations::get_head_commit(&package_path).ok(),
+            installed_at: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+            tracking_branch: None,
+            content_hash: IntegrityChecker::hash_tree(&package_path).ok(),
+            registry_content_hash: None,
+        })
+    }
+
+    /// Checks every `file:` dependency for problems `get_project_info` would otherwise only report as
+    /// "local" or "unknown": missing folders, missing or unreadable package.json files and mismatched names.
+    pub fn diagnose_project(project_path: &str) -> Result<Vec<ProjectIssue>> {
+        let packages_dir = Path::new(project_path).join("Packages");
+        let manifest_path = packages_dir.join("manifest.json");
+
+        if !manifest_path.exists() {
+            return Err(CupmError::manifest_not_found(&manifest_path).into());
+        }
+
+        let content = fs::read_to_string(&manifest_path)
+            .context("Failed to read manifest.json")?;
+
+        let manifest: Value = serde_json::from_str(&content)
+            .context("Failed to parse manifest.json")?;
+
+        let mut issues = Vec::new();
+
+        if let Some(dependencies) = manifest.get("dependencies").and_then(|d| d.as_object()) {
+            for (name, value) in dependencies {
+                if let Some(file_ref) = value.as_str().and_then(|v| v.strip_prefix("file:")) {
+                    if let Some(issue) = Self::diagnose_file_dependency(&packages_dir, name, file_ref) {
+                        info!("Found problem with {}: {}", name, issue.message);
+                        issues.push(issue);
+                    }
+                }
+            }
+        }
+
+        Ok(issues)
+    }
+
+    fn diagnose_file_dependency(packages_dir: &Path, package_name: &str, file_ref: &str) -> Option<ProjectIssue> {
+        let package_path = packages_dir.join(file_ref);
+        let issue = |kind: &str, message: String, actual_name: Option<String>| ProjectIssue {
+            package_name: package_name.to_string(),
+            kind: kind.to_string(),
+            message,
+            path: package_path.to_string_lossy().to_string(),
+            actual_name,
+        };
+
+        let exists = if Self::is_tarball_reference(file_ref) { package_path.is_file() } else { package_path.is_dir() };
+        if !exists {
+            return Some(issue("missing_path", format!("{} does not exist", package_path.display()), None));
+        }
+
+        match Self::read_local_package_json(packages_dir, file_ref) {
+            None => Some(issue("missing_package_json", format!("No package.json in {}", package_path.display()), None)),
+            Some(Err(e)) => Some(issue("invalid_package_json", format!("Unreadable package.json: {}", e), None)),
+            Some(Ok(package_data)) => match package_data.get("name").and_then(|n| n.as_str()) {
+                None => Some(issue("invalid_package_json", "package.json has no name".to_string(), None)),
+                Some(actual_name) if actual_name != package_name => Some(issue(
+                    "name_mismatch",
+                    format!("package.json names the package {}", actual_name),
+                    Some(actual_name.to_string()),
+                )),
+                Some(_) => None,
+            },
+        }
+    }
+
+    /// Fixes what `diagnose_project` finds where that can be done safely: missing packages are re-cloned from
+    /// their recorded provenance, dangling entries are removed and manifest keys are renamed to match package.json.
+    /// Folders that exist but can't be read are left alone and reported as unresolved.
+    pub fn repair_project(project_path: &str) -> Result<RepairReport> {
+        let issues = Self::diagnose_project(project_path)?;
+        let provenance = ProvenanceStore::load(project_path)?;
+        let links = LinkStore::load(project_path)?;
+
+        let mut report = RepairReport {
+            reinstalled: Vec::new(),
+            removed: Vec::new(),
+            renamed: Vec::new(),
+            unresolved: Vec::new(),
+        };
+        let mut dangling = Vec::new();
+        let mut renames = Vec::new();
+
+        for mut issue in issues {
+            let name = issue.package_name.clone();
+
+            match issue.kind.as_str() {
+                "missing_path" | "missing_package_json" if links.contains_key(&name) => {
+                    // The working copy of a link is gone, fall back to what was there before
+                    match Self::unlink_package(project_path, &name) {
+                        Ok(Some(_)) => report.reinstalled.push(name),
+                        Ok(None) => report.removed.push(name),
+                        Err(e) => {
+                            issue.message = format!("{} (unlink failed: {})", issue.message, e);
+                            report.unresolved.push(issue);
+                        }
+                    }
+                }
+                "missing_path" | "missing_package_json" if provenance.get(&name).is_some_and(|p| !p.git_url.is_empty()) => {
+                    match Self::reinstall_from_provenance(project_path, &provenance[&name]) {
+                        Ok(_) => report.reinstalled.push(name),
+                        Err(e) => {
+                            issue.message = format!("{} (reinstall failed: {})", issue.message, e);
+                            report.unresolved.push(issue);
+                        }
+                    }
+                }
+                "missing_path" => dangling.push(name),
+                "name_mismatch" => renames.push(issue),
+                _ => report.unresolved.push(issue),
+            }
+        }
+
+        if dangling.is_empty() && renames.is_empty() {
+            return Ok(report);
+        }
+
+        let manifest_path = Path::new(project_path).join("Packages").join("manifest.json");
+
+        let content = fs::read_to_string(&manifest_path)
+            .context("Failed to read manifest.json")?;
+
+        let mut manifest: Value = serde_json::from_str(&content)
+            .context("Failed to parse manifest.json")?;
+
+        let dependencies = manifest.get_mut("dependencies")
+            .and_then(|d| d.as_object_mut())
+            .context("manifest.json has no dependencies")?;
+
+        for name in dangling {
+            info!("Removing dangling manifest entry: {}", name);
+            dependencies.remove(&name);
+            ProvenanceStore::remove(project_path, &name)?;
+            report.removed.push(name);
+        }
+
+        for issue in renames {
+            let old_name = issue.package_name.clone();
+            let new_name = issue.actual_name.clone().unwrap_or_default();
+
+            if let Err(e) = Self::validate_package_name(&new_name) {
+                report.unresolved.push(ProjectIssue { message: format!("{} ({})", issue.message, e), ..issue });
+                continue;
+            }
+
+            if dependencies.contains_key(&new_name) {
+                report.unresolved.push(ProjectIssue {
+                    message: format!("{}, which the manifest already lists separately", issue.message),
+                    ..issue
+                });
+                continue;
+            }
+
+            info!("Renaming manifest entry {} to {}", old_name, new_name);
+            if let Some(value) = dependencies.remove(&old_name) {
+                dependencies.insert(new_name.clone(), value);
+            }
+
+            // Keep CUPM's own records attached to the package
+            ProvenanceStore::rename(project_path, &old_name, &new_name)?;
+            LinkStore::rename(project_path, &old_name, &new_name)?;
+
+            let old_patch = PatchManager::get_patch_path(project_path, &old_name);
+            if old_patch.exists() {
+                fs::rename(&old_patch, PatchManager::get_patch_path(project_path, &new_name))
+                    .with_context(|| format!("Failed to rename patch: {}", old_patch.display()))?;
+            }
+
+            report.renamed.push(format!("{} -> {}", old_name, new_name));
+        }
+
+        let updated_content = serde_json::to_string_pretty(&manifest)
+            .context("Failed to serialize manifest.json")?;
+
+        fs::write(&manifest_path, updated_content)
+            .context("Failed to write manifest.json")?;
+
+        info!("Repaired project: {} reinstalled, {} removed, {} renamed, {} unresolved",
+            report.reinstalled.len(), report.removed.len(), report.renamed.len(), report.unresolved.len());
+
+        Ok(report)
+    }
+
+    /// Lists folders in Packages that contain a package.json but aren't referenced by manifest.json.
+    /// Unity still imports these as embedded packages, usually left behind by renamed or failed installs.
+    pub fn find_orphaned_packages(project_path: &str) -> Result<Vec<OrphanedPackage>> {
+        let packages_dir = Path::new(project_path).join("Packages");
+        let manifest_path = packages_dir.join("manifest.json");
+
+        if !manifest_path.exists() {
+            return Err(CupmError::manifest_not_found(&manifest_path).into());
+        }
+
+        let content = fs::read_to_string(&manifest_path)
+            .context("Failed to read manifest.json")?;
+
+        let manifest: Value = serde_json::from_str(&content)
+            .context("Failed to parse manifest.json")?;
+
+        let dependencies = manifest.get("dependencies").and_then(|d| d.as_object());
+
+        // Embedded references may point into a subfolder, the folder directly in Packages is what counts
+        let referenced: Vec<String> = dependencies
+            .map(|deps| {
+                deps.values()
+                    .filter_map(|v| v.as_str())
+                    .filter_map(|v| v.strip_prefix("file:"))
+                    .filter(|file_ref| Self::get_file_reference_source(file_ref) == "embedded")
+                    .filter_map(Self::get_install_folder_name)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let mut orphans = Vec::new();
+
+        for entry in fs::read_dir(&packages_dir).context("Failed to read Packages directory")? {
+            let path = entry?.path();
+            let folder_name = match path.file_name().and_then(|n| n.to_str()) {
+                Some(name) => name.to_string(),
+                None => continue,
+            };
+
+            if !path.is_dir() || referenced.contains(&folder_name) {
+                continue;
+            }
+
+            let package_json_path = path.join("package.json");
+            if !package_json_path.exists() {
+                continue;
+            }
+
+            let package_data: Option<Value> = fs::read_to_string(&package_json_path)
+                .ok()
+                .and_then(|content| serde_json::from_str(&content).ok());
+
+            let package_name = package_data.as_ref()
+                .and_then(|data| data.get("name"))
+                .and_then(|n| n.as_str())
+                .map(|n| n.to_string());
+
+            let version = package_data.as_ref()
+                .and_then(|data| data.get("version"))
+                .and_then(|v| v.as_str())
+                .map(|v| v.to_string());
+
+            let manifest_entry = package_name.as_ref()
+                .and_then(|name| dependencies.and_then(|deps| deps.get(name)))
+                .and_then(|v| v.as_str())
+                .map(|v| v.to_string());
+
+            // Linked working copies aren't ours to measure, only the link itself lives in Packages
+            let size = if Self::is_symlink(&path) { 0 } else { Self::get_dir_size(&path) };
+
+            orphans.push(OrphanedPackage {
+                folder_name,
+                path: path.to_string_lossy().to_string(),
+                package_name,
+                version,
+                size,
+                manifest_entry,
+            });
+        }
+
+        orphans.sort_by(|a, b| a.folder_name.cmp(&b.folder_name));
+        info!("Found {} orphaned package folder(s) in {}", orphans.len(), packages_dir.display());
+
+        Ok(orphans)
+    }
+
+    /// Deletes a folder reported by `find_orphaned_packages`. Folders the manifest references are refused.
+    pub fn delete_orphaned_package(project_path: &str, folder_name: &str) -> Result<()> {
+        let orphan = Self::find_orphan(project_path, folder_name)?;
+        let path = Path::new(&orphan.path);
+
+        info!("Deleting orphaned package folder: {}", path.display());
+
+        let packages_dir = Path::new(project_path).join("Packages");
+        PathGuard::remove_dir_all(&packages_dir, path, "delete orphaned package folder")
+    }
+
+    /// Adds a manifest entry for a folder reported by `find_orphaned_packages`, using the name from its package.json.
+    pub fn register_orphaned_package(project_path: &str, folder_name: &str) -> Result<String> {
+        let orphan = Self::find_orphan(project_path, folder_name)?;
+
+        let package_name = orphan.package_name
+            .with_context(|| format!("package.json in {} has no usable name", folder_name))?;
+
+        Self::validate_package_name(&package_name)?;
+
+        if let Some(entry) = orphan.manifest_entry {
+            return Err(anyhow::anyhow!(
+                "Package {} is already installed as {}, remove it before registering {}",
+                package_name, entry, folder_name
+            ));
+        }
+
+        let manifest_path = Path::new(project_path).join("Packages").join("manifest.json");
+
+        let content = fs::read_to_string(&manifest_path)
+            .context("Failed to read manifest.json")?;
+
+        let mut manifest: Value = serde_json::from_str(&content)
+            .context("Failed to parse manifest.json")?;
+
+        if manifest.get("dependencies").is_none() {
+            manifest["dependencies"] = serde_json::json!({});
+        }
+
+        manifest["dependencies"][&package_name] = Value::String(format!("file:{}", folder_name));
+
+        let updated_content = serde_json::to_string_pretty(&manifest)
+            .context("Failed to serialize manifest.json")?;
+
+        fs::write(&manifest_path, updated_content)
+            .context("Failed to write manifest.json")?;
+
+        info!("Registered orphaned folder {} as {}", folder_name, package_name);
+
+        Ok(package_name)
+    }
+
+    fn find_orphan(project_path: &str, folder_name: &str) -> Result<OrphanedPackage> {
+        Self::find_orphaned_packages(project_path)?
+            .into_iter()
+            .find(|orphan| orphan.folder_name == folder_name)
+            .with_context(|| format!("{} is not an orphaned package folder", folder_name))
+    }
+
+    fn get_dir_size(path: &Path) -> u64 {
+        let entries = match fs::read_dir(path) {
+            Ok(entries) => entries,
+            Err(_) => return 0,
+        };
+
+        entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let metadata = fs::symlink_metadata(entry.path()).ok()?;
+                Some(if metadata.is_dir() { Self::get_dir_size(&entry.path()) } else { metadata.len() })
+            })
+            .sum()
+    }
+
+    fn is_tarball_reference(file_ref: &str) -> bool {
+        let file_ref = file_ref.to_lowercase();
+        file_ref.ends_with(".tgz") || file_ref.ends_with(".tar.gz")
+    }
+
+    fn get_file_reference_source(file_ref: &str) -> &'static str {
+        if Self::is_tarball_reference(file_ref) {
+            return "tarball";
+        }
+
+        // Folders directly inside Packages are embedded, anything else lives elsewhere on disk
+        let path = Path::new(file_ref);
+        let is_embedded = !path.is_absolute()
+            && path.components().all(|c| matches!(c, std::path::Component::Normal(_) | std::path::Component::CurDir));
+
+        if is_embedded { "embedded" } else { "local" }
+    }
+
+    /// Reads the package.json of a `file:` dependency, which may be a folder or a tarball.
+    /// Relative references resolve against the Packages directory, as Unity does.
+    /// Returns None if there is no package.json to read.
+    fn read_local_package_json(packages_dir: &Path, file_ref: &str) -> Option<Result<Value>> {
+        let package_path = packages_dir.join(file_ref);
+
+        if Self::is_tarball_reference(file_ref) {
+            if !package_path.is_file() {
+                return None;
+            }
+            return Some(Self::read_tarball_package_json(&package_path));
+        }
+
+        let package_json_path = package_path.join("package.json");
+        if !package_json_path.exists() {
+            return None;
+        }
+
+        Some(
+            fs::read_to_string(&package_json_path)
+                .context("Failed to read package.json")
+                .and_then(|content| serde_json::from_str(&content).context("Failed to parse package.json")),
+        )
+    }
+
+    fn open_tarball(tarball_path: &Path) -> Result<tar::Archive<flate2::read::GzDecoder<fs::File>>> {
+        let file = fs::File::open(tarball_path)
+            .with_context(|| format!("Failed to open tarball: {}", tarball_path.display()))?;
+
+        Ok(tar::Archive::new(flate2::read::GzDecoder::new(file)))
+    }
+
+    /// Strips the single top level folder npm puts around a package ("package/") from an entry path.
+    /// Returns None for the top level folder itself and for paths trying to escape the package.
+    fn strip_tarball_root(entry_path: &Path) -> Option<PathBuf> {
+        let mut components = entry_path.components();
+        components.next()?;
+
+        let relative: PathBuf = components.collect();
+        let is_safe = relative.components().all(|c| matches!(c, std::path::Component::Normal(_)));
+
+        if relative.as_os_str().is_empty() || !is_safe {
+            return None;
+        }
+
+        Some(relative)
+    }
+
+    fn read_tarball_package_json(tarball_path: &Path) -> Result<Value> {
+        let mut archive = Self::open_tarball(tarball_path)?;
+
+        for entry in archive.entries().context("Failed to read tarball")? {
+            let mut entry = entry.context("Failed to read tarball entry")?;
+            let entry_path = entry.path().context("Invalid path in tarball")?.into_owned();
+
+            if Self::strip_tarball_root(&entry_path).as_deref() == Some(Path::new("package.json")) {
+                let mut content = String::new();
+                std::io::Read::read_to_string(&mut entry, &mut content)
+                    .context("Failed to read package.json from tarball")?;
+
+                return serde_json::from_str(&content)
+                    .context("Failed to parse package.json from tarball");
+            }
+        }
+
+        Err(CupmError::NotAUnityPackage { path: tarball_path.to_string_lossy().to_string() }.into())
+    }
+
+    fn extract_tarball(tarball_path: &Path, destination: &Path) -> Result<()> {
+        info!("Extracting {} to {}", tarball_path.display(), destination.display());
+
+        fs::create_dir_all(destination)
+            .with_context(|| format!("Failed to create directory: {}", destination.display()))?;
+
+        let mut archive = Self::open_tarball(tarball_path)?;
+
+        for entry in archive.entries().context("Failed to read tarball")? {
+            let mut entry = entry.context("Failed to read tarball entry")?;
+            let entry_path = entry.path().context("Invalid path in tarball")?.into_owned();
+
+            let relative = match Self::strip_tarball_root(&entry_path) {
+                Some(relative) => relative,
+                None => continue,
+            };
+
+            // A link could point anywhere, and later entries would then be written through it
+            let entry_type = entry.header().entry_type();
+            if entry_type.is_symlink() || entry_type.is_hard_link() {
+                return Err(SecurityError {
+                    kind: "archive_link".to_string(),
+                    operation: "extract tarball".to_string(),
+                    path: entry_path.to_string_lossy().to_string(),
+                    allowed_root: destination.to_string_lossy().to_string(),
+                    message: format!("Refusing to extract {}, package tarballs can't contain links", entry_path.display()),
+                }.into());
+            }
+            if !entry_type.is_file() && !entry_type.is_dir() {
+                debug!("Skipping {} ({:?}) in tarball", entry_path.display(), entry_type);
+                continue;
+            }
+
+            let output_path = destination.join(&relative);
+            if let Some(parent) = output_path.parent() {
+                fs::create_dir_all(parent)
+                    .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+            }
+            let output_path = PathGuard::confine(destination, &output_path, "extract tarball entry")?;
+
+            entry.unpack(&output_path)
+                .with_context(|| format!("Failed to extract {}", relative.display()))?;
+        }
+
+        Ok(())
+    }
+
+    /// Recursively copies a package folder, leaving out its git metadata.
+    fn copy_package_dir(source: &Path, destination: &Path) -> Result<()> {
+        fs::create_dir_all(destination)
+            .with_context(|| format!("Failed to create directory: {}", destination.display()))?;
+
+        for entry in fs::read_dir(source).with_context(|| format!("Failed to read {}", source.display()))? {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+            let target = destination.join(entry.file_name());
+
+            if entry.file_name() == ".git" {
+                continue;
+            }
+
+            if file_type.is_dir() {
+                Self::copy_package_dir(&entry.path(), &target)?;
+            } else {
+                fs::copy(entry.path(), &target)
+                    .with_context(|| format!("Failed to copy {}", entry.path().display()))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Builds a relative path from one absolute directory to another absolute path.
+    fn get_relative_path(from_dir: &Path, to: &Path) -> PathBuf {
+        let from: Vec<_> = from_dir.components().collect();
+        let to: Vec<_> = to.components().collect();
+
+        let common = from.iter().zip(to.iter()).take_while(|(a, b)| a == b).count();
+
+        let mut relative = PathBuf::new();
+        for _ in common..from.len() {
+            relative.push("..");
+        }
+        for component in &to[common..] {
+            relative.push(component);
+        }
+
+        relative
+    }
+
+    /// Updates an embedded package by fetching into its existing repository.
+    /// Falls back to a fresh install when the repository is missing, corrupt or points at a different remote.
+    pub fn update_package(project_path: &str, package: &Package) -> Result<InstallResult> {
+        Self::check_not_linked(project_path, &package.name)?;
+
+        let (ref_kind, reference) = match (&package.git_tag, &package.git_branch) {
+            (Some(tag), _) => ("tag", tag),
+            (None, Some(branch)) => ("branch", branch),
+            (None, None) => {
+                info!("No tag or branch to update {} to, reinstalling", package.name);
+                return Self::install_package(project_path, package);
+            }
+        };
+
+        if let Some(registry_id) = &package.registry_id {
+            RegistryTrust::check_package(registry_id, &package.name)?;
+        }
+
+        let packages_dir = Path::new(project_path).join("Packages");
+        let package_path = match Self::get_package_repository_path(project_path, &package.name) {
+            Ok(path) => path,
+            Err(e) => {
+                info!("Package {} is not embedded ({}), reinstalling", package.name, e);
+                return Self::install_package(project_path, package);
+            }
+        };
+
+        match GitOperations::get_origin_url(&package_path) {
+            Some(origin_url) if GitOperations::urls_match(&origin_url, &package.git_url) => {}
+            Some(origin_url) => {
+                info!("Existing repository points at {} instead of {}, reinstalling", origin_url, package.git_url);
+                return Self::install_package(project_path, package);
+            }
+            None => {
+                info!("No usable repository found at {}, reinstalling", package_path.display());
+                return Self::install_package(project_path, package);
+            }
+        }
+
+        Self::check_patch_covers_changes(project_path, &package.name, &package_path, "updating")?;
+
+        GitOperations::fetch_updates(&package_path)
+            .with_context(|| format!("Failed to fetch updates for {}", package.name))?;
+
+        let target_commit = GitOperations::resolve_reference(&package_path, ref_kind, reference)
+            .with_context(|| format!("Failed to find {} {} of {}", ref_kind, reference, package.name))?;
+
+        // The new commit is checked in staging, the installed package isn't touched until it has passed
+        let staging_path = Self::get_staging_path(project_path);
+        let checked = GitOperations::export_commit(&package_path, &target_commit, &staging_path).and_then(|_| {
+            Self::verify_content_hash(package, &staging_path)?;
+            let new_version = GitOperations::get_current_version_from_package_json(&staging_path).ok();
+            Self::check_before_install(project_path, &package.name, &staging_path, new_version.as_deref(), package.registry_id.as_deref())
+        });
+        Self::discard_staging(project_path, &staging_path);
+        checked?;
+
+        GitOperations::checkout_commit(&package_path, &target_commit)
+            .with_context(|| format!("Failed to checkout {}", target_commit))?;
+
+        // The hard reset keeps untracked files around, clear them so patches re-apply onto a pristine tree
+        GitOperations::remove_untracked_files(&packages_dir, &package_path)
+            .with_context(|| format!("Failed to clean package directory: {}", package_path.display()))?;
+
+        let actual_version = GitOperations::get_current_version_from_package_json(&package_path)
+            .unwrap_or_else(|e| {
+                warn!("Could not read version from package.json: {}", e);
+                "unknown".to_string()
+            });
+
+        let patch = PatchManager::apply_patch(project_path, &package.name, &package_path)
+            .with_context(|| format!("Failed to apply local patch for {}", package.name))?;
+
+        Self::record_provenance(project_path, package, &actual_version, Some(&package_path))?;
+
+        info!("Successfully updated package {} in place (version: {})", package.name, actual_version);
+
+        Ok(InstallResult {
+            package_name: package.name.clone(),
+            version: actual_version,
+            patch,
+            advisories: Vec::new(),
+        })
+    }
+
+    /// Refuses git operations on a package that is linked to a developer's working copy, through the link store
+    /// or a symlink made by hand, since fetching, resetting and cleaning would happen in that working copy.
+    fn check_not_linked(project_path: &str, package_name: &str) -> Result<()> {
+        let source_path = match LinkStore::get(project_path, package_name)? {
+            Some(link) => link.source_path,
+            None => match Self::get_package_repository_path(project_path, package_name) {
+                Ok(package_path) if Self::is_symlink(&package_path) => fs::read_link(&package_path)
+                    .unwrap_or(package_path)
+                    .to_string_lossy()
+                    .to_string(),
+                _ => return Ok(()),
+            },
+        };
+
+        Err(CupmError::PackageLinked { package_name: package_name.to_string(), source_path }.into())
+    }
+
+    /// Refuses to go on when an embedded package has edits its saved patch doesn't capture exactly,
+    /// since checking out another commit would lose them.
+    fn check_patch_covers_changes(project_path: &str, package_name: &str, package_path: &Path, action: &str) -> Result<()> {
+        let modifications = GitOperations::get_local_modifications(package_path)?;
+
+        if modifications.is_empty() || PatchManager::matches_saved_patch(project_path, package_name, package_path)? {
+            return Ok(());
+        }
+
+        Err(anyhow::Error::from(CupmError::LocalChanges {
+            package_name: package_name.to_string(),
+            files: modifications,
+        }).context(format!("Package {} has local changes that aren't in its saved patch, save them as a patch before {}", package_name, action)))
+    }
+
+    /// Fetches the branch a tracking install follows and reports how far the installed copy is behind it.
+    pub fn get_branch_status(project_path: &str, package_name: &str) -> Result<BranchStatus> {
+        let (provenance, branch, package_path) = Self::get_tracking_install(project_path, package_name)?;
+
+        GitOperations::fetch_updates(&package_path)
+            .with_context(|| format!("Failed to fetch updates for {}", package_name))?;
+
+        let (ahead, behind, incoming_commits) = GitOperations::compare_with_remote_branch(&package_path, &branch)?;
+
+        let installed_commit = GitOperations::get_head_commit(&package_path)?;
+        let upstream_commit = incoming_commits.first()
+            .map(|commit| commit.id.clone())
+            .unwrap_or_else(|| installed_commit.clone());
+
+        info!("Package {} is {} commit(s) behind and {} ahead of origin/{}",
+            provenance.package_name, behind, ahead, branch);
+
+        Ok(BranchStatus {
+            package_name: provenance.package_name,
+            branch,
+            installed_commit,
+            upstream_commit,
+            behind,
+            ahead,
+            can_fast_forward: ahead == 0 && behind > 0,
+            incoming_commits,
+        })
+    }
+
+    /// Moves a tracking install to the current tip of its branch, re-applying the local patch if there is one.
+    pub fn fast_forward_package(project_path: &str, package_name: &str) -> Result<InstallResult> {
+        let status = Self::get_branch_status(project_path, package_name)?;
+
+        if status.ahead > 0 {
+            return Err(anyhow::anyhow!(
+                "Package {} has diverged from origin/{}, reinstall it to move to the new branch tip",
+                package_name, status.branch
+            ));
+        }
+
+        let (provenance, _, package_path) = Self::get_tracking_install(project_path, package_name)?;
+
+        if status.behind == 0 {
+            info!("Package {} is already up to date with origin/{}", package_name, status.branch);
+            return Ok(InstallResult {
+                package_name: package_name.to_string(),
+                version: provenance.version,
+                patch: None,
+                advisories: Vec::new(),
+            });
+        }
+
+        // Edits the patch file doesn't capture would be lost by the checkout
+        Self::check_patch_covers_changes(project_path, package_name, &package_path, "fast-forwarding")?;
+
+        info!("Fast-forwarding {} to {} ({} new commit(s))", package_name, status.upstream_commit, status.behind);
+        Self::update_package(project_path, &Self::package_from_provenance(&provenance))
+    }
+
+    fn get_tracking_install(project_path: &str, package_name: &str) -> Result<(PackageProvenance, String, PathBuf)> {
+        Self::check_not_linked(project_path, package_name)?;
+
+        let provenance = ProvenanceStore::get(project_path, package_name)?
+            .with_context(|| format!("Package {} was not installed by CUPM", package_name))?;
+
+        let branch = provenance.tracking_branch.clone()
+            .with_context(|| format!("Package {} is not tracking a branch", package_name))?;
+
+        let package_path = Self::get_package_repository_path(project_path, package_name)?;
+
+        match GitOperations::get_origin_url(&package_path) {
+            Some(origin_url) if GitOperations::urls_match(&origin_url, &provenance.git_url) => {}
+            _ => return Err(anyhow::anyhow!(
+                "Package {} is no longer a clone of {}", package_name, provenance.git_url
+            )),
+        }
+
+        Ok((provenance, branch, package_path))
+    }
+
+    pub fn get_package_path(project_path: &str, package_name: &str) -> Result<PathBuf> {
+        let packages_dir = Path::new(project_path).join("Packages");
+        let manifest_path = packages_dir.join("manifest.json");
+
+        if !manifest_path.exists() {
+            return Err(CupmError::manifest_not_found(&manifest_path).into());
+        }
+
+        let content = fs::read_to_string(&manifest_path)
+            .context("Failed to read manifest.json")?;
+
+        let manifest: Value = serde_json::from_str(&content)
+            .context("Failed to parse manifest.json")?;
+
+        let package_ref = manifest.get("dependencies")
+            .and_then(|d| d.get(package_name))
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| CupmError::PackageNotInstalled { package_name: package_name.to_string() })?;
+
+        let package_dir_name = package_ref.strip_prefix("file:")
+            .ok_or_else(|| CupmError::PackageNotEmbedded {
+                package_name: package_name.to_string(),
+                reference: package_ref.to_string(),
+            })?;
+
+        Ok(packages_dir.join(package_dir_name))
+    }
+
+    /// Re-hashes an installed package and compares it with the hash recorded when it was installed,
+    /// which shows whether anything in the folder changed since. When the registry declared a hash for the version,
+    /// the installed commit is checked against it as well.
+    pub fn verify_package(project_path: &str, package_name: &str) -> Result<PackageVerification> {
+        let package_path = Self::get_package_repository_path(project_path, package_name)?;
+
+        if !package_path.exists() {
+            return Err(CupmError::PathNotFound { path: package_path.to_string_lossy().to_string() }.into());
+        }
+
+        let provenance = ProvenanceStore::get(project_path, package_name)?;
+        let expected_hash = provenance.as_ref().and_then(|provenance| provenance.content_hash.clone());
+        let registry_hash = provenance.and_then(|provenance| provenance.registry_content_hash);
+
+        let actual_hash = IntegrityChecker::hash_tree(&package_path)?;
+        let verified = expected_hash.as_deref() == Some(actual_hash.as_str());
+
+        match &expected_hash {
+            Some(expected) if verified => info!("Package {} matches its installed contents ({})", package_name, expected),
+            Some(expected) => warn!("Package {} was modified after install (expected {}, found {})", package_name, expected, actual_hash),
+            None => info!("No content hash recorded for {}, it was installed before hashes were kept", package_name),
+        }
+
+        let registry_verified = match &registry_hash {
+            Some(registry_hash) => {
+                // Applied patches change the folder, so git checkouts are compared by the commit they have checked out
+                let pristine_hash = match GitOperations::get_head_commit(&package_path) {
+                    Ok(commit) => IntegrityChecker::hash_commit(&package_path, &commit)?,
+                    Err(_) => actual_hash.clone(),
+                };
+
+                let matches = IntegrityChecker::hashes_match(registry_hash, &pristine_hash);
+                if !matches {
+                    warn!("Package {} doesn't match the hash its registry declared (expected {}, found {})",
+                        package_name, registry_hash, pristine_hash);
+                }
+                Some(matches)
+            }
+            None => None,
+        };
+
+        Ok(PackageVerification {
+            package_name: package_name.to_string(),
+            path: package_path.to_string_lossy().to_string(),
+            expected_hash,
+            actual_hash,
+            verified,
+            registry_hash,
+            registry_verified,
+        })
+    }
+
+    /// Returns the install folder of an embedded package, which is the git repository root
+    /// even when the package itself lives in a subfolder of it.
+    pub fn get_package_repository_path(project_path: &str, package_name: &str) -> Result<PathBuf> {
+        let packages_dir = Path::new(project_path).join("Packages");
+        let package_path = Self::get_package_path(project_path, package_name)?;
+
+        let install_dir = package_path.strip_prefix(&packages_dir)
+            .ok()
+            .and_then(|relative| relative.components().next())
+            .map(|first| packages_dir.join(first))
+            .unwrap_or(package_path);
+
+        // References like "file:../../Elsewhere" are not install folders, callers reset and clean what this returns
+        PathGuard::confine(&packages_dir, &install_dir, "use package folder")?;
+
+        Ok(install_dir)
+    }
+
+    pub fn remove_package(project_path: &str, package_name: &str) -> Result<()> {
+        let packages_dir = Path::new(project_path).join("Packages");
+        let manifest_path = packages_dir.join("manifest.json");
+
+        if !manifest_path.exists() {
+            return Err(CupmError::manifest_not_found(&manifest_path).into());
+        }
+
+        let content = fs::read_to_string(&manifest_path)
+            .context("Failed to read manifest.json")?;
+
+        let mut manifest: Value = serde_json::from_str(&content)
+            .context("Failed to parse manifest.json")?;
+
+        // Remove package from dependencies and get the package path if it's local
+        let mut package_dir_to_remove: Option<String> = None;
+        if let Some(dependencies) = manifest.get_mut("dependencies").and_then(|d| d.as_object_mut()) {
+            if let Some(package_ref) = dependencies.get(package_name) {
+                if let Some(package_ref_str) = package_ref.as_str() {
+                    // Check if it's an embedded package, folders referenced from elsewhere are left alone
+                    if let Some(file_ref) = package_ref_str.strip_prefix("file:") {
+                        if Self::get_file_reference_source(file_ref) == "embedded" {
+                            package_dir_to_remove = Self::get_install_folder_name(file_ref);
+                        }
+                    }
+                }
+            }
+            dependencies.remove(package_name);
+        }
+
+        // Remove the local package directory if it exists
+        if let Some(package_dir_name) = package_dir_to_remove {
+            let package_path = packages_dir.join(&package_dir_name);
+            if Self::is_symlink(&package_path) || package_path.exists() {
+                info!("Removing local package directory: {}", package_path.display());
+                PathGuard::remove_dir_all(&packages_dir, &package_path, "remove package directory")?;
+            }
+        }
+
+        // Write back to file
+        let updated_content = serde_json::to_string_pretty(&manifest)
+            .context("Failed to serialize manifest.json")?;
+
+        fs::write(&manifest_path, updated_content)
+            .context("Failed to write manifest.json")?;
+
+        ProvenanceStore::remove(project_path, package_name)?;
+        LinkStore::remove(project_path, package_name)?;
+
+        info!("Successfully removed package: {}", package_name);
+        Ok(())
+    }
+
+    /// Records how a package looks before an operation changes it. Clean git checkouts are kept as a commit
+    /// reference, anything else that lives in Packages is copied, since it can't be fetched again.
+    pub fn snapshot_package(project_path: &str, operation_id: &str, package_name: &str) -> Result<PackageSnapshot> {
+        let packages_dir = Path::new(project_path).join("Packages");
+        let manifest_entry = Self::check_package_conflicts(project_path, package_name)?;
+
+        let mut snapshot = PackageSnapshot {
+            operation_id: operation_id.to_string(),
+            package_name: package_name.to_string(),
+            created_at: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+            manifest_entry: manifest_entry.clone(),
+            tree_kind: "none".to_string(),
+            install_folder: None,
+            git_url: None,
+            commit: None,
+            link_target: None,
+            provenance: ProvenanceStore::get(project_path, package_name)?,
+            link: LinkStore::get(project_path, package_name)?,
+        };
+
+        // Git URLs, tarballs and folders elsewhere on disk are only referenced, the manifest entry is all there is to keep
+        let install_folder = manifest_entry.as_deref()
+            .and_then(|entry| entry.strip_prefix("file:"))
+            .filter(|file_ref| Self::get_file_reference_source(file_ref) == "embedded")
+            .and_then(Self::get_install_folder_name);
+
+        if let Some(install_folder) = install_folder {
+            let package_path = packages_dir.join(&install_folder);
+
+            if Self::is_symlink(&package_path) {
+                let target = fs::read_link(&package_path)
+                    .with_context(|| format!("Failed to read link {}", package_path.display()))?;
+                snapshot.tree_kind = "symlink".to_string();
+                snapshot.link_target = Some(target.to_string_lossy().to_string());
+            } else if package_path.is_dir() {
+                let clean_checkout = GitOperations::get_origin_url(&package_path).filter(|_| {
+                    GitOperations::get_local_modifications(&package_path)
+                        .map(|files| files.is_empty())
+                        .unwrap_or(false)
+                });
+
+                match clean_checkout {
+                    Some(git_url) => {
+                        snapshot.tree_kind = "commit".to_string();
+                        snapshot.commit = Some(GitOperations::get_head_commit(&package_path)?);
+                        snapshot.git_url = Some(git_url);
+                    }
+                    None => {
+                        let copy_path = SnapshotStore::get_copy_path(project_path, operation_id);
+                        Self::copy_package_dir(&package_path, &copy_path)
+                            .with_context(|| format!("Failed to keep a copy of {}", package_name))?;
+                        snapshot.tree_kind = "copy".to_string();
+                    }
+                }
+            }
+
+            snapshot.install_folder = Some(install_folder);
+        }
+
+        SnapshotStore::save(project_path, &snapshot)?;
+        debug!("Snapshotted {} as {} before operation {}", package_name, snapshot.tree_kind, operation_id);
+
+        Ok(snapshot)
+    }
+
+    /// Puts a package back the way a snapshot recorded it, replacing whatever the project has for it now.
+    /// Linked packages are refused, unless `undoing_link` says the link is what is being undone.
+    pub fn restore_snapshot(project_path: &str, snapshot: &PackageSnapshot, undoing_link: bool) -> Result<()> {
+        let packages_dir = Path::new(project_path).join("Packages");
+        let package_name = snapshot.package_name.as_str();
+
+        // The link goes first, so nothing below can reach into the working copy it points at
+        if undoing_link {
+            Self::remove_link(project_path, package_name)?;
+        }
+
+        Self::check_not_linked(project_path, package_name)?;
+        Self::check_unchanged_since_operation(project_path, package_name)?;
+        let target = snapshot.install_folder.as_ref().map(|folder| packages_dir.join(folder));
+
+        // An update in place leaves the previous commit in the repository, so going back needs no clone
+        let restored_in_place = match (snapshot.tree_kind.as_str(), &target, &snapshot.commit) {
+            ("commit", Some(target), Some(commit))
+                if !Self::is_symlink(target)
+                    && target.is_dir()
+                    && Self::get_package_repository_path(project_path, package_name).ok().as_ref() == Some(target) =>
+            {
+                match GitOperations::checkout_commit(target, commit)
+                    .and_then(|_| GitOperations::remove_untracked_files(&packages_dir, target))
+                {
+                    Ok(()) => true,
+                    Err(e) => {
+                        warn!("Could not check out {} in place, reinstalling it: {:#}", commit, e);
+                        false
+                    }
+                }
+            }
+            _ => false,
+        };
+
+        if !restored_in_place {
+            if Self::check_package_conflicts(project_path, package_name)?.is_some() {
+                Self::remove_package(project_path, package_name)?;
+            }
+
+            if let Some(target) = &target {
+                if Self::is_symlink(target) || target.exists() {
+                    return Err(CupmError::FolderOccupied { path: target.to_string_lossy().to_string(), occupant: None }.into());
+                }
+
+                match (snapshot.tree_kind.as_str(), &snapshot.git_url, &snapshot.commit, &snapshot.link_target) {
+                    ("commit", Some(git_url), Some(commit), _) => {
+                        GitOperations::clone_repository(git_url, target)?;
+                        GitOperations::checkout_commit(target, commit)?;
+                    }
+                    ("copy", _, _, _) => {
+                        let copy_path = SnapshotStore::get_copy_path(project_path, &snapshot.operation_id);
+                        Self::copy_package_dir(&copy_path, target)
+                            .with_context(|| format!("Failed to restore the copy of {}", package_name))?;
+                    }
+                    ("symlink", _, _, Some(link_target)) => {
+                        Self::create_dir_symlink(Path::new(link_target), target)?;
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        Self::write_manifest_entry(project_path, package_name, snapshot.manifest_entry.as_deref())?;
+
+        match &snapshot.provenance {
+            Some(provenance) => ProvenanceStore::record(project_path, provenance.clone())?,
+            None => ProvenanceStore::remove(project_path, package_name)?,
+        }
+        match &snapshot.link {
+            Some(link) => LinkStore::record(project_path, link.clone())?,
+            None => LinkStore::remove(project_path, package_name)?,
+        }
+
+        match &snapshot.manifest_entry {
+            Some(entry) => info!("Restored {} to {}", package_name, entry),
+            None => info!("Removed {}, it wasn't installed before", package_name),
+        }
+
+        Ok(())
+    }
+
+    /// Refuses to restore a snapshot over a package that was edited after the operation being undone,
+    /// since those edits aren't in the snapshot and would be lost.
+    fn check_unchanged_since_operation(project_path: &str, package_name: &str) -> Result<()> {
+        if Self::check_package_conflicts(project_path, package_name)?.is_none() {
+            return Ok(());
+        }
+
+        // Only embedded packages have a folder of their own that could hold edits
+        let package_path = match Self::get_package_repository_path(project_path, package_name) {
+            Ok(path) if path.is_dir() => path,
+            _ => return Ok(()),
+        };
+
+        if GitOperations::get_origin_url(&package_path).is_some() {
+            return Self::check_patch_covers_changes(project_path, package_name, &package_path, "undoing the operation");
+        }
+
+        // Copies from a folder or tarball have no repository, their recorded hash shows whether they were edited
+        let verification = Self::verify_package(project_path, package_name)?;
+        if verification.expected_hash.is_none() || verification.verified {
+            return Ok(());
+        }
+
+        Err(anyhow::Error::from(CupmError::LocalChanges {
+            package_name: package_name.to_string(),
+            files: vec![verification.path],
+        }).context(format!("Package {} was changed after it was installed, back up or discard the changes before undoing the operation", package_name)))
+    }
+
+    /// Restores the snapshot taken before an operation and returns the version the package is back at.
+    pub fn revert_operation(project_path: &str, operation: &HistoryEntry) -> Result<Option<String>> {
+        let snapshot = SnapshotStore::load(project_path, &operation.id)?
+            .ok_or_else(|| CupmError::OperationNotRevertible {
+                operation_id: operation.id.clone(),
+                reason: "its snapshot no longer exists".to_string(),
+            })?;
+
+        Self::restore_snapshot(project_path, &snapshot, operation.operation == "link")?;
+
+        Ok(Self::get_installed_package_info(project_path, &snapshot.package_name)?.map(|(version, _)| version))
+    }
+
+    /// Sets or removes a single dependency in manifest.json.
+    fn write_manifest_entry(project_path: &str, package_name: &str, entry: Option<&str>) -> Result<()> {
+        let manifest_path = Path::new(project_path).join("Packages").join("manifest.json");
+
+        if !manifest_path.exists() {
+            return Err(CupmError::manifest_not_found(&manifest_path).into());
+        }
+
+        let content = fs::read_to_string(&manifest_path)
+            .context("Failed to read manifest.json")?;
+
+        let mut manifest: Value = serde_json::from_str(&content)
+            .context("Failed to parse manifest.json")?;
+
+        if manifest.get("dependencies").is_none() {
+            manifest["dependencies"] = serde_json::json!({});
+        }
+
+        if let Some(dependencies) = manifest.get_mut("dependencies").and_then(|d| d.as_object_mut()) {
+            match entry {
+                Some(entry) => {
+                    dependencies.insert(package_name.to_string(), Value::String(entry.to_string()));
+                }
+                None => {
+                    dependencies.remove(package_name);
+                }
+            }
+        }
+
+        let updated_content = serde_json::to_string_pretty(&manifest)
+            .context("Failed to serialize manifest.json")?;
+
+        fs::write(&manifest_path, updated_content)
+            .context("Failed to write manifest.json")?;
+
+        Ok(())
+    }
+
+    pub fn check_package_conflicts(project_path: &str, package_name: &str) -> Result<Option<String>> {
+        let manifest_path = Path::new(project_path)
+            .join("Packages")
+            .join("manifest.json");
+
+        if !manifest_path.exists() {
+            return Ok(None);
+        }
+
+        let content = fs::read_to_string(&manifest_path)
+            .context("Failed to read manifest.json")?;
+
+        let manifest: Value = serde_json::from_str(&content)
+            .context("Failed to parse manifest.json")?;
+
+        if let Some(dependencies) = manifest.get("dependencies").and_then(|d| d.as_object()) {
+            if let Some(existing_ref) = dependencies.get(package_name).and_then(|v| v.as_str()) {
+                return Ok(Some(existing_ref.to_string()));
+            }
+        }
+
+        Ok(None)
+    }
+
+    pub fn get_installed_package_info(project_path: &str, package_name: &str) -> Result<Option<(String, String)>> {
+        let packages_dir = Path::new(project_path).join("Packages");
+        let manifest_path = packages_dir.join("manifest.json");
+
+        if !manifest_path.exists() {
+            return Ok(None);
+        }
+
+        let content = fs::read_to_string(&manifest_path)
+            .context("Failed to read manifest.json")?;
+
+        let manifest: Value = serde_json::from_str(&content)
+            .context("Failed to parse manifest.json")?;
+
+        if let Some(dependencies) = manifest.get("dependencies").and_then(|d| d.as_object()) {
+            if let Some(package_ref) = dependencies.get(package_name).and_then(|v| v.as_str()) {
+                if let Some(file_ref) = package_ref.strip_prefix("file:") {
+                    // Local package - read version from package.json
+                    let source = if Self::is_tarball_reference(file_ref) { "tarball" } else { "local" };
+
+                    if let Some(package_data) = Self::read_local_package_json(&packages_dir, file_ref) {
+                        let package_data = package_data
                             .context("Failed to read package.json from local package")?;
-                        let package_data: Value = serde_json::from_str(&package_content)
-                            .context("Failed to parse package.json from local package")?;
 
                         let version = package_data.get("version")
                             .and_then(|v| v.as_str())
                             .unwrap_or("unknown")
                             .to_string();
 
-                        return Ok(Some((version, "local".to_string())));
+                        return Ok(Some((version, source.to_string())));
                     } else {
-                        return Ok(Some(("unknown".to_string(), "local".to_string())));
+                        return Ok(Some(("unknown".to_string(), source.to_string())));
                     }
                 } else if package_ref.starts_with("https://") || package_ref.starts_with("git+") || package_ref.contains(".git") {
                     // Git URL - extract version if available
@@ -383,3 +2600,444 @@ impl PackageManager {
         Ok(None)
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::HistoryStore;
+    use crate::integrity::IntegrityError;
+    use crate::test_support::{self, TempDir};
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    /// The package in `remote` as a registry would list it at `tag`.
+    fn tagged_package(remote: &Path, tag: &str) -> Package {
+        Package {
+            git_url: remote.to_string_lossy().to_string(),
+            git_tag: Some(tag.to_string()),
+            ..PackageManager::read_package_json(remote, "").unwrap()
+        }
+    }
+
+    fn write_tarball(path: &Path, build: impl FnOnce(&mut tar::Builder<GzEncoder<fs::File>>)) {
+        let mut builder = tar::Builder::new(GzEncoder::new(fs::File::create(path).unwrap(), Compression::default()));
+        build(&mut builder);
+        builder.into_inner().unwrap().finish().unwrap();
+    }
+
+    fn append_file(builder: &mut tar::Builder<GzEncoder<fs::File>>, path: &str, content: &[u8]) {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append_data(&mut header, path, content).unwrap();
+    }
+
+    fn append_link(builder: &mut tar::Builder<GzEncoder<fs::File>>, entry_type: tar::EntryType, path: &str, target: &Path) {
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(entry_type);
+        header.set_size(0);
+        header.set_mode(0o777);
+        builder.append_link(&mut header, path, target).unwrap();
+    }
+
+    #[test]
+    fn validate_package_name_accepts_reverse_domain_names() {
+        for name in ["com.company.package", "com.cvr.tools-2", "dev.user_name.pkg", "com.company.123"] {
+            assert!(PackageManager::validate_package_name(name).is_ok(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn validate_package_name_rejects_invalid_names() {
+        let too_long = format!("com.{}", "a".repeat(211));
+        for name in ["", "package", "Com.Company.Package", "com..package", "com.-package", "com.company/package",
+            "../com.company", "com.company.package\\x", too_long.as_str()] {
+            assert!(PackageManager::validate_package_name(name).is_err(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn validate_package_name_rejects_windows_reserved_names() {
+        for name in ["con.tools", "prn.package", "aux.x", "nul.x", "com1.tools", "lpt9.printer"] {
+            let error = PackageManager::validate_package_name(name).unwrap_err();
+            assert!(error.to_string().contains("reserved device name"), "{}: {}", name, error);
+        }
+
+        // Only the exact device names are reserved
+        for name in ["com.tools", "com10.tools", "console.tools", "lpt0.tools"] {
+            assert!(PackageManager::validate_package_name(name).is_ok(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn prepare_install_target_rejects_invalid_names_before_removing_anything() {
+        let project = TempDir::new("prepare");
+        let project_path = project.path().to_str().unwrap();
+        let packages_dir = project.join("Packages");
+        fs::create_dir_all(packages_dir.join("Bad_Name")).unwrap();
+        fs::write(packages_dir.join("Bad_Name").join("package.json"), "{\"name\":\"Bad_Name\"}").unwrap();
+
+        let mut manifest = serde_json::json!({ "dependencies": { "Bad_Name": "file:Bad_Name" } });
+        let Err(error) = PackageManager::prepare_install_target(project_path, &mut manifest, "Bad_Name") else {
+            panic!("expected an invalid name to be refused");
+        };
+
+        assert!(matches!(error.downcast_ref::<CupmError>(), Some(CupmError::InvalidPackageName { .. })));
+        assert!(packages_dir.join("Bad_Name").join("package.json").exists());
+    }
+
+    #[test]
+    fn place_package_puts_the_previous_install_back_when_placing_fails() {
+        let project = TempDir::new("place");
+        let project_path = test_support::create_project(project.path());
+        let existing = project.join("Packages").join("com.test.pkg");
+        test_support::write_package_json(&existing, "com.test.pkg", "1.0.0");
+
+        let mut manifest = serde_json::json!({ "dependencies": { "com.test.pkg": "file:com.test.pkg" } });
+        let target = PackageManager::prepare_install_target(&project_path, &mut manifest, "com.test.pkg").unwrap();
+        assert!(!existing.exists());
+
+        let placed = PackageManager::place_package(&project_path, &target, |path| {
+            fs::create_dir_all(path)?;
+            Err(anyhow::anyhow!("copy failed"))
+        });
+
+        assert!(placed.is_err());
+        assert!(fs::read_to_string(existing.join("package.json")).unwrap().contains("1.0.0"));
+        assert_eq!(fs::read_dir(PackageManager::get_staging_dir(&project_path)).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn install_from_git_url_leaves_the_previous_install_when_its_folder_is_taken() {
+        let dir = TempDir::new("install-taken");
+        let remote = dir.join("remote");
+        test_support::init_package_repo(&remote, "com.test.pkg", "2.0.0");
+
+        // An older install under the legacy folder name, and an unrelated package in the new folder
+        let project_path = test_support::create_project(&dir.join("project"));
+        let packages_dir = dir.join("project").join("Packages");
+        test_support::write_package_json(&packages_dir.join("com_test_pkg"), "com.test.pkg", "1.0.0");
+        test_support::write_package_json(&packages_dir.join("com.test.pkg"), "com.other.pkg", "1.0.0");
+        fs::write(packages_dir.join("manifest.json"), "{ \"dependencies\": { \"com.test.pkg\": \"file:com_test_pkg\" } }").unwrap();
+
+        let error = PackageManager::install_from_git_url(&project_path, remote.to_str().unwrap(), Some("v2.0.0"), None, None)
+            .unwrap_err();
+
+        assert!(matches!(error.downcast_ref::<CupmError>(), Some(CupmError::FolderOccupied { .. })));
+        assert!(packages_dir.join("com_test_pkg").join("package.json").exists());
+        assert!(fs::read_to_string(packages_dir.join("manifest.json")).unwrap().contains("file:com_test_pkg"));
+        assert_eq!(fs::read_dir(PackageManager::get_staging_dir(&project_path)).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn install_package_discards_staging_when_the_install_folder_is_taken() {
+        let dir = TempDir::new("install-staging");
+        let remote = dir.join("remote");
+        test_support::init_package_repo(&remote, "com.test.pkg", "1.0.0");
+
+        let project_path = test_support::create_project(&dir.join("project"));
+        test_support::write_package_json(&dir.join("project").join("Packages").join("com.test.pkg"), "com.other.pkg", "1.0.0");
+
+        let error = PackageManager::install_package(&project_path, &tagged_package(&remote, "v1.0.0")).unwrap_err();
+
+        assert!(matches!(error.downcast_ref::<CupmError>(), Some(CupmError::FolderOccupied { .. })));
+        assert_eq!(fs::read_dir(PackageManager::get_staging_dir(&project_path)).unwrap().count(), 0);
+    }
+    #[test]
+    fn strip_tarball_root_removes_the_top_level_folder() {
+        assert_eq!(PackageManager::strip_tarball_root(Path::new("package/package.json")), Some(PathBuf::from("package.json")));
+        assert_eq!(PackageManager::strip_tarball_root(Path::new("package/Runtime/a.cs")), Some(PathBuf::from("Runtime/a.cs")));
+        // npm always uses "package/", other archives use the package name
+        assert_eq!(PackageManager::strip_tarball_root(Path::new("com.test.pkg/package.json")), Some(PathBuf::from("package.json")));
+    }
+
+    #[test]
+    fn strip_tarball_root_rejects_the_root_and_escaping_paths() {
+        assert_eq!(PackageManager::strip_tarball_root(Path::new("package")), None);
+        assert_eq!(PackageManager::strip_tarball_root(Path::new("package/")), None);
+        assert_eq!(PackageManager::strip_tarball_root(Path::new("package/../outside.txt")), None);
+        assert_eq!(PackageManager::strip_tarball_root(Path::new("package/Runtime/../../outside.txt")), None);
+        assert_eq!(PackageManager::strip_tarball_root(Path::new("package/./a.cs")), Some(PathBuf::from("a.cs")));
+    }
+
+    #[test]
+    fn extract_tarball_writes_package_contents() {
+        let dir = TempDir::new("tarball");
+        let tarball = dir.join("package.tgz");
+        write_tarball(&tarball, |builder| {
+            append_file(builder, "package/package.json", b"{\"name\":\"com.test.pkg\"}");
+            append_file(builder, "package/Runtime/a.cs", b"class A {}");
+        });
+
+        let destination = dir.join("out");
+        PackageManager::extract_tarball(&tarball, &destination).unwrap();
+
+        assert!(destination.join("package.json").is_file());
+        assert_eq!(fs::read_to_string(destination.join("Runtime/a.cs")).unwrap(), "class A {}");
+    }
+
+    #[test]
+    fn extract_tarball_rejects_links_out_of_the_package() {
+        for entry_type in [tar::EntryType::Symlink, tar::EntryType::Link] {
+            let dir = TempDir::new("tarball-link");
+            let outside = dir.join("outside");
+            fs::create_dir_all(&outside).unwrap();
+
+            let tarball = dir.join("package.tgz");
+            write_tarball(&tarball, |builder| {
+                append_file(builder, "package/package.json", b"{\"name\":\"com.test.pkg\"}");
+                append_link(builder, entry_type, "package/x", &outside);
+                append_file(builder, "package/x/authorized_keys", b"ssh-ed25519 AAAA");
+            });
+
+            let error = PackageManager::extract_tarball(&tarball, &dir.join("out")).unwrap_err();
+
+            let security_error = error.downcast_ref::<SecurityError>().expect("expected a security error");
+            assert_eq!(security_error.kind, "archive_link");
+            assert!(!outside.join("authorized_keys").exists());
+        }
+    }
+
+    #[test]
+    fn install_from_tarball_discards_staging_when_the_install_folder_is_taken() {
+        let dir = TempDir::new("tarball-taken");
+        let tarball = dir.join("package.tgz");
+        write_tarball(&tarball, |builder| {
+            append_file(builder, "package/package.json", b"{\"name\":\"com.test.pkg\",\"version\":\"1.0.0\"}");
+        });
+
+        let project_path = test_support::create_project(&dir.join("project"));
+        let packages_dir = dir.join("project").join("Packages");
+        test_support::write_package_json(&packages_dir.join("com.test.pkg"), "com.other.pkg", "1.0.0");
+
+        let error = PackageManager::install_from_tarball(&project_path, tarball.to_str().unwrap()).unwrap_err();
+
+        assert!(matches!(error.downcast_ref::<CupmError>(), Some(CupmError::FolderOccupied { .. })));
+        assert!(fs::read_to_string(packages_dir.join("com.test.pkg").join("package.json")).unwrap().contains("com.other.pkg"));
+        assert_eq!(fs::read_dir(PackageManager::get_staging_dir(&project_path)).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn adopt_git_dependency_discards_staging_when_the_install_folder_is_taken() {
+        let dir = TempDir::new("adopt-taken");
+        let remote = dir.join("remote.git");
+        test_support::init_package_repo(&remote, "com.test.pkg", "1.0.0");
+
+        let project_path = test_support::create_project(&dir.join("project"));
+        let packages_dir = dir.join("project").join("Packages");
+        test_support::write_package_json(&packages_dir.join("com.test.pkg"), "com.other.pkg", "1.0.0");
+        let dependency = format!("{}#v1.0.0", remote.to_string_lossy().replace('\\', "/"));
+        fs::write(packages_dir.join("manifest.json"), serde_json::json!({ "dependencies": { "com.test.pkg": dependency } }).to_string()).unwrap();
+
+        let package = PackageManager::read_package_json(&remote, "").unwrap();
+        let error = PackageManager::adopt_git_dependency(&project_path, &package).unwrap_err();
+
+        assert!(matches!(error.downcast_ref::<CupmError>(), Some(CupmError::FolderOccupied { .. })));
+        assert!(fs::read_to_string(packages_dir.join("manifest.json")).unwrap().contains("#v1.0.0"));
+        assert_eq!(fs::read_dir(PackageManager::get_staging_dir(&project_path)).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn install_from_tarball_with_a_saved_patch_is_referenced_and_reports_the_patch_unapplied() {
+        let dir = TempDir::new("tarball-patch");
+        let tarball = dir.join("package.tgz");
+        write_tarball(&tarball, |builder| {
+            append_file(builder, "package/package.json", b"{\"name\":\"com.test.pkg\",\"version\":\"1.0.0\"}");
+        });
+
+        let project_path = test_support::create_project(&dir.join("project"));
+        let patch_path = PatchManager::get_patch_path(&project_path, "com.test.pkg");
+        fs::create_dir_all(patch_path.parent().unwrap()).unwrap();
+        fs::write(&patch_path, "diff --git a/a.cs b/a.cs\nnew file mode 100644\n--- /dev/null\n+++ b/a.cs\n@@ -0,0 +1 @@\n+class A {}\n").unwrap();
+
+        let result = PackageManager::install_from_tarball(&project_path, tarball.to_str().unwrap()).unwrap();
+
+        let patch = result.patch.expect("expected the patch to be reported");
+        assert!(!patch.applied);
+        assert!(patch.conflicts.is_empty());
+        let manifest = fs::read_to_string(dir.join("project").join("Packages").join("manifest.json")).unwrap();
+        assert!(manifest.contains("file:com.test.pkg"), "{}", manifest);
+    }
+
+    #[test]
+    fn update_package_updates_in_place_and_reapplies_the_saved_patch() {
+        let dir = TempDir::new("update-in-place");
+        let remote = dir.join("remote");
+        let repo = test_support::init_package_repo(&remote, "com.test.pkg", "1.0.0");
+        let project_path = test_support::create_project(&dir.join("project"));
+        PackageManager::install_package(&project_path, &tagged_package(&remote, "v1.0.0")).unwrap();
+
+        let package_path = PackageManager::get_package_repository_path(&project_path, "com.test.pkg").unwrap();
+        fs::create_dir_all(package_path.join("Editor")).unwrap();
+        fs::write(package_path.join("Editor").join("Local.cs"), "class Local {}").unwrap();
+        PatchManager::create_patch(&project_path, "com.test.pkg", &package_path).unwrap();
+
+        // Only the existing repository has this, a fresh clone wouldn't
+        fs::write(package_path.join(".git").join("cupm-test-marker"), "").unwrap();
+
+        test_support::write_package_json(&remote, "com.test.pkg", "2.0.0");
+        let new_commit = test_support::commit_all(&repo, "Release 2.0.0", Some("v2.0.0"));
+
+        let result = PackageManager::update_package(&project_path, &tagged_package(&remote, "v2.0.0")).unwrap();
+
+        assert_eq!(result.version, "2.0.0");
+        assert!(result.patch.expect("expected the patch to be re-applied").applied);
+        assert!(package_path.join(".git").join("cupm-test-marker").exists());
+        assert_eq!(GitOperations::get_head_commit(&package_path).unwrap(), new_commit);
+        assert_eq!(fs::read_to_string(package_path.join("Editor").join("Local.cs")).unwrap(), "class Local {}");
+        assert_eq!(ProvenanceStore::get(&project_path, "com.test.pkg").unwrap().unwrap().version, "2.0.0");
+    }
+
+    #[test]
+    fn update_package_refuses_changes_outside_the_saved_patch() {
+        let dir = TempDir::new("update-unsaved");
+        let remote = dir.join("remote");
+        let repo = test_support::init_package_repo(&remote, "com.test.pkg", "1.0.0");
+        let project_path = test_support::create_project(&dir.join("project"));
+        PackageManager::install_package(&project_path, &tagged_package(&remote, "v1.0.0")).unwrap();
+
+        let package_path = PackageManager::get_package_repository_path(&project_path, "com.test.pkg").unwrap();
+        let installed_commit = GitOperations::get_head_commit(&package_path).unwrap();
+        fs::write(package_path.join("Unsaved.cs"), "class Unsaved {}").unwrap();
+
+        test_support::write_package_json(&remote, "com.test.pkg", "2.0.0");
+        test_support::commit_all(&repo, "Release 2.0.0", Some("v2.0.0"));
+
+        let error = PackageManager::update_package(&project_path, &tagged_package(&remote, "v2.0.0")).unwrap_err();
+
+        assert!(matches!(error.downcast_ref::<CupmError>(), Some(CupmError::LocalChanges { .. })));
+        assert!(package_path.join("Unsaved.cs").exists());
+        assert_eq!(GitOperations::get_head_commit(&package_path).unwrap(), installed_commit);
+    }
+
+    #[test]
+    fn convert_to_git_dependency_writes_a_git_url_unity_recognises() {
+        let dir = TempDir::new("convert");
+        let remote = dir.join("remote");
+        test_support::init_package_repo(&remote, "com.test.pkg", "1.0.0");
+        let project_path = test_support::create_project(&dir.join("project"));
+        PackageManager::install_package(&project_path, &tagged_package(&remote, "v1.0.0")).unwrap();
+
+        // An origin without a ".git" suffix or "git+" prefix
+        let package_path = PackageManager::get_package_repository_path(&project_path, "com.test.pkg").unwrap();
+        git2::Repository::open(&package_path).unwrap().remote_set_url("origin", "https://github.com/owner/repo").unwrap();
+
+        let reference = PackageManager::convert_to_git_dependency(&project_path, "com.test.pkg").unwrap();
+
+        assert_eq!(reference, "git+https://github.com/owner/repo.git#v1.0.0");
+        let manifest = fs::read_to_string(dir.join("project").join("Packages").join("manifest.json")).unwrap();
+        assert!(manifest.contains("\"git+https://github.com/owner/repo.git#v1.0.0\""), "{}", manifest);
+        assert!(!package_path.exists());
+    }
+
+    #[test]
+    fn verify_package_checks_the_installed_commit_against_the_registry_hash() {
+        let dir = TempDir::new("verify");
+        let remote = dir.join("remote");
+        test_support::init_package_repo(&remote, "com.test.pkg", "1.0.0");
+        let project_path = test_support::create_project(&dir.join("project"));
+
+        let package = Package {
+            content_hash: Some(IntegrityChecker::hash_tree(&remote).unwrap()),
+            ..tagged_package(&remote, "v1.0.0")
+        };
+        PackageManager::install_package(&project_path, &package).unwrap();
+
+        let verification = PackageManager::verify_package(&project_path, "com.test.pkg").unwrap();
+        assert!(verification.verified);
+        assert_eq!(verification.registry_verified, Some(true));
+
+        // A local edit changes the folder but not the commit the registry vouched for
+        let package_path = PackageManager::get_package_repository_path(&project_path, "com.test.pkg").unwrap();
+        fs::write(package_path.join("Local.cs"), "class Local {}").unwrap();
+
+        let verification = PackageManager::verify_package(&project_path, "com.test.pkg").unwrap();
+        assert!(!verification.verified);
+        assert_eq!(verification.registry_verified, Some(true));
+    }
+
+    #[test]
+    fn reinstall_from_provenance_checks_registry_packages_like_an_install() {
+        let dir = TempDir::new("reinstall");
+        let remote = dir.join("remote");
+        test_support::init_package_repo(&remote, "com.test.pkg", "1.0.0");
+        let project_path = test_support::create_project(&dir.join("project"));
+
+        let package = Package {
+            registry_id: Some("test-registry".to_string()),
+            content_hash: Some(IntegrityChecker::hash_tree(&remote).unwrap()),
+            ..tagged_package(&remote, "v1.0.0")
+        };
+        PackageManager::install_package(&project_path, &package).unwrap();
+        let provenance = ProvenanceStore::get(&project_path, "com.test.pkg").unwrap().unwrap();
+
+        let tampered = PackageProvenance { registry_content_hash: Some(format!("sha256:{}", "0".repeat(64))), ..provenance.clone() };
+        let error = PackageManager::reinstall_from_provenance(&project_path, &tampered).unwrap_err();
+        assert!(error.downcast_ref::<IntegrityError>().is_some(), "{:#}", error);
+
+        let result = PackageManager::reinstall_from_provenance(&project_path, &provenance).unwrap();
+        assert_eq!(result.version, "1.0.0");
+        let package_path = PackageManager::get_package_repository_path(&project_path, "com.test.pkg").unwrap();
+        assert_eq!(GitOperations::get_head_commit(&package_path).ok(), provenance.commit);
+    }
+
+    #[test]
+    fn link_and_unlink_bring_back_the_installed_package() {
+        let dir = TempDir::new("link");
+        let remote = dir.join("remote");
+        test_support::init_package_repo(&remote, "com.test.pkg", "1.0.0");
+        let working_copy = dir.join("working-copy");
+        test_support::write_package_json(&working_copy, "com.test.pkg", "1.1.0-dev");
+        let project_path = test_support::create_project(&dir.join("project"));
+        let manifest_path = dir.join("project").join("Packages").join("manifest.json");
+        PackageManager::install_package(&project_path, &tagged_package(&remote, "v1.0.0")).unwrap();
+
+        for use_symlink in [true, false] {
+            let result = PackageManager::link_package(&project_path, working_copy.to_str().unwrap(), use_symlink).unwrap();
+            assert_eq!(result.version, "1.1.0-dev");
+            assert_eq!(PackageManager::get_installed_package_info(&project_path, "com.test.pkg").unwrap().unwrap().0, "1.1.0-dev");
+            let package_path = PackageManager::get_package_path(&project_path, "com.test.pkg").unwrap();
+            assert_eq!(PackageManager::is_symlink(&package_path), use_symlink);
+
+            let result = PackageManager::unlink_package(&project_path, "com.test.pkg").unwrap().unwrap();
+            assert_eq!(result.version, "1.0.0");
+
+            let package_path = PackageManager::get_package_repository_path(&project_path, "com.test.pkg").unwrap();
+            assert!(!PackageManager::is_symlink(&package_path));
+            assert!(package_path.join(".git").exists());
+            assert!(fs::read_to_string(&manifest_path).unwrap().contains("\"file:com.test.pkg\""));
+            assert!(LinkStore::get(&project_path, "com.test.pkg").unwrap().is_none());
+            assert!(working_copy.join("package.json").exists());
+        }
+    }
+
+    #[test]
+    fn undoing_a_link_restores_the_embedded_copy() {
+        let dir = TempDir::new("undo-link");
+        let remote = dir.join("remote");
+        test_support::init_package_repo(&remote, "com.test.pkg", "1.0.0");
+        let working_copy = dir.join("working-copy");
+        test_support::write_package_json(&working_copy, "com.test.pkg", "1.1.0-dev");
+        let project_path = test_support::create_project(&dir.join("project"));
+        PackageManager::install_package(&project_path, &tagged_package(&remote, "v1.0.0")).unwrap();
+        let installed_path = PackageManager::get_package_repository_path(&project_path, "com.test.pkg").unwrap();
+        let installed_commit = GitOperations::get_head_commit(&installed_path).unwrap();
+
+        let entry = HistoryStore::begin_with_snapshot(&project_path, "link", "com.test.pkg");
+        assert!(entry.snapshot);
+        PackageManager::link_package(&project_path, working_copy.to_str().unwrap(), true).unwrap();
+
+        PackageManager::revert_operation(&project_path, &entry).unwrap();
+
+        let package_path = PackageManager::get_package_repository_path(&project_path, "com.test.pkg").unwrap();
+        assert!(!PackageManager::is_symlink(&package_path));
+        assert_eq!(GitOperations::get_head_commit(&package_path).unwrap(), installed_commit);
+        assert_eq!(PackageManager::get_installed_package_info(&project_path, "com.test.pkg").unwrap().unwrap().0, "1.0.0");
+        let manifest = fs::read_to_string(dir.join("project").join("Packages").join("manifest.json")).unwrap();
+        assert!(manifest.contains("\"file:com.test.pkg\""), "{}", manifest);
+        assert!(LinkStore::get(&project_path, "com.test.pkg").unwrap().is_none());
+        assert!(fs::read_to_string(working_copy.join("package.json")).unwrap().contains("1.1.0-dev"));
+    }
+}
@@ -0,0 +1,91 @@
+use anyhow::{Context, Result};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+
+/// Per-package metadata kept in a `.cupm/*.json` file, keyed by package name.
+pub trait PackageStoreEntry: Serialize + DeserializeOwned {
+    const FILE_NAME: &'static str;
+    // What the file holds, for error messages
+    const DESCRIPTION: &'static str;
+
+    fn package_name(&self) -> &str;
+    fn set_package_name(&mut self, package_name: &str);
+}
+
+/// Project-local map from package name to a metadata entry, stored as pretty-printed JSON under `.cupm`.
+pub struct PackageStore<T> {
+    entry: PhantomData<T>,
+}
+
+impl<T: PackageStoreEntry> PackageStore<T> {
+    pub fn get_store_path(project_path: &str) -> PathBuf {
+        Path::new(project_path).join(".cupm").join(T::FILE_NAME)
+    }
+
+    pub fn load(project_path: &str) -> Result<BTreeMap<String, T>> {
+        let store_path = Self::get_store_path(project_path);
+
+        if !store_path.exists() {
+            return Ok(BTreeMap::new());
+        }
+
+        let content = fs::read_to_string(&store_path)
+            .with_context(|| format!("Failed to read {}", T::DESCRIPTION))?;
+
+        let entries = serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", T::DESCRIPTION))?;
+
+        Ok(entries)
+    }
+
+    pub fn save(project_path: &str, entries: &BTreeMap<String, T>) -> Result<()> {
+        let store_path = Self::get_store_path(project_path);
+
+        if let Some(parent) = store_path.parent() {
+            if !parent.exists() {
+                fs::create_dir_all(parent)
+                    .context("Failed to create project metadata directory")?;
+            }
+        }
+
+        let content = serde_json::to_string_pretty(entries)
+            .with_context(|| format!("Failed to serialize {}", T::DESCRIPTION))?;
+
+        fs::write(&store_path, content)
+            .with_context(|| format!("Failed to write {}", T::DESCRIPTION))?;
+
+        Ok(())
+    }
+
+    pub fn get(project_path: &str, package_name: &str) -> Result<Option<T>> {
+        Ok(Self::load(project_path)?.remove(package_name))
+    }
+
+    pub fn record(project_path: &str, entry: T) -> Result<()> {
+        let mut entries = Self::load(project_path)?;
+        entries.insert(entry.package_name().to_string(), entry);
+        Self::save(project_path, &entries)
+    }
+
+    pub fn rename(project_path: &str, old_name: &str, new_name: &str) -> Result<()> {
+        let mut entries = Self::load(project_path)?;
+        if let Some(mut entry) = entries.remove(old_name) {
+            entry.set_package_name(new_name);
+            entries.insert(new_name.to_string(), entry);
+            Self::save(project_path, &entries)?;
+        }
+        Ok(())
+    }
+
+    pub fn remove(project_path: &str, package_name: &str) -> Result<()> {
+        let mut entries = Self::load(project_path)?;
+        if entries.remove(package_name).is_some() {
+            Self::save(project_path, &entries)?;
+        }
+        Ok(())
+    }
+}
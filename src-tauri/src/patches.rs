@@ -0,0 +1,289 @@
+use anyhow::{Context, Result};
+use git2::{ApplyLocation, ApplyOptions, Diff, DiffOptions, Patch, Repository};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+use crate::models::{PackagePatch, PatchResult};
+
+pub struct PatchManager;
+
+impl PatchManager {
+    pub fn get_patches_dir(project_path: &str) -> PathBuf {
+        Path::new(project_path).join(".cupm").join("patches")
+    }
+
+    pub fn get_patch_path(project_path: &str, package_name: &str) -> PathBuf {
+        // Package names are used as-is, only path separators need replacing to keep the file in the patches folder
+        let file_name = format!("{}.patch", package_name.replace("/", "_").replace("\\", "_"));
+        Self::get_patches_dir(project_path).join(file_name)
+    }
+
+    /// Captures the difference between the installed commit and the working tree of an embedded package.
+    /// Returns the path of the written patch, or None if the package has no local changes.
+    pub fn create_patch(project_path: &str, package_name: &str, package_path: &Path) -> Result<Option<PathBuf>> {
+        let (patch_content, changed_files) = Self::diff_working_tree(package_name, package_path)?;
+
+        let patch_path = Self::get_patch_path(project_path, package_name);
+
+        if patch_content.is_empty() {
+            info!("No local changes found for package {}", package_name);
+            if patch_path.exists() {
+                fs::remove_file(&patch_path)
+                    .with_context(|| format!("Failed to remove stale patch: {}", patch_path.display()))?;
+            }
+            return Ok(None);
+        }
+
+        let patches_dir = Self::get_patches_dir(project_path);
+        if !patches_dir.exists() {
+            fs::create_dir_all(&patches_dir)
+                .context("Failed to create patches directory")?;
+        }
+
+        fs::write(&patch_path, &patch_content)
+            .with_context(|| format!("Failed to write patch: {}", patch_path.display()))?;
+
+        info!("Saved {} changed file(s) for package {} to {}",
+            changed_files, package_name, patch_path.display());
+
+        Ok(Some(patch_path))
+    }
+
+    /// Checks whether the working tree of an embedded package differs from its installed commit by exactly
+    /// the saved patch, i.e. a checkout followed by re-applying the patch would lose nothing.
+    pub fn matches_saved_patch(project_path: &str, package_name: &str, package_path: &Path) -> Result<bool> {
+        let patch_path = Self::get_patch_path(project_path, package_name);
+        let (patch_content, _) = Self::diff_working_tree(package_name, package_path)?;
+
+        if !patch_path.exists() {
+            return Ok(patch_content.is_empty());
+        }
+
+        let saved_content = fs::read(&patch_path)
+            .with_context(|| format!("Failed to read patch: {}", patch_path.display()))?;
+
+        Ok(patch_content == saved_content)
+    }
+
+    /// Formats the difference between the installed commit and the working tree, untracked files included,
+    /// and returns it with the number of changed files.
+    fn diff_working_tree(package_name: &str, package_path: &Path) -> Result<(Vec<u8>, usize)> {
+        let repo = Repository::open(package_path)
+            .with_context(|| format!("Package {} is not a git repository: {}", package_name, package_path.display()))?;
+
+        let head_tree = repo.head()
+            .and_then(|head| head.peel_to_tree())
+            .context("Failed to resolve installed commit of package")?;
+
+        let mut diff_options = DiffOptions::new();
+        diff_options
+            .include_untracked(true)
+            .recurse_untracked_dirs(true)
+            .show_untracked_content(true)
+            .show_binary(true);
+
+        let diff = repo.diff_tree_to_workdir_with_index(Some(&head_tree), Some(&mut diff_options))
+            .context("Failed to diff package against installed commit")?;
+
+        let mut patch_content = Vec::new();
+        for index in 0..diff.deltas().len() {
+            if let Some(mut patch) = Patch::from_diff(&diff, index).context("Failed to build patch")? {
+                let buf = patch.to_buf().context("Failed to format patch")?;
+                patch_content.extend_from_slice(&buf);
+            }
+        }
+
+        Ok((patch_content, diff.deltas().len()))
+    }
+
+    /// Re-applies a previously captured patch onto a freshly checked out package.
+    /// Nothing is written if any file fails to apply; the failing files are reported as conflicts instead.
+    /// Packages that aren't git repositories are left alone and the patch is reported as not applied.
+    pub fn apply_patch(project_path: &str, package_name: &str, package_path: &Path) -> Result<Option<PatchResult>> {
+        let patch_path = Self::get_patch_path(project_path, package_name);
+        if !patch_path.exists() {
+            return Ok(None);
+        }
+
+        info!("Applying local patch {} to package {}", patch_path.display(), package_name);
+
+        let patch_content = fs::read(&patch_path)
+            .with_context(|| format!("Failed to read patch: {}", patch_path.display()))?;
+
+        let diff = Diff::from_buffer(&patch_content)
+            .with_context(|| format!("Failed to parse patch: {}", patch_path.display()))?;
+
+        let patch_path_str = patch_path.to_string_lossy().to_string();
+
+        // Copies from a folder or tarball have no commit the patch was made against, it's reported as not applied
+        let repo = match Repository::open(package_path) {
+            Ok(repo) => repo,
+            Err(e) => {
+                warn!("Package {} is not a git repository, not applying its patch: {}", package_name, e);
+                return Ok(Some(PatchResult {
+                    package_name: package_name.to_string(),
+                    patch_path: patch_path_str,
+                    applied: false,
+                    conflicts: Vec::new(),
+                }));
+            }
+        };
+
+        // Check every file on its own so all conflicting files can be reported at once
+        let mut conflicts = Vec::new();
+        for (index, delta) in diff.deltas().enumerate() {
+            let mut check_options = ApplyOptions::new();
+            let mut current = 0;
+            check_options.check(true);
+            check_options.delta_callback(move |_| {
+                let selected = current == index;
+                current += 1;
+                selected
+            });
+
+            if repo.apply(&diff, ApplyLocation::WorkDir, Some(&mut check_options)).is_err() {
+                let file = delta.new_file().path()
+                    .or_else(|| delta.old_file().path())
+                    .map(|p| p.to_string_lossy().to_string())
+                    .unwrap_or_else(|| "unknown".to_string());
+                conflicts.push(file);
+            }
+        }
+
+        if !conflicts.is_empty() {
+            warn!("Patch for {} no longer applies cleanly, conflicting files: {:?}", package_name, conflicts);
+            return Ok(Some(PatchResult {
+                package_name: package_name.to_string(),
+                patch_path: patch_path_str,
+                applied: false,
+                conflicts,
+            }));
+        }
+
+        repo.apply(&diff, ApplyLocation::WorkDir, None)
+            .with_context(|| format!("Failed to apply patch: {}", patch_path.display()))?;
+
+        info!("Successfully applied patch to package {}", package_name);
+
+        // Save it again against the commit it now applies to, so it keeps matching the working tree exactly
+        Self::create_patch(project_path, package_name, package_path)?;
+
+        Ok(Some(PatchResult {
+            package_name: package_name.to_string(),
+            patch_path: patch_path_str,
+            applied: true,
+            conflicts,
+        }))
+    }
+
+    pub fn list_patches(project_path: &str) -> Result<Vec<PackagePatch>> {
+        let patches_dir = Self::get_patches_dir(project_path);
+        if !patches_dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut patches = Vec::new();
+        for entry in fs::read_dir(&patches_dir).context("Failed to read patches directory")? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("patch") {
+                continue;
+            }
+
+            if let Some(package_name) = path.file_stem().and_then(|s| s.to_str()) {
+                patches.push(PackagePatch {
+                    package_name: package_name.to_string(),
+                    patch_path: path.to_string_lossy().to_string(),
+                });
+            }
+        }
+
+        patches.sort_by(|a, b| a.package_name.cmp(&b.package_name));
+        Ok(patches)
+    }
+
+    pub fn remove_patch(project_path: &str, package_name: &str) -> Result<()> {
+        let patch_path = Self::get_patch_path(project_path, package_name);
+        if patch_path.exists() {
+            fs::remove_file(&patch_path)
+                .with_context(|| format!("Failed to remove patch: {}", patch_path.display()))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, TempDir};
+
+    fn clone(remote: &Path, path: &Path) {
+        Repository::clone(&remote.to_string_lossy(), path).unwrap();
+    }
+
+    fn package_with_saved_patch(dir: &TempDir) -> (String, PathBuf) {
+        let remote = dir.join("remote");
+        let repo = test_support::init_package_repo(&remote, "com.test.pkg", "1.0.0");
+        fs::create_dir_all(remote.join("Runtime")).unwrap();
+        fs::write(remote.join("Runtime").join("a.cs"), "class A {}\n").unwrap();
+        test_support::commit_all(&repo, "Add A", None);
+
+        let project_path = test_support::create_project(&dir.join("project"));
+        let edited = dir.join("edited");
+        clone(&remote, &edited);
+        fs::write(edited.join("Runtime").join("a.cs"), "class A { int x; }\n").unwrap();
+        fs::write(edited.join("Runtime").join("b.cs"), "class B {}\n").unwrap();
+
+        assert!(PatchManager::create_patch(&project_path, "com.test.pkg", &edited).unwrap().is_some());
+        (project_path, remote)
+    }
+
+    #[test]
+    fn apply_patch_reapplies_saved_changes_to_a_fresh_checkout() {
+        let dir = TempDir::new("patch-apply");
+        let (project_path, remote) = package_with_saved_patch(&dir);
+
+        let fresh = dir.join("fresh");
+        clone(&remote, &fresh);
+        let result = PatchManager::apply_patch(&project_path, "com.test.pkg", &fresh).unwrap().unwrap();
+
+        assert!(result.applied);
+        assert!(result.conflicts.is_empty());
+        assert_eq!(fs::read_to_string(fresh.join("Runtime").join("a.cs")).unwrap(), "class A { int x; }\n");
+        assert_eq!(fs::read_to_string(fresh.join("Runtime").join("b.cs")).unwrap(), "class B {}\n");
+        assert!(PatchManager::matches_saved_patch(&project_path, "com.test.pkg", &fresh).unwrap());
+    }
+
+    #[test]
+    fn apply_patch_reports_conflicts_and_writes_nothing() {
+        let dir = TempDir::new("patch-conflict");
+        let (project_path, remote) = package_with_saved_patch(&dir);
+
+        // Upstream changed the same line the patch edits
+        let repo = Repository::open(&remote).unwrap();
+        fs::write(remote.join("Runtime").join("a.cs"), "class A { string y; }\n").unwrap();
+        test_support::commit_all(&repo, "Change A", None);
+
+        let fresh = dir.join("fresh");
+        clone(&remote, &fresh);
+        let result = PatchManager::apply_patch(&project_path, "com.test.pkg", &fresh).unwrap().unwrap();
+
+        assert!(!result.applied);
+        assert_eq!(result.conflicts, vec!["Runtime/a.cs".to_string()]);
+        assert_eq!(fs::read_to_string(fresh.join("Runtime").join("a.cs")).unwrap(), "class A { string y; }\n");
+        assert!(!fresh.join("Runtime").join("b.cs").exists());
+    }
+
+    #[test]
+    fn apply_patch_leaves_packages_without_a_repository_alone() {
+        let dir = TempDir::new("patch-copy");
+        let (project_path, _) = package_with_saved_patch(&dir);
+
+        let copy = dir.join("copy");
+        test_support::write_package_json(&copy, "com.test.pkg", "1.0.0");
+        let result = PatchManager::apply_patch(&project_path, "com.test.pkg", &copy).unwrap().unwrap();
+
+        assert!(!result.applied);
+        assert!(result.conflicts.is_empty());
+        assert!(!copy.join("Runtime").exists());
+    }
+}
@@ -0,0 +1,18 @@
+use crate::models::PackageProvenance;
+use crate::package_store::{PackageStore, PackageStoreEntry};
+
+/// Project-local record of which packages CUPM installed and where they came from.
+pub type ProvenanceStore = PackageStore<PackageProvenance>;
+
+impl PackageStoreEntry for PackageProvenance {
+    const FILE_NAME: &'static str = "installed.json";
+    const DESCRIPTION: &'static str = "installed package metadata";
+
+    fn package_name(&self) -> &str {
+        &self.package_name
+    }
+
+    fn set_package_name(&mut self, package_name: &str) {
+        self.package_name = package_name.to_string();
+    }
+}
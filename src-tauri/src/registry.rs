@@ -0,0 +1,70 @@
+use anyhow::Result;
+use crate::errors::CupmError;
+use crate::models::{Package, PackageRegistry};
+use crate::registry_trust::RegistryTrust;
+
+pub struct RegistryClient;
+
+impl RegistryClient {
+    /// Downloads a registry and expands packages with multiple versions into one entry per version.
+    /// Every package is tagged with `registry_id` so installs can record where they came from,
+    /// and configured registries are compared with the fingerprint pinned when they were added.
+    pub async fn fetch_registry(registry_url: &str, registry_id: Option<&str>) -> Result<PackageRegistry> {
+        let client = reqwest::Client::new();
+        let response = client
+            .get(registry_url)
+            .send()
+            .await
+            .map_err(|e| CupmError::RegistryUnreachable { url: registry_url.to_string(), reason: e.to_string() })?;
+
+        let mut registry: PackageRegistry = response
+            .json()
+            .await
+            .map_err(|e| CupmError::RegistryInvalid { url: registry_url.to_string(), reason: e.to_string() })?;
+
+        // Expand packages that have multiple versions into separate package entries
+        let mut expanded_packages = Vec::new();
+
+        for package in registry.packages {
+            if let Some(versions) = &package.versions {
+                // New format: package has multiple versions
+                for version_info in versions {
+                    let expanded_package = Package {
+                        name: package.name.clone(),
+                        display_name: package.display_name.clone(),
+                        description: package.description.clone(),
+                        git_url: package.git_url.clone(),
+                        author: package.author.clone(),
+                        keywords: package.keywords.clone(),
+                        category: package.category.clone(),
+                        license: package.license.clone(),
+                        versions: None, // Don't include versions in expanded format
+                        // Use version-specific data
+                        version: Some(version_info.version.clone()),
+                        git_tag: version_info.git_tag.clone(),
+                        git_branch: version_info.git_branch.clone(),
+                        dependencies: version_info.dependencies.clone(),
+                        unity_version: version_info.unity_version.clone(),
+                        is_prerelease: version_info.is_prerelease,
+                        registry_id: registry_id.map(|id| id.to_string()),
+                        content_hash: version_info.content_hash.clone(),
+                    };
+                    expanded_packages.push(expanded_package);
+                }
+            } else {
+                // Legacy format: single version package
+                let mut package = package;
+                package.registry_id = registry_id.map(|id| id.to_string());
+                expanded_packages.push(package);
+            }
+        }
+
+        registry.packages = expanded_packages;
+
+        if let Some(registry_id) = registry_id {
+            registry.trust_changes = RegistryTrust::observe(registry_id, registry_url, &registry)?;
+        }
+
+        Ok(registry)
+    }
+}
@@ -0,0 +1,210 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+use crate::config::Config;
+use crate::git_operations::GitOperations;
+use crate::models::{PackageRegistry, RegistryPin, RegistryTrustChange};
+
+/// Raised when a package is used while its registry has ownership changes the user hasn't confirmed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegistryTrustError {
+    pub kind: String, // "registry_trust_changed"
+    pub message: String,
+    pub registry_id: String,
+    pub package_name: String,
+    pub changes: Vec<RegistryTrustChange>,
+}
+
+impl fmt::Display for RegistryTrustError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for RegistryTrustError {}
+
+/// Trust-on-first-use pinning of registries: what a registry serves when it is added is remembered,
+/// and later fetches that change who controls a package have to be confirmed before it is used again.
+pub struct RegistryTrust;
+
+impl RegistryTrust {
+    pub fn get_store_path() -> Result<PathBuf> {
+        let config_path = Config::get_config_path()?;
+        Ok(config_path.with_file_name("registry-pins.json"))
+    }
+
+    pub fn load() -> Result<BTreeMap<String, RegistryPin>> {
+        let store_path = Self::get_store_path()?;
+
+        if !store_path.exists() {
+            return Ok(BTreeMap::new());
+        }
+
+        let content = fs::read_to_string(&store_path)
+            .context("Failed to read registry pins")?;
+
+        serde_json::from_str(&content)
+            .context("Failed to parse registry pins")
+    }
+
+    pub fn save(pins: &BTreeMap<String, RegistryPin>) -> Result<()> {
+        let store_path = Self::get_store_path()?;
+
+        let content = serde_json::to_string_pretty(pins)
+            .context("Failed to serialize registry pins")?;
+
+        fs::write(&store_path, content)
+            .context("Failed to write registry pins")
+    }
+
+    /// Compares a freshly fetched registry with its pin and returns what changed.
+    /// The first fetch pins the registry, packages it didn't serve before are added to the pin as they appear.
+    pub fn observe(registry_id: &str, registry_url: &str, registry: &PackageRegistry) -> Result<Vec<RegistryTrustChange>> {
+        let mut pins = Self::load()?;
+
+        let pin = match pins.get_mut(registry_id) {
+            Some(pin) => pin,
+            None => {
+                let pin = RegistryPin {
+                    registry_id: registry_id.to_string(),
+                    url: registry_url.to_string(),
+                    signing_key: registry.signing_key.clone(),
+                    package_hosts: Self::get_package_hosts(registry),
+                    pinned_at: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+                    pending_changes: Vec::new(),
+                };
+
+                info!("Pinned registry {} with {} package(s)", registry_url, pin.package_hosts.len());
+                pins.insert(registry_id.to_string(), pin);
+                Self::save(&pins)?;
+                return Ok(Vec::new());
+            }
+        };
+
+        let mut changes = Vec::new();
+
+        if pin.signing_key != registry.signing_key {
+            changes.push(RegistryTrustChange {
+                registry_id: registry_id.to_string(),
+                package_name: None,
+                field: "signing_key".to_string(),
+                previous: pin.signing_key.clone(),
+                current: registry.signing_key.clone(),
+            });
+        }
+
+        for (package_name, host) in Self::get_package_hosts(registry) {
+            match pin.package_hosts.get(&package_name) {
+                Some(pinned_host) if pinned_host != &host => {
+                    changes.push(RegistryTrustChange {
+                        registry_id: registry_id.to_string(),
+                        package_name: Some(package_name),
+                        field: "git_host".to_string(),
+                        previous: Some(pinned_host.clone()),
+                        current: Some(host),
+                    });
+                }
+                Some(_) => {}
+                None => {
+                    pin.package_hosts.insert(package_name, host);
+                }
+            }
+        }
+
+        if !changes.is_empty() {
+            warn!("Registry {} changed {} ownership-relevant field(s) since it was pinned", registry_url, changes.len());
+        }
+
+        // Changes that were reverted upstream no longer need confirming
+        pin.pending_changes = changes.clone();
+        Self::save(&pins)?;
+
+        Ok(changes)
+    }
+
+    /// Accepts the pending changes of a registry into its pin.
+    pub fn confirm(registry_id: &str) -> Result<()> {
+        let mut pins = Self::load()?;
+
+        let pin = pins.get_mut(registry_id)
+            .with_context(|| format!("Registry {} has not been pinned", registry_id))?;
+
+        for change in std::mem::take(&mut pin.pending_changes) {
+            match (change.field.as_str(), change.package_name) {
+                ("signing_key", _) => pin.signing_key = change.current,
+                ("git_host", Some(package_name)) => match change.current {
+                    Some(host) => {
+                        pin.package_hosts.insert(package_name, host);
+                    }
+                    None => {
+                        pin.package_hosts.remove(&package_name);
+                    }
+                },
+                _ => {}
+            }
+        }
+
+        info!("Confirmed changes to registry {}", pin.url);
+        Self::save(&pins)
+    }
+
+    pub fn remove(registry_id: &str) -> Result<()> {
+        let mut pins = Self::load()?;
+
+        if pins.remove(registry_id).is_some() {
+            Self::save(&pins)?;
+        }
+
+        Ok(())
+    }
+
+    /// Stops a registry package from being used while its registry has unconfirmed changes that affect it.
+    pub fn check_package(registry_id: &str, package_name: &str) -> Result<()> {
+        let changes: Vec<RegistryTrustChange> = Self::load()?
+            .remove(registry_id)
+            .map(|pin| pin.pending_changes)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|change| change.package_name.is_none() || change.package_name.as_deref() == Some(package_name))
+            .collect();
+
+        if changes.is_empty() {
+            return Ok(());
+        }
+
+        let summary = changes.iter()
+            .map(|change| format!(
+                "{} changed from {} to {}",
+                change.field,
+                change.previous.as_deref().unwrap_or("none"),
+                change.current.as_deref().unwrap_or("none"),
+            ))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        Err(RegistryTrustError {
+            kind: "registry_trust_changed".to_string(),
+            message: format!(
+                "The registry of {} changed since it was added ({}), confirm the change before installing",
+                package_name, summary
+            ),
+            registry_id: registry_id.to_string(),
+            package_name: package_name.to_string(),
+            changes,
+        }.into())
+    }
+
+    fn get_package_hosts(registry: &PackageRegistry) -> BTreeMap<String, String> {
+        registry.packages.iter()
+            .map(|package| {
+                let host = GitOperations::get_url_host(&package.git_url).unwrap_or_default();
+                (package.name.clone(), host)
+            })
+            .collect()
+    }
+}
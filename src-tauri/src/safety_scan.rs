@@ -0,0 +1,315 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::info;
+use crate::models::{SafetyAcknowledgement, SafetyFinding, SafetyPolicy, SafetyReport};
+
+// Attributes and base classes that make Unity run editor code without the user doing anything
+const AUTO_RUN_PATTERNS: &[&str] = &[
+    "InitializeOnLoad",
+    "DidReloadScripts",
+    "RuntimeInitializeOnLoadMethod",
+    ": AssetPostprocessor",
+    ": AssetModificationProcessor",
+];
+
+const NETWORK_PATTERNS: &[&str] = &[
+    "System.Net", "HttpClient", "WebClient", "WebRequest", "UnityWebRequest", "TcpClient", "UdpClient", "Socket",
+];
+
+const PROCESS_PATTERNS: &[&str] = &["Process.Start", "ProcessStartInfo", "System.Diagnostics.Process"];
+
+const REGISTRY_PATTERNS: &[&str] = &["Microsoft.Win32", "RegistryKey", "Registry.CurrentUser", "Registry.LocalMachine"];
+
+// Marker attributes common obfuscators leave in the assemblies they process
+const OBFUSCATOR_MARKERS: &[&str] = &[
+    "ConfusedByAttribute",
+    "DotfuscatorAttribute",
+    "ObfuscatedByGoliath",
+    "BabelObfuscatorAttribute",
+    "SmartAssembly.Attributes",
+    "PoweredByAttribute",
+    "Xenocode.Client.Attributes",
+    "CryptoObfuscator",
+    "ObfuscatorAttribute",
+];
+
+const NATIVE_EXTENSIONS: &[&str] = &["dll", "so", "dylib", "bundle"];
+
+// Larger files are only inventoried by name, not read
+const MAX_SCANNED_FILE_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Raised when an install is stopped by the safety scan, either because the policy blocks a finding
+/// or because the findings haven't been acknowledged yet.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SafetyScanError {
+    pub kind: String, // "safety_blocked", "safety_acknowledgement_required"
+    pub message: String,
+    pub report: SafetyReport,
+}
+
+impl fmt::Display for SafetyScanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for SafetyScanError {}
+
+/// Inventories what a package will run inside the Unity editor before it is installed.
+pub struct SafetyScanner;
+
+impl SafetyScanner {
+    pub fn get_acknowledgements_path(project_path: &str) -> PathBuf {
+        Path::new(project_path).join(".cupm").join("acknowledgements.json")
+    }
+
+    pub fn scan(package_name: &str, package_path: &Path, policy: &SafetyPolicy) -> Result<SafetyReport> {
+        let mut findings = Vec::new();
+        Self::scan_dir(package_path, package_path, &mut findings)?;
+        findings.sort_by(|a, b| (&a.category, &a.path).cmp(&(&b.category, &b.path)));
+
+        let mut blocked_categories: Vec<String> = findings.iter()
+            .filter(|finding| policy.blocked_categories.contains(&finding.category))
+            .map(|finding| finding.category.clone())
+            .collect();
+        blocked_categories.dedup();
+
+        info!("Safety scan of {} found {} item(s)", package_name, findings.len());
+
+        Ok(SafetyReport {
+            package_name: package_name.to_string(),
+            findings,
+            blocked_categories,
+        })
+    }
+
+    /// Scans a checked out package and decides whether it may be installed into the project.
+    /// Blocked categories always stop the install; other findings need to have been acknowledged for this package.
+    pub fn check(project_path: &str, package_name: &str, package_path: &Path, policy: &SafetyPolicy) -> Result<SafetyReport> {
+        let report = Self::scan(package_name, package_path, policy)?;
+
+        if !report.blocked_categories.is_empty() {
+            return Err(SafetyScanError {
+                kind: "safety_blocked".to_string(),
+                message: format!(
+                    "Installing {} is blocked by the safety policy: {}",
+                    package_name, report.blocked_categories.join(", ")
+                ),
+                report,
+            }.into());
+        }
+
+        if policy.require_acknowledgement && !report.findings.is_empty() {
+            let acknowledged = Self::load_acknowledgements(project_path)?
+                .remove(package_name)
+                .map(|ack| ack.findings)
+                .unwrap_or_default();
+
+            // Line numbers move with unrelated edits, so they don't count towards a finding being new
+            let unacknowledged = report.findings.iter()
+                .filter(|finding| {
+                    !acknowledged.iter().any(|ack| {
+                        ack.category == finding.category && ack.path == finding.path && ack.detail == finding.detail
+                    })
+                })
+                .count();
+
+            if unacknowledged > 0 {
+                return Err(SafetyScanError {
+                    kind: "safety_acknowledgement_required".to_string(),
+                    message: format!(
+                        "{} has {} safety finding(s) that need to be reviewed before installing",
+                        package_name, unacknowledged
+                    ),
+                    report,
+                }.into());
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Records that the user reviewed a report, so installing the same findings again won't ask a second time.
+    pub fn acknowledge(project_path: &str, report: &SafetyReport) -> Result<()> {
+        let mut acknowledgements = Self::load_acknowledgements(project_path)?;
+
+        acknowledgements.insert(report.package_name.clone(), SafetyAcknowledgement {
+            package_name: report.package_name.clone(),
+            findings: report.findings.clone(),
+            acknowledged_at: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+        });
+
+        let store_path = Self::get_acknowledgements_path(project_path);
+        if let Some(parent) = store_path.parent() {
+            if !parent.exists() {
+                fs::create_dir_all(parent)
+                    .context("Failed to create project metadata directory")?;
+            }
+        }
+
+        let content = serde_json::to_string_pretty(&acknowledgements)
+            .context("Failed to serialize safety acknowledgements")?;
+
+        fs::write(&store_path, content)
+            .context("Failed to write safety acknowledgements")?;
+
+        info!("Acknowledged {} safety finding(s) for {}", report.findings.len(), report.package_name);
+        Ok(())
+    }
+
+    pub fn load_acknowledgements(project_path: &str) -> Result<BTreeMap<String, SafetyAcknowledgement>> {
+        let store_path = Self::get_acknowledgements_path(project_path);
+
+        if !store_path.exists() {
+            return Ok(BTreeMap::new());
+        }
+
+        let content = fs::read_to_string(&store_path)
+            .context("Failed to read safety acknowledgements")?;
+
+        serde_json::from_str(&content)
+            .context("Failed to parse safety acknowledgements")
+    }
+
+    fn scan_dir(root: &Path, dir: &Path, findings: &mut Vec<SafetyFinding>) -> Result<()> {
+        let mut entries: Vec<PathBuf> = fs::read_dir(dir)
+            .with_context(|| format!("Failed to read {}", dir.display()))?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .collect();
+        entries.sort();
+
+        for path in entries {
+            let metadata = match fs::symlink_metadata(&path) {
+                Ok(metadata) => metadata,
+                Err(_) => continue,
+            };
+
+            let file_name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+            let relative = path.strip_prefix(root).unwrap_or(&path).to_string_lossy().replace('\\', "/");
+            let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+
+            if metadata.file_type().is_symlink() || file_name == ".git" {
+                continue;
+            }
+
+            if metadata.is_dir() {
+                // macOS plugins are folders with a .bundle extension
+                if extension == "bundle" {
+                    findings.push(Self::finding("native_binary", &relative, "macOS plugin bundle".to_string(), None));
+                } else {
+                    Self::scan_dir(root, &path, findings)?;
+                }
+                continue;
+            }
+
+            if metadata.len() > MAX_SCANNED_FILE_SIZE {
+                if NATIVE_EXTENSIONS.contains(&extension.as_str()) {
+                    findings.push(Self::finding("native_binary", &relative, "Binary too large to inspect".to_string(), None));
+                }
+                continue;
+            }
+
+            match extension.as_str() {
+                "dll" => Self::scan_assembly(&path, &relative, findings)?,
+                "so" | "dylib" | "bundle" => {
+                    findings.push(Self::finding("native_binary", &relative, format!("Native .{} library", extension), None));
+                }
+                "cs" => Self::scan_script(&path, &relative, findings)?,
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    fn scan_assembly(path: &Path, relative: &str, findings: &mut Vec<SafetyFinding>) -> Result<()> {
+        let bytes = fs::read(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+
+        if !Self::is_managed_assembly(&bytes) {
+            findings.push(Self::finding("native_binary", relative, "Native Windows library".to_string(), None));
+            return Ok(());
+        }
+
+        if let Some(marker) = OBFUSCATOR_MARKERS.iter().find(|marker| Self::contains_bytes(&bytes, marker.as_bytes())) {
+            findings.push(Self::finding("obfuscated_assembly", relative, format!("Contains obfuscator marker {}", marker), None));
+        }
+
+        Ok(())
+    }
+
+    /// Checks the PE header for a CLR runtime header, which only .NET assemblies have.
+    fn is_managed_assembly(bytes: &[u8]) -> bool {
+        let read_u16 = |offset: usize| bytes.get(offset..offset + 2).map(|b| u16::from_le_bytes([b[0], b[1]]));
+        let read_u32 = |offset: usize| bytes.get(offset..offset + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+
+        if bytes.get(0..2) != Some(b"MZ") {
+            return false;
+        }
+
+        let pe_offset = match read_u32(0x3c) {
+            Some(offset) => offset as usize,
+            None => return false,
+        };
+
+        if bytes.get(pe_offset..pe_offset + 4) != Some(b"PE\0\0") {
+            return false;
+        }
+
+        // The optional header follows the 4 byte signature and the 20 byte COFF header
+        let optional_header = pe_offset + 24;
+        let data_directories = match read_u16(optional_header) {
+            Some(0x10b) => optional_header + 96,
+            Some(0x20b) => optional_header + 112,
+            _ => return false,
+        };
+
+        // Data directory 14 is the CLR runtime header
+        read_u32(data_directories + 14 * 8).map(|rva| rva != 0).unwrap_or(false)
+    }
+
+    fn scan_script(path: &Path, relative: &str, findings: &mut Vec<SafetyFinding>) -> Result<()> {
+        let bytes = fs::read(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let content = String::from_utf8_lossy(&bytes);
+
+        let categories: [(&str, &[&str]); 4] = [
+            ("auto_run_editor_code", AUTO_RUN_PATTERNS),
+            ("network_api", NETWORK_PATTERNS),
+            ("process_api", PROCESS_PATTERNS),
+            ("registry_api", REGISTRY_PATTERNS),
+        ];
+
+        // One finding per file and category, pointing at the first line that matched
+        for (category, patterns) in categories {
+            let first_match = content.lines().enumerate().find_map(|(index, line)| {
+                let code = line.split("//").next().unwrap_or("");
+                patterns.iter().find(|pattern| code.contains(*pattern)).map(|pattern| (index + 1, *pattern))
+            });
+
+            if let Some((line, pattern)) = first_match {
+                findings.push(Self::finding(category, relative, format!("Uses {}", pattern), Some(line)));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn contains_bytes(haystack: &[u8], needle: &[u8]) -> bool {
+        haystack.windows(needle.len()).any(|window| window == needle)
+    }
+
+    fn finding(category: &str, path: &str, detail: String, line: Option<usize>) -> SafetyFinding {
+        SafetyFinding {
+            category: category.to_string(),
+            path: path.to_string(),
+            detail,
+            line,
+        }
+    }
+}
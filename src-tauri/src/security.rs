@@ -0,0 +1,175 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Raised when a filesystem operation would reach outside the folder it is allowed to touch.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SecurityError {
+    pub kind: String, // "path_outside_root", "invalid_path"
+    pub operation: String,
+    pub path: String,
+    pub allowed_root: String,
+    pub message: String,
+}
+
+impl fmt::Display for SecurityError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Security violation: {}", self.message)
+    }
+}
+
+impl std::error::Error for SecurityError {}
+
+/// Confines destructive filesystem operations to a root folder, such as a project's Packages directory.
+pub struct PathGuard;
+
+impl PathGuard {
+    /// Resolves `path` and checks that it lies strictly inside `root`.
+    /// Symlinks in parent folders are followed, a symlink at `path` itself is not, since removing it only removes the link.
+    pub fn confine(root: &Path, path: &Path, operation: &str) -> Result<PathBuf> {
+        let violation = |kind: &str, message: String| SecurityError {
+            kind: kind.to_string(),
+            operation: operation.to_string(),
+            path: path.to_string_lossy().to_string(),
+            allowed_root: root.to_string_lossy().to_string(),
+            message,
+        };
+
+        let canonical_root = fs::canonicalize(root)
+            .with_context(|| format!("Failed to resolve {}", root.display()))?;
+
+        let is_symlink = fs::symlink_metadata(path).map(|m| m.file_type().is_symlink()).unwrap_or(false);
+
+        let resolved = if is_symlink || !path.exists() {
+            let (parent, file_name) = match (path.parent(), path.file_name()) {
+                (Some(parent), Some(file_name)) => (parent, file_name),
+                _ => return Err(violation("invalid_path", format!("Refusing to {} {}", operation, path.display())).into()),
+            };
+
+            fs::canonicalize(parent)
+                .with_context(|| format!("Failed to resolve {}", parent.display()))?
+                .join(file_name)
+        } else {
+            fs::canonicalize(path)
+                .with_context(|| format!("Failed to resolve {}", path.display()))?
+        };
+
+        if resolved == canonical_root || !resolved.starts_with(&canonical_root) {
+            return Err(violation(
+                "path_outside_root",
+                format!("Refusing to {} {}, it resolves to {} which is outside of {}",
+                    operation, path.display(), resolved.display(), canonical_root.display()),
+            ).into());
+        }
+
+        Ok(resolved)
+    }
+
+    /// Removes a folder inside `root`. A symlinked folder only has its link removed, never the folder it points at.
+    pub fn remove_dir_all(root: &Path, path: &Path, operation: &str) -> Result<()> {
+        let resolved = Self::confine(root, path, operation)?;
+
+        let is_symlink = fs::symlink_metadata(&resolved).map(|m| m.file_type().is_symlink()).unwrap_or(false);
+
+        if is_symlink {
+            // Windows directory symlinks are removed like directories, elsewhere they are plain files
+            return fs::remove_dir(&resolved)
+                .or_else(|_| fs::remove_file(&resolved))
+                .with_context(|| format!("Failed to remove symlink: {}", resolved.display()));
+        }
+
+        fs::remove_dir_all(&resolved)
+            .with_context(|| format!("Failed to remove directory: {}", resolved.display()))
+    }
+
+    /// Removes a single file or an empty folder inside `root`.
+    pub fn remove_file(root: &Path, path: &Path, operation: &str) -> Result<()> {
+        let resolved = Self::confine(root, path, operation)?;
+
+        let is_dir = fs::symlink_metadata(&resolved).map(|m| m.is_dir()).unwrap_or(false);
+
+        if is_dir {
+            fs::remove_dir(&resolved)
+                .with_context(|| format!("Failed to remove folder: {}", resolved.display()))
+        } else {
+            fs::remove_file(&resolved)
+                .with_context(|| format!("Failed to remove file: {}", resolved.display()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+
+    fn temp_root() -> TempDir {
+        let root = TempDir::new("guard");
+        fs::create_dir_all(root.join("Packages").join("com.test.pkg")).unwrap();
+        root
+    }
+
+    fn violation_kind(result: Result<PathBuf>) -> String {
+        result.unwrap_err().downcast::<SecurityError>().expect("expected a security error").kind
+    }
+
+    #[test]
+    fn confine_accepts_paths_inside_root() {
+        let root = temp_root();
+        let packages_dir = root.join("Packages");
+
+        let resolved = PathGuard::confine(&packages_dir, &packages_dir.join("com.test.pkg"), "test").unwrap();
+        assert!(resolved.ends_with("Packages/com.test.pkg"));
+
+        // Paths that don't exist yet are resolved through their parent
+        assert!(PathGuard::confine(&packages_dir, &packages_dir.join("com.test.new"), "test").is_ok());
+    }
+
+    #[test]
+    fn confine_rejects_parent_components() {
+        let root = temp_root();
+        let packages_dir = root.join("Packages");
+
+        let escaping = packages_dir.join("com.test.pkg").join("..").join("..");
+        assert_eq!(violation_kind(PathGuard::confine(&packages_dir, &escaping, "test")), "path_outside_root");
+    }
+
+    #[test]
+    fn confine_rejects_root_itself() {
+        let root = temp_root();
+        let packages_dir = root.join("Packages");
+
+        assert_eq!(violation_kind(PathGuard::confine(&packages_dir, &packages_dir, "test")), "path_outside_root");
+        assert_eq!(
+            violation_kind(PathGuard::confine(&packages_dir, &packages_dir.join("com.test.pkg").join(".."), "test")),
+            "path_outside_root"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn confine_follows_symlinked_parents_but_not_the_link_itself() {
+        let root = temp_root();
+        let packages_dir = root.join("Packages");
+        let outside = root.join("outside");
+        fs::create_dir_all(&outside).unwrap();
+        fs::write(outside.join("keep.txt"), "keep").unwrap();
+
+        let link = packages_dir.join("com.test.link");
+        std::os::unix::fs::symlink(&outside, &link).unwrap();
+
+        // The link lives in Packages, removing it only removes the link
+        assert!(PathGuard::confine(&packages_dir, &link, "test").is_ok());
+
+        // Anything reached through it lives outside
+        assert_eq!(violation_kind(PathGuard::confine(&packages_dir, &link.join("keep.txt"), "test")), "path_outside_root");
+        assert!(PathGuard::remove_file(&packages_dir, &link.join("keep.txt"), "test").is_err());
+        assert!(outside.join("keep.txt").exists());
+
+        PathGuard::remove_dir_all(&packages_dir, &link, "test").unwrap();
+        assert!(!link.exists());
+        assert!(outside.join("keep.txt").exists());
+    }
+}
@@ -0,0 +1,108 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::info;
+use crate::models::PackageSnapshot;
+use crate::security::PathGuard;
+
+// Retained package copies can be large, older snapshots are dropped and their operations can no longer be undone
+const MAX_SNAPSHOTS: usize = 50;
+
+/// Project-local storage of package snapshots, one folder per operation under `.cupm/snapshots`.
+pub struct SnapshotStore;
+
+impl SnapshotStore {
+    pub fn get_snapshots_dir(project_path: &str) -> PathBuf {
+        Path::new(project_path).join(".cupm").join("snapshots")
+    }
+
+    pub fn get_snapshot_dir(project_path: &str, operation_id: &str) -> PathBuf {
+        Self::get_snapshots_dir(project_path).join(operation_id)
+    }
+
+    /// Where the retained copy of a package folder goes for snapshots of the "copy" kind.
+    pub fn get_copy_path(project_path: &str, operation_id: &str) -> PathBuf {
+        Self::get_snapshot_dir(project_path, operation_id).join("package")
+    }
+
+    pub fn load(project_path: &str, operation_id: &str) -> Result<Option<PackageSnapshot>> {
+        let snapshot_path = Self::get_snapshot_dir(project_path, operation_id).join("snapshot.json");
+
+        if !snapshot_path.exists() {
+            return Ok(None);
+        }
+
+        let content = fs::read_to_string(&snapshot_path)
+            .context("Failed to read package snapshot")?;
+
+        let snapshot = serde_json::from_str(&content)
+            .context("Failed to parse package snapshot")?;
+
+        Ok(Some(snapshot))
+    }
+
+    pub fn save(project_path: &str, snapshot: &PackageSnapshot) -> Result<()> {
+        let snapshot_dir = Self::get_snapshot_dir(project_path, &snapshot.operation_id);
+
+        fs::create_dir_all(&snapshot_dir)
+            .context("Failed to create snapshot directory")?;
+
+        let content = serde_json::to_string_pretty(snapshot)
+            .context("Failed to serialize package snapshot")?;
+
+        fs::write(snapshot_dir.join("snapshot.json"), content)
+            .context("Failed to write package snapshot")?;
+
+        Ok(())
+    }
+
+    pub fn exists(project_path: &str, operation_id: &str) -> bool {
+        Self::get_snapshot_dir(project_path, operation_id).join("snapshot.json").exists()
+    }
+
+    pub fn remove(project_path: &str, operation_id: &str) -> Result<()> {
+        let snapshots_dir = Self::get_snapshots_dir(project_path);
+        let snapshot_dir = Self::get_snapshot_dir(project_path, operation_id);
+
+        if snapshot_dir.exists() {
+            PathGuard::remove_dir_all(&snapshots_dir, &snapshot_dir, "remove package snapshot")?;
+        }
+
+        Ok(())
+    }
+
+    /// Keeps the newest snapshots and removes the rest.
+    pub fn prune(project_path: &str) -> Result<()> {
+        let snapshots_dir = Self::get_snapshots_dir(project_path);
+
+        if !snapshots_dir.exists() {
+            return Ok(());
+        }
+
+        let mut snapshots: Vec<(u64, String)> = Vec::new();
+        for entry in fs::read_dir(&snapshots_dir).context("Failed to read snapshot directory")? {
+            let operation_id = entry?.file_name().to_string_lossy().to_string();
+            // Folders without a readable snapshot.json are leftovers of a capture that failed midway
+            let created_at = Self::load(project_path, &operation_id)
+                .ok()
+                .flatten()
+                .map(|snapshot| snapshot.created_at)
+                .unwrap_or(0);
+            snapshots.push((created_at, operation_id));
+        }
+
+        if snapshots.len() <= MAX_SNAPSHOTS {
+            return Ok(());
+        }
+
+        snapshots.sort();
+        let excess = snapshots.len() - MAX_SNAPSHOTS;
+
+        for (_, operation_id) in snapshots.into_iter().take(excess) {
+            info!("Removing old package snapshot {}", operation_id);
+            Self::remove(project_path, &operation_id)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,75 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A uniquely named folder under the system temp directory that is removed again when dropped.
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    pub fn new(prefix: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("cupm-{}-{}", prefix, uuid::Uuid::new_v4()));
+        fs::create_dir_all(&path).unwrap();
+        Self { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn join(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.path.join(path)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        // Best effort, a failed cleanup shouldn't fail the test that used the folder
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// Creates a Unity project folder with an empty manifest and returns its path.
+pub fn create_project(path: &Path) -> String {
+    fs::create_dir_all(path.join("Packages")).unwrap();
+    fs::write(path.join("Packages").join("manifest.json"), "{\n  \"dependencies\": {}\n}").unwrap();
+    path.to_string_lossy().to_string()
+}
+
+/// Writes a minimal package.json into `path`.
+pub fn write_package_json(path: &Path, name: &str, version: &str) {
+    fs::create_dir_all(path).unwrap();
+    fs::write(
+        path.join("package.json"),
+        format!("{{\n  \"name\": \"{}\",\n  \"version\": \"{}\",\n  \"displayName\": \"Test Package\"\n}}\n", name, version),
+    ).unwrap();
+}
+
+/// Creates a git repository holding a package at `version`, committed and tagged `v{version}`.
+pub fn init_package_repo(path: &Path, name: &str, version: &str) -> git2::Repository {
+    let repo = git2::Repository::init(path).unwrap();
+    write_package_json(path, name, version);
+    commit_all(&repo, &format!("Release {}", version), Some(&format!("v{}", version)));
+    repo
+}
+
+/// Commits everything in the working tree, optionally tagging the commit, and returns its id.
+pub fn commit_all(repo: &git2::Repository, message: &str, tag: Option<&str>) -> String {
+    let mut index = repo.index().unwrap();
+    index.add_all(["*"].iter(), git2::IndexAddOption::DEFAULT, None).unwrap();
+    index.update_all(["*"].iter(), None).unwrap();
+    index.write().unwrap();
+
+    let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+    let signature = git2::Signature::now("CUPM Tests", "tests@example.com").unwrap();
+    let parent = repo.head().ok().and_then(|head| head.peel_to_commit().ok());
+    let parents: Vec<&git2::Commit> = parent.iter().collect();
+
+    let oid = repo.commit(Some("HEAD"), &signature, &signature, message, &tree, &parents).unwrap();
+
+    if let Some(tag) = tag {
+        repo.tag_lightweight(tag, &repo.find_object(oid, None).unwrap(), false).unwrap();
+    }
+
+    oid.to_string()
+}
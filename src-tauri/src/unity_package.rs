@@ -0,0 +1,481 @@
+use anyhow::{Context, Result};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{debug, info, warn};
+use crate::models::{
+    ImportConflict, ImportedAsset, UnityPackageExportReport, UnityPackageImport, UnityPackageImportReport,
+};
+use crate::security::PathGuard;
+
+pub struct UnityPackageArchive;
+
+// One asset inside a .unitypackage, stored in the archive as a folder named after its GUID
+#[derive(Default)]
+struct ArchiveAsset {
+    pathname: Option<String>,
+    asset: Option<Vec<u8>>,
+    meta: Option<Vec<u8>>,
+}
+
+impl UnityPackageArchive {
+    pub fn get_imports_dir(project_path: &str) -> PathBuf {
+        Path::new(project_path).join(".cupm").join("imports")
+    }
+
+    /// Imports a .unitypackage into the project's Assets folder.
+    /// Existing files are only replaced when `overwrite` is set, GUIDs already used elsewhere always block the import.
+    pub fn import(project_path: &str, archive_path: &str, overwrite: bool) -> Result<UnityPackageImportReport> {
+        let project_dir = Path::new(project_path);
+        if !project_dir.join("Assets").is_dir() {
+            return Err(anyhow::anyhow!("Project Assets folder not found"));
+        }
+
+        info!("Importing {} into {}", archive_path, project_path);
+
+        let assets = Self::read_archive(Path::new(archive_path))?;
+        let existing_guids = Self::collect_project_guids(&project_dir.join("Assets"))?;
+
+        let mut conflicts = Vec::new();
+        let mut skipped = Vec::new();
+        let mut planned = Vec::new();
+
+        for (guid, asset) in assets {
+            let pathname = match &asset.pathname {
+                Some(pathname) => pathname.clone(),
+                None => {
+                    skipped.push(format!("{} (no pathname)", guid));
+                    continue;
+                }
+            };
+
+            if !Self::is_safe_asset_path(&pathname) {
+                debug!("Skipping asset outside of Assets: {}", pathname);
+                skipped.push(pathname);
+                continue;
+            }
+
+            let target = project_dir.join(&pathname);
+            let is_folder = asset.asset.is_none();
+
+            if let Some(existing_path) = existing_guids.get(&guid) {
+                if existing_path != &pathname {
+                    conflicts.push(ImportConflict {
+                        path: pathname.clone(),
+                        guid: guid.clone(),
+                        kind: "guid_in_use".to_string(),
+                        existing_path: Some(existing_path.clone()),
+                    });
+                    continue;
+                }
+            }
+
+            if !is_folder && target.exists() {
+                conflicts.push(ImportConflict {
+                    path: pathname.clone(),
+                    guid: guid.clone(),
+                    kind: "file_exists".to_string(),
+                    existing_path: None,
+                });
+            }
+
+            planned.push((guid, pathname, asset));
+        }
+
+        let blocked = conflicts.iter().any(|c| c.kind == "guid_in_use" || !overwrite);
+        if blocked {
+            warn!("Import of {} blocked by {} conflict(s)", archive_path, conflicts.len());
+            return Ok(UnityPackageImportReport {
+                imported: false,
+                import: None,
+                conflicts,
+                skipped,
+            });
+        }
+
+        // Create folders before files so parents exist, shallow paths first
+        planned.sort_by(|a, b| a.1.cmp(&b.1));
+
+        let mut files = Vec::new();
+        for (guid, pathname, asset) in planned {
+            let target = project_dir.join(&pathname);
+            let is_folder = asset.asset.is_none();
+
+            if is_folder {
+                fs::create_dir_all(&target)
+                    .with_context(|| format!("Failed to create folder: {}", target.display()))?;
+            } else {
+                if let Some(parent) = target.parent() {
+                    fs::create_dir_all(parent)
+                        .with_context(|| format!("Failed to create folder: {}", parent.display()))?;
+                }
+                fs::write(&target, asset.asset.unwrap_or_default())
+                    .with_context(|| format!("Failed to write asset: {}", target.display()))?;
+            }
+
+            if let Some(meta) = asset.meta {
+                let meta_path = PathBuf::from(format!("{}.meta", target.display()));
+                fs::write(&meta_path, meta)
+                    .with_context(|| format!("Failed to write meta file: {}", meta_path.display()))?;
+            }
+
+            files.push(ImportedAsset {
+                path: pathname,
+                guid,
+                is_folder,
+            });
+        }
+
+        let archive_name = Path::new(archive_path)
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_else(|| archive_path.to_string());
+
+        let import = UnityPackageImport {
+            id: uuid::Uuid::new_v4().to_string(),
+            archive_name,
+            imported_at: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+            files,
+        };
+
+        Self::save_import(project_path, &import)?;
+
+        info!("Successfully imported {} asset(s) from {}", import.files.len(), archive_path);
+
+        Ok(UnityPackageImportReport {
+            imported: true,
+            import: Some(import),
+            conflicts,
+            skipped,
+        })
+    }
+
+    /// Writes a package folder out as a .unitypackage that places it under `assets_path` in the target project.
+    /// GUIDs are taken from the package's own .meta files so references to its assets keep working.
+    pub fn export(package_path: &Path, output_path: &Path, assets_path: &str) -> Result<UnityPackageExportReport> {
+        let assets_path = assets_path.trim().trim_matches('/').replace('\\', "/");
+        let assets_path = if assets_path == "Assets" || assets_path.starts_with("Assets/") {
+            assets_path
+        } else {
+            format!("Assets/{}", assets_path)
+        };
+
+        if !Self::is_safe_asset_path(&assets_path) {
+            return Err(anyhow::anyhow!("Invalid export path '{}', it must be a folder inside Assets", assets_path));
+        }
+
+        info!("Exporting {} to {} as {}", package_path.display(), output_path.display(), assets_path);
+
+        let mut entries = Vec::new();
+        let mut skipped = Vec::new();
+        Self::collect_export_entries(package_path, package_path, &mut entries, &mut skipped)?;
+
+        let file = fs::File::create(output_path)
+            .with_context(|| format!("Failed to create {}", output_path.display()))?;
+
+        let mut builder = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+        let mut seen_guids = HashSet::new();
+        let mut exported = 0;
+
+        for (relative, path, is_folder) in entries {
+            let meta_path = PathBuf::from(format!("{}.meta", path.display()));
+            let guid = match Self::read_meta_guid(&meta_path) {
+                Some(guid) => guid,
+                None => {
+                    skipped.push(format!("{} (missing .meta)", relative));
+                    continue;
+                }
+            };
+
+            if !seen_guids.insert(guid.clone()) {
+                skipped.push(format!("{} (duplicate GUID {})", relative, guid));
+                continue;
+            }
+
+            let pathname = format!("{}/{}", assets_path, relative);
+            Self::append_archive_file(&mut builder, &format!("{}/pathname", guid), pathname.as_bytes())?;
+
+            let meta = fs::read(&meta_path)
+                .with_context(|| format!("Failed to read {}", meta_path.display()))?;
+            Self::append_archive_file(&mut builder, &format!("{}/asset.meta", guid), &meta)?;
+
+            if !is_folder {
+                let asset = fs::read(&path)
+                    .with_context(|| format!("Failed to read {}", path.display()))?;
+                Self::append_archive_file(&mut builder, &format!("{}/asset", guid), &asset)?;
+            }
+
+            exported += 1;
+        }
+
+        builder.into_inner()
+            .and_then(|encoder| encoder.finish())
+            .with_context(|| format!("Failed to finish writing {}", output_path.display()))?;
+
+        info!("Exported {} asset(s), skipped {}", exported, skipped.len());
+
+        Ok(UnityPackageExportReport {
+            output_path: output_path.to_string_lossy().to_string(),
+            assets_path,
+            exported,
+            skipped,
+        })
+    }
+
+    /// Collects files and folders Unity would import, following its rules for hidden assets:
+    /// names starting with "." or ending with "~", "cvs" folders and ".tmp" files are ignored, as are symlinks.
+    fn collect_export_entries(
+        root: &Path,
+        dir: &Path,
+        entries: &mut Vec<(String, PathBuf, bool)>,
+        skipped: &mut Vec<String>,
+    ) -> Result<()> {
+        let mut dir_entries: Vec<_> = fs::read_dir(dir)
+            .with_context(|| format!("Failed to read {}", dir.display()))?
+            .collect::<std::io::Result<_>>()?;
+        dir_entries.sort_by_key(|e| e.file_name());
+
+        for entry in dir_entries {
+            let path = entry.path();
+            let name = entry.file_name().to_string_lossy().to_string();
+            let relative = path.strip_prefix(root).unwrap_or(&path).to_string_lossy().replace('\\', "/");
+
+            let is_hidden = name.starts_with('.')
+                || name.ends_with('~')
+                || name.eq_ignore_ascii_case("cvs")
+                || name.to_lowercase().ends_with(".tmp");
+
+            if is_hidden || name.ends_with(".meta") {
+                continue;
+            }
+
+            let file_type = fs::symlink_metadata(&path)
+                .with_context(|| format!("Failed to read {}", path.display()))?
+                .file_type();
+
+            if file_type.is_symlink() {
+                skipped.push(format!("{} (symlink)", relative));
+                continue;
+            }
+
+            if file_type.is_dir() {
+                entries.push((relative, path.clone(), true));
+                Self::collect_export_entries(root, &path, entries, skipped)?;
+            } else {
+                entries.push((relative, path, false));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn append_archive_file<W: std::io::Write>(builder: &mut tar::Builder<W>, path: &str, data: &[u8]) -> Result<()> {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+
+        builder.append_data(&mut header, path, data)
+            .with_context(|| format!("Failed to write {} to archive", path))?;
+
+        Ok(())
+    }
+
+    pub fn list_imports(project_path: &str) -> Result<Vec<UnityPackageImport>> {
+        let imports_dir = Self::get_imports_dir(project_path);
+        if !imports_dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut imports = Vec::new();
+        for entry in fs::read_dir(&imports_dir).context("Failed to read imports directory")? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+
+            let content = fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read import record: {}", path.display()))?;
+
+            let import: UnityPackageImport = serde_json::from_str(&content)
+                .with_context(|| format!("Failed to parse import record: {}", path.display()))?;
+
+            imports.push(import);
+        }
+
+        imports.sort_by_key(|i| i.imported_at);
+        Ok(imports)
+    }
+
+    /// Removes everything a previous import wrote. Folders are only removed once they are empty.
+    pub fn remove_import(project_path: &str, import_id: &str) -> Result<()> {
+        uuid::Uuid::parse_str(import_id)
+            .with_context(|| format!("Invalid import id: {}", import_id))?;
+
+        let record_path = Self::get_imports_dir(project_path).join(format!("{}.json", import_id));
+        if !record_path.exists() {
+            return Err(anyhow::anyhow!("Import {} not found", import_id));
+        }
+
+        let content = fs::read_to_string(&record_path)
+            .context("Failed to read import record")?;
+
+        let import: UnityPackageImport = serde_json::from_str(&content)
+            .context("Failed to parse import record")?;
+
+        let project_dir = Path::new(project_path);
+        let assets_dir = project_dir.join("Assets");
+        let mut folders = Vec::new();
+
+        for asset in &import.files {
+            if !Self::is_safe_asset_path(&asset.path) {
+                continue;
+            }
+
+            let target = project_dir.join(&asset.path);
+            let meta_path = PathBuf::from(format!("{}.meta", target.display()));
+
+            if asset.is_folder {
+                folders.push((target, meta_path));
+                continue;
+            }
+
+            for path in [&target, &meta_path] {
+                if path.is_file() {
+                    PathGuard::remove_file(&assets_dir, path, "remove imported asset")?;
+                }
+            }
+        }
+
+        // Deepest folders first so parents are empty by the time they are checked
+        folders.sort_by_key(|(folder, _)| std::cmp::Reverse(folder.components().count()));
+        for (folder, meta_path) in folders {
+            let is_empty = fs::read_dir(&folder).map(|mut d| d.next().is_none()).unwrap_or(false);
+            if is_empty {
+                PathGuard::remove_file(&assets_dir, &folder, "remove imported folder")?;
+                if meta_path.is_file() {
+                    PathGuard::remove_file(&assets_dir, &meta_path, "remove imported asset")?;
+                }
+            } else {
+                debug!("Keeping non-empty folder: {}", folder.display());
+            }
+        }
+
+        fs::remove_file(&record_path)
+            .context("Failed to remove import record")?;
+
+        info!("Removed import {} ({})", import.id, import.archive_name);
+        Ok(())
+    }
+
+    fn save_import(project_path: &str, import: &UnityPackageImport) -> Result<()> {
+        let imports_dir = Self::get_imports_dir(project_path);
+        if !imports_dir.exists() {
+            fs::create_dir_all(&imports_dir)
+                .context("Failed to create imports directory")?;
+        }
+
+        let content = serde_json::to_string_pretty(import)
+            .context("Failed to serialize import record")?;
+
+        fs::write(imports_dir.join(format!("{}.json", import.id)), content)
+            .context("Failed to write import record")?;
+
+        Ok(())
+    }
+
+    fn read_archive(archive_path: &Path) -> Result<BTreeMap<String, ArchiveAsset>> {
+        let file = fs::File::open(archive_path)
+            .with_context(|| format!("Failed to open {}", archive_path.display()))?;
+
+        let mut archive = tar::Archive::new(GzDecoder::new(file));
+        let mut assets: BTreeMap<String, ArchiveAsset> = BTreeMap::new();
+
+        for entry in archive.entries().context("Failed to read .unitypackage archive")? {
+            let mut entry = entry.context("Failed to read .unitypackage entry")?;
+            let entry_path = entry.path().context("Invalid path in .unitypackage")?.into_owned();
+
+            // Entries look like "<guid>/pathname", sometimes prefixed with "./"
+            let parts: Vec<String> = entry_path.components()
+                .filter_map(|c| match c {
+                    Component::Normal(part) => Some(part.to_string_lossy().to_string()),
+                    _ => None,
+                })
+                .collect();
+
+            if parts.len() != 2 {
+                continue;
+            }
+
+            let mut content = Vec::new();
+            entry.read_to_end(&mut content)
+                .with_context(|| format!("Failed to read {}", entry_path.display()))?;
+
+            let asset = assets.entry(parts[0].clone()).or_default();
+            match parts[1].as_str() {
+                "pathname" => {
+                    let pathname = String::from_utf8_lossy(&content);
+                    asset.pathname = pathname.lines().next().map(|l| l.trim().replace('\\', "/"));
+                }
+                "asset" => asset.asset = Some(content),
+                "asset.meta" => asset.meta = Some(content),
+                _ => {}
+            }
+        }
+
+        Ok(assets)
+    }
+
+    /// Maps every GUID in the project's meta files to the asset path it belongs to.
+    fn collect_project_guids(assets_dir: &Path) -> Result<HashMap<String, String>> {
+        let mut guids = HashMap::new();
+        let project_dir = assets_dir.parent().unwrap_or(assets_dir);
+        Self::collect_guids_in(assets_dir, project_dir, &mut guids)?;
+        Ok(guids)
+    }
+
+    fn collect_guids_in(dir: &Path, project_dir: &Path, guids: &mut HashMap<String, String>) -> Result<()> {
+        for entry in fs::read_dir(dir).with_context(|| format!("Failed to read {}", dir.display()))? {
+            let entry = entry?;
+            let path = entry.path();
+
+            if entry.file_type()?.is_dir() {
+                Self::collect_guids_in(&path, project_dir, guids)?;
+                continue;
+            }
+
+            if path.extension().and_then(|e| e.to_str()) != Some("meta") {
+                continue;
+            }
+
+            if let Some(guid) = Self::read_meta_guid(&path) {
+                let asset_path = path.with_extension("");
+                let relative = asset_path.strip_prefix(project_dir).unwrap_or(&asset_path);
+                guids.insert(guid, relative.to_string_lossy().replace('\\', "/"));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn read_meta_guid(meta_path: &Path) -> Option<String> {
+        let content = fs::read_to_string(meta_path).ok()?;
+        content.lines()
+            .find_map(|line| line.trim().strip_prefix("guid:"))
+            .map(|guid| guid.trim().to_string())
+    }
+
+    /// Asset paths must stay inside Assets, never climb out of it.
+    fn is_safe_asset_path(pathname: &str) -> bool {
+        let components: Vec<Component> = Path::new(pathname).components().collect();
+
+        components.len() > 1
+            && components[0] == Component::Normal("Assets".as_ref())
+            && components.iter().all(|c| matches!(c, Component::Normal(_)))
+    }
+}
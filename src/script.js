@@ -309,6 +309,13 @@ class PackageManager {
         this.showLoading();
 
         try {
+            // Packages cloned before CUPM kept provenance are matched against the registries once per project selection
+            try {
+                await window.invoke('adopt_unmanaged_packages', { projectPath });
+            } catch (error) {
+                console.warn('Failed to adopt existing packages:', error);
+            }
+
             const projectInfo = await window.invoke('get_project_info', { projectPath });
             this.currentProject = projectInfo;
 
@@ -538,7 +545,8 @@ class PackageManager {
                     const registryData = await window.invoke('get_packages_from_registry', {
                         registryUrl: registry.url
                     });
-                    availablePackages.push(...registryData.packages);
+                    const trusted = await this.reviewRegistryChanges(registry, registryData.trust_changes);
+                    availablePackages.push(...registryData.packages.filter(pkg => trusted(pkg.name)));
                 } catch (error) {
                     console.warn(`Failed to fetch packages from registry ${registry.name}:`, error);
                 }
@@ -556,6 +564,28 @@ class PackageManager {
         }
     }
 
+    // Asks the user to confirm ownership changes since the registry was added.
+    // Returns a filter that hides the affected packages if the changes were declined.
+    async reviewRegistryChanges(registry, changes) {
+        if (!changes || changes.length === 0) return () => true;
+
+        const details = changes
+            .map(c => `${c.package_name || 'All packages'}: ${c.field} changed from ${c.previous || 'none'} to ${c.current || 'none'}`)
+            .join('\n');
+
+        const accepted = await confirm(`Registry ${registry.name} changed since it was added:\n\n${details}\n\n` +
+            'This can mean the registry changed owners. Trust the new values?');
+
+        if (accepted === true) {
+            await window.invoke('confirm_registry_changes', { registryId: registry.id });
+            return () => true;
+        }
+
+        if (changes.some(c => !c.package_name)) return () => false;
+        const affected = new Set(changes.map(c => c.package_name));
+        return name => !affected.has(name);
+    }
+
     combinePackages(installed, available) {
         // Filter out Unity native packages (those starting with 'com.unity.')
         const filteredInstalled = installed.filter(pkg => !pkg.name.startsWith('com.unity.'));
@@ -590,7 +620,8 @@ class PackageManager {
                 git_branch: pkg.git_branch,
                 dependencies: pkg.dependencies,
                 unity_version: pkg.unity_version,
-                is_prerelease: pkg.is_prerelease
+                is_prerelease: pkg.is_prerelease,
+                content_hash: pkg.content_hash
             });
         }
 
@@ -973,20 +1004,23 @@ class PackageManager {
             git_branch: versionData.git_branch,
             dependencies: versionData.dependencies,
             unity_version: versionData.unity_version,
-            is_prerelease: versionData.is_prerelease
+            is_prerelease: versionData.is_prerelease,
+            registry_id: pkg.registry_id,
+            content_hash: versionData.content_hash
         };
 
         this.showLoading();
 
         try {
-            await window.invoke('install_package', {
+            const result = await this.invokeWithSafetyReview('install_package', {
                 projectPath: this.currentProject.path,
                 package: packageToInstall
             });
+            this.reportInstallResult(result);
             await this.refreshPackages();
         } catch (error) {
             console.error('Failed to perform package action:', error);
-            alert('Failed to perform package action. Please try again.');
+            alert(`Failed to perform package action.\n\n${this.describeError(error)}`);
         } finally {
             this.hideLoading();
         }
@@ -1034,16 +1068,17 @@ class PackageManager {
 
             this.showLoading();
 
-            await window.invoke('install_package', {
+            const result = await this.invokeWithSafetyReview('install_package', {
                 projectPath: this.currentProject.path,
                 package: pkg
             });
+            this.reportInstallResult(result);
             await this.refreshPackages();
 
             console.log('Package installed successfully');
         } catch (error) {
             console.error('Failed to install package:', error);
-            alert('Failed to install package. Please try again.');
+            alert(`Failed to install package.\n\n${this.describeError(error)}`);
         } finally {
             this.hideLoading();
         }
@@ -1056,19 +1091,107 @@ class PackageManager {
         this.showLoading();
 
         try {
-            await window.invoke('update_package', {
+            const result = await this.invokeWithSafetyReview('update_package', {
                 projectPath: this.currentProject.path,
                 package: pkg
             });
+            this.reportInstallResult(result);
             await this.refreshPackages();
         } catch (error) {
             console.error('Failed to update package:', error);
-            alert('Failed to update package. Please try again.');
+            alert(`Failed to update package.\n\n${this.describeError(error)}`);
         } finally {
             this.hideLoading();
         }
     }
 
+    // Installs stopped by the pre-install safety scan come back as JSON with the scan report.
+    // The user reviews the findings, and once acknowledged the install is retried.
+    async invokeWithSafetyReview(command, args) {
+        try {
+            return await window.invoke(command, args);
+        } catch (error) {
+            if (!error || !error.code) throw error;
+
+            switch (error.code) {
+                case 'advisory_blocked': {
+                    const advisories = error.fields.advisories
+                        .map(a => `[${a.severity}] ${a.id} - ${a.message}`)
+                        .join('\n');
+                    alert(`${error.message}\n\n${advisories}`);
+                    return null;
+                }
+                case 'registry_trust_changed':
+                    alert(`${error.message}\n\nRefresh the package list to review the registry changes.`);
+                    return null;
+                case 'content_hash_mismatch':
+                case 'git_url_not_allowed':
+                    alert(error.message);
+                    return null;
+                case 'safety_blocked':
+                case 'safety_acknowledgement_required':
+                    break;
+                default:
+                    throw error;
+            }
+
+            const report = error.fields.report;
+            const findings = report.findings
+                .map(f => `[${f.category}] ${f.path}${f.line ? `:${f.line}` : ''} - ${f.detail}`)
+                .join('\n');
+
+            if (error.code === 'safety_blocked') {
+                alert(`${error.message}\n\n${findings}`);
+                return null;
+            }
+
+            const acknowledged = await confirm(`${error.message}:\n\n${findings}\n\nInstall anyway?`);
+            if (acknowledged !== true) return null;
+
+            await window.invoke('acknowledge_safety_report', {
+                projectPath: args.projectPath,
+                report
+            });
+            return await window.invoke(command, args);
+        }
+    }
+
+    // Commands fail with { code, category, message, context, fields }, codes with a known fix get a hint
+    describeError(error) {
+        if (!error || !error.code) return String(error);
+
+        const hints = {
+            manifest_not_found: 'Make sure the selected folder is a Unity project with a Packages/manifest.json.',
+            git_ref_not_found: 'This version may have been removed from the repository, try another version.',
+            git_clone_failed: 'Check your internet connection and that the repository still exists.',
+            registry_unreachable: 'Check your internet connection and the registry URL.',
+            network_error: 'Check your internet connection and try again.',
+            local_changes: 'Save the changes as a patch or discard them, then try again.',
+            folder_occupied: 'Move or delete the folder in Packages, then try again.'
+        };
+
+        const hint = hints[error.code];
+        return hint ? `${error.message}\n\n${hint}` : error.message;
+    }
+
+    reportInstallResult(result) {
+        if (result && result.advisories && result.advisories.length > 0) {
+            const advisories = result.advisories
+                .map(a => `[${a.severity}] ${a.id} - ${a.message}`)
+                .join('\n');
+            alert(`${result.package_name} ${result.version} has known issues:\n\n${advisories}`);
+        }
+
+        // Local patches that no longer apply are left for the user to resolve
+        if (result && result.patch && !result.patch.applied && result.patch.conflicts.length > 0) {
+            alert(`Local patch for ${result.package_name} no longer applies cleanly and was not applied.\n\n` +
+                `Conflicting files:\n${result.patch.conflicts.join('\n')}\n\nPatch file: ${result.patch.patch_path}`);
+        } else if (result && result.patch && !result.patch.applied) {
+            alert(`Local patch for ${result.package_name} was not applied, the package was not installed from git.\n\n` +
+                `Patch file: ${result.patch.patch_path}`);
+        }
+    }
+
     async removePackage(packageName) {
         console.log('removePackage called for:', packageName);
 
@@ -1097,7 +1220,7 @@ class PackageManager {
                     console.log('Package removed successfully');
                 } catch (error) {
                     console.error('Failed to remove package:', error);
-                    alert('Failed to remove package. Please try again.');
+                    alert(`Failed to remove package.\n\n${this.describeError(error)}`);
                 } finally {
                     this.hideLoading();
                 }
@@ -1122,7 +1245,7 @@ class PackageManager {
                     await this.refreshPackages();
                 } catch (error) {
                     console.error('Failed to remove package:', error);
-                    alert('Failed to remove package. Please try again.');
+                    alert(`Failed to remove package.\n\n${this.describeError(error)}`);
                 } finally {
                     this.hideLoading();
                 }